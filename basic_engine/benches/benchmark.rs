@@ -1,6 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 
-use basic_engine::{AlphaBeta, Board, Color, Engine, Game, SearchParameters};
+use basic_engine::{AlphaBeta, Board, Color, Engine, SearchParameters};
 
 const TEST_POSITIONS: [&str; 4] = [
     "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", // initial
@@ -58,7 +58,7 @@ macro_rules! bench_engine_fen {
 
 bench_engine_fen!(alpha_beta_5, engine, {
     engine.clear_cache();
-    engine.iterative_deepening_search(SearchParameters::new_with_depth(5))
+    engine.iterative_deepening_search(SearchParameters::builder().depth(5).build())
 });
 
 criterion_group!(board_benches, square_attacked, generate_moves,);
@@ -1,5 +1,4 @@
-use basic_engine::{AlphaBeta, Board, Engine, Game, SearchParameters};
-use iai;
+use basic_engine::{AlphaBeta, Board, Engine, SearchParameters};
 
 // TODO share these with criterion benches
 const TEST_POSITIONS: [&str; 4] = [
@@ -12,9 +11,10 @@ const TEST_POSITIONS: [&str; 4] = [
 fn iai_alpha_beta() {
     for fen in TEST_POSITIONS {
         let b = iai::black_box(Board::from_fen(fen).unwrap());
-        let mut e = <AlphaBeta as Engine>::new(b.clone());
+        let mut e = <AlphaBeta as Engine>::new(b);
         e.clear_cache();
-        e.iterative_deepening_search(SearchParameters::new_with_depth(5));
+        e.iterative_deepening_search(SearchParameters::builder().depth(5).build())
+            .unwrap();
     }
 }
 
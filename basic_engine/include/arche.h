@@ -0,0 +1,42 @@
+/* C interface to the arche chess engine, built as the basic_engine cdylib */
+#ifndef ARCHE_H
+#define ARCHE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct ArcheEngine ArcheEngine;
+
+/* Functions return -1 or NULL if the engine panicked, it should then only be freed */
+
+/* Create an engine at the starting position with a hash table of hash_size_mb megabytes, release
+ * it with arche_engine_free, returns NULL if it couldn't be created */
+ArcheEngine *arche_engine_new(size_t hash_size_mb);
+void arche_engine_free(ArcheEngine *engine);
+
+/* Replace the hash table with an empty one of megabytes, returns 0 on success and -1 on failure */
+int arche_engine_set_hash_size(ArcheEngine *engine, size_t megabytes);
+
+/* Set the position from a FEN (NULL for the starting position) followed by space separated UCI
+ * moves (or NULL), returns 0 on success and -1 if the FEN or a move is invalid */
+int arche_engine_set_position(ArcheEngine *engine, const char *fen, const char *moves);
+
+/* Search for move_time_ms milliseconds, returns the best move in UCI notation or NULL if there
+ * is no legal move */
+char *arche_engine_search(ArcheEngine *engine, uint64_t move_time_ms);
+
+/* The principal variation of the last search as space separated UCI moves, or NULL on failure */
+char *arche_engine_pv(const ArcheEngine *engine);
+
+/* Release a string returned by arche_engine_search or arche_engine_pv */
+void arche_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,51 @@
+//! The squares each kind of piece attacks, looked up from precomputed tables
+//!
+//! Squares are numbered from a1 = 0 to h8 = 63, and a bitboard has bit n set for square n.
+
+use crate::board::ATTACK_MASKS;
+use crate::misc::Color;
+
+pub use crate::magic::{bishop_attacks, queen_attacks, rook_attacks};
+
+/// The squares a knight on the square attacks
+#[inline(always)]
+pub fn knight_attacks(square: u8) -> u64 {
+    ATTACK_MASKS.knights[square as usize]
+}
+
+/// The squares a king on the square attacks
+#[inline(always)]
+pub fn king_attacks(square: u8) -> u64 {
+    ATTACK_MASKS.kings[square as usize]
+}
+
+/// The squares a pawn of the colour on the square attacks, diagonally towards the opponent
+#[inline(always)]
+pub fn pawn_attacks(square: u8, color: Color) -> u64 {
+    // The masks are indexed by the attacked square, black_pawns[square] is where black pawns
+    // attack the square from, which are the squares a white pawn on it attacks
+    match color {
+        Color::White => ATTACK_MASKS.black_pawns[square as usize],
+        Color::Black => ATTACK_MASKS.white_pawns[square as usize],
+    }
+}
+
+#[cfg(test)]
+mod test_attacks {
+    use super::{king_attacks, knight_attacks, pawn_attacks};
+    use crate::misc::Color;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_leaper_attacks() {
+        // Knight on a1 attacks b3 and c2
+        assert_eq!(knight_attacks(0), (1 << 17) | (1 << 10));
+        // King on h8 attacks g8, g7 and h7
+        assert_eq!(king_attacks(63), (1 << 62) | (1 << 54) | (1 << 55));
+        // Pawns on e4 attack d5 and f5 for white, d3 and f3 for black
+        assert_eq!(pawn_attacks(28, Color::White), (1 << 35) | (1 << 37));
+        assert_eq!(pawn_attacks(28, Color::Black), (1 << 19) | (1 << 21));
+        // a-file pawns only attack the b-file
+        assert_eq!(pawn_attacks(8, Color::White), 1 << 17);
+    }
+}
@@ -1,6 +1,7 @@
-use crate::misc::{coordinate_to_index, File};
+use crate::misc::{coordinate_to_index, File, Rank};
 use smallvec::SmallVec;
 
+#[allow(dead_code)]
 pub trait BitBoard {
     fn set_bit(&mut self, index: u8);
     fn clear_bit(&mut self, index: u8);
@@ -8,15 +9,16 @@ pub trait BitBoard {
     fn debug_print(&self);
     fn is_bit_set(&self, index: u8) -> bool;
     fn get_set_bits(&self) -> SmallVec<[u8; 32]>;
+    fn iter_bits(&self) -> BitIter;
     fn pop_bit(&mut self) -> Option<u8>;
 
     // TODO Remove these?
     #[inline(always)]
-    fn set_bit_from_coordinate(&mut self, rank: u8, file: File) {
+    fn set_bit_from_coordinate(&mut self, rank: Rank, file: File) {
         self.set_bit(coordinate_to_index(rank, file));
     }
     #[inline(always)]
-    fn clear_bit_from_coordinate(&mut self, rank: u8, file: File) {
+    fn clear_bit_from_coordinate(&mut self, rank: Rank, file: File) {
         self.clear_bit(coordinate_to_index(rank, file));
     }
 }
@@ -53,6 +55,12 @@ impl BitBoard for u64 {
         v
     }
 
+    #[inline(always)]
+    fn iter_bits(&self) -> BitIter {
+        BitIter(*self)
+    }
+
+    #[inline(always)]
     fn pop_bit(&mut self) -> Option<u8> {
         if *self == 0 {
             return None;
@@ -65,7 +73,7 @@ impl BitBoard for u64 {
     fn debug_print(&self) {
         println!("    a b c d e f g h");
         println!("  -----------------");
-        for rank in 1..9 {
+        for rank in Rank::VARIANTS {
             print!("{} |", rank);
             for file in File::VARIANTS {
                 if (self & (1u64 << coordinate_to_index(rank, file))) > 0 {
@@ -78,3 +86,26 @@ impl BitBoard for u64 {
         }
     }
 }
+
+/// The indexes of the set bits of a bitboard, lowest first
+///
+/// Unlike get_set_bits nothing is collected, each bit is popped as it's needed.
+#[derive(Debug, Copy, Clone)]
+pub struct BitIter(u64);
+
+impl Iterator for BitIter {
+    type Item = u8;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<u8> {
+        self.0.pop_bit()
+    }
+
+    #[inline(always)]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = self.0.count_ones() as usize;
+        (count, Some(count))
+    }
+}
+
+impl ExactSizeIterator for BitIter {}
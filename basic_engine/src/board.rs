@@ -1,14 +1,20 @@
 use super::bitboard::BitBoard;
 use super::misc::{
-    coordinate_to_index, coordinate_to_large_index, index_to_coordinate, CastlePermissions, Color,
-    Coordinate, File, Piece, PromotePiece,
+    chebyshev_distance, coordinate_to_index, coordinate_to_large_index, index_to_coordinate,
+    rank_of, CastlePermissions, Color, Coordinate, FenError, FenField, File, Piece, PromotePiece,
+    Rank,
 };
-use super::play::Play;
-use crate::magic::Magic;
-use crate::pvt::PieceValueTables;
+use super::play::{MoveBuffer, MoveError, MoveList, NoMoveToUndo, Play};
+use crate::attacks::{
+    bishop_attacks, king_attacks, knight_attacks, pawn_attacks, queen_attacks, rook_attacks,
+};
+use crate::book::polyglot_key;
+use crate::eval_params::{default_params, EvalParams};
+use crate::syzygy::{self, Dtz, Wdl};
 use crate::zorbrist::Zorbrist;
 use crate::Game;
 use std::fmt;
+use std::sync::Arc;
 
 /// Play State is used to store the history of moves (plays)
 ///
@@ -24,69 +30,60 @@ struct PlayState {
     position_key: u64,
 }
 
-// TODO use zorb for castling
+// A subtree count stored by perft_hashed
+#[derive(Debug, Copy, Clone)]
+struct PerftEntry {
+    key: u64,
+    depth: u8,
+    nodes: u64,
+}
 
-const MAX_GAME_SIZE: usize = 375;
-const EMPTY_HISTORY: [Option<PlayState>; MAX_GAME_SIZE] = [None; MAX_GAME_SIZE];
+/// Game phase with all the pieces on the board, the phase falls to 0 as pieces are captured
+pub const MAX_PHASE: u32 = 256;
+// Total phase weight of the pieces at the start of the game
+const STARTING_PHASE_WEIGHT: u32 = 24;
+// Drawish endings scale the evaluation by a number of eighths
+const SCALE_NORMAL: i64 = 8;
+const SCALE_MINOR_ONLY: i64 = 1;
+const SCALE_SMALL_ADVANTAGE: i64 = 2;
+const SCALE_OPPOSITE_BISHOPS: i64 = 4;
+const SCALE_OPPOSITE_BISHOPS_WITH_ROOKS: i64 = 6;
 
 const A1: u8 = 0;
-const B1: u8 = 1;
 const C1: u8 = 2;
 const D1: u8 = 3;
-const E1: u8 = 4;
 const F1: u8 = 5;
 const G1: u8 = 6;
 const H1: u8 = 7;
 
 const A8: u8 = 56;
-const B8: u8 = 57;
 const C8: u8 = 58;
 const D8: u8 = 59;
-const E8: u8 = 60;
 const F8: u8 = 61;
 const G8: u8 = 62;
 const H8: u8 = 63;
 
+// Key of an empty board with white to move
+const EMPTY_KEY: u64 = 2340980257093; // TODO start with random number?
+
+const LIGHT_SQUARES: u64 = 0x55AA_55AA_55AA_55AA;
+const A_FILE: u64 = 0x0101_0101_0101_0101;
+
 lazy_static! {
-    static ref ATTACK_MASKS: AttackMasks = AttackMasks::new();
+    pub(crate) static ref ATTACK_MASKS: AttackMasks = AttackMasks::new();
+    static ref PAWN_MASKS: PawnMasks = PawnMasks::new();
+    static ref LINE_MASKS: LineMasks = LineMasks::new();
     pub static ref BASE_CONVERSIONS: BaseConversions = BaseConversions::new();
-    static ref CASTLE_PERMISSION_SQUARES: [u8; 6] = [
-        coordinate_to_index(1, File::A) as u8,
-        coordinate_to_index(1, File::E) as u8,
-        coordinate_to_index(1, File::H) as u8,
-        coordinate_to_index(8, File::A) as u8,
-        coordinate_to_index(8, File::E) as u8,
-        coordinate_to_index(8, File::H) as u8,
-    ];
     static ref ZORB: Zorbrist = Zorbrist::new();
-    static ref PVT: PieceValueTables = PieceValueTables::new();
-    static ref MAGIC: Magic = Magic::new();
-    static ref B1_C1_D1: u64 = {
-        let mut mask = 0u64;
-        mask.set_bit(B1);
-        mask.set_bit(C1);
-        mask.set_bit(D1);
-        mask
-    };
-    static ref F1_G1: u64 = {
-        let mut mask = 0u64;
-        mask.set_bit(F1);
-        mask.set_bit(G1);
-        mask
-    };
-    static ref B8_C8_D8: u64 = {
-        let mut mask = 0u64;
-        mask.set_bit(B8);
-        mask.set_bit(C8);
-        mask.set_bit(D8);
-        mask
-    };
-    static ref F8_G8: u64 = {
-        let mut mask = 0u64;
-        mask.set_bit(F8);
-        mask.set_bit(G8);
-        mask
-    };
+}
+
+// Build the tables now rather than when they are first used, see crate::init
+pub(crate) fn init_tables() {
+    lazy_static::initialize(&ATTACK_MASKS);
+    lazy_static::initialize(&PAWN_MASKS);
+    lazy_static::initialize(&LINE_MASKS);
+    lazy_static::initialize(&BASE_CONVERSIONS);
+    lazy_static::initialize(&ZORB);
 }
 
 pub struct BaseConversions {
@@ -101,7 +98,7 @@ impl BaseConversions {
             base_100_to_64: [Self::OFF_BOARD; 100],
             base_64_to_100: [0u8; 64],
         };
-        for rank in 1..=8 {
+        for rank in Rank::VARIANTS {
             for file in File::VARIANTS {
                 let index = coordinate_to_large_index(rank, file);
                 let index_64 = coordinate_to_index(rank, file) as usize;
@@ -131,13 +128,13 @@ impl fmt::Display for BaseConversions {
     }
 }
 
-struct AttackMasks {
-    black_pawns: [u64; 64],
-    white_pawns: [u64; 64],
-    knights: [u64; 64],
+pub(crate) struct AttackMasks {
+    pub(crate) black_pawns: [u64; 64],
+    pub(crate) white_pawns: [u64; 64],
+    pub(crate) knights: [u64; 64],
     straight: [u64; 64], // rooks and queens
     diagonal: [u64; 64], // bishops and queens
-    kings: [u64; 64],
+    pub(crate) kings: [u64; 64],
 }
 
 impl AttackMasks {
@@ -233,7 +230,261 @@ impl AttackMasks {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone, Eq, Hash)]
+struct PawnMasks {
+    // Squares in front of a pawn on its own and the adjacent files, a pawn is passed when there
+    // are no enemy pawns on them
+    white_passed: [u64; 64],
+    black_passed: [u64; 64],
+    // Squares in front of a pawn on its own file
+    white_front: [u64; 64],
+    black_front: [u64; 64],
+}
+
+impl PawnMasks {
+    fn new() -> Self {
+        let mut pm = PawnMasks {
+            white_passed: [0; 64],
+            black_passed: [0; 64],
+            white_front: [0; 64],
+            black_front: [0; 64],
+        };
+        for i in 0u8..64 {
+            let (rank, file) = (i / 8, i % 8);
+            for f in file.saturating_sub(1)..=(file + 1).min(7) {
+                for r in (rank + 1)..8 {
+                    pm.white_passed[i as usize].set_bit(r * 8 + f);
+                }
+                for r in 0..rank {
+                    pm.black_passed[i as usize].set_bit(r * 8 + f);
+                }
+            }
+            let file_mask = A_FILE << file;
+            pm.white_front[i as usize] = pm.white_passed[i as usize] & file_mask;
+            pm.black_front[i as usize] = pm.black_passed[i as usize] & file_mask;
+        }
+        pm
+    }
+}
+
+struct LineMasks {
+    // Squares strictly between two squares on the same rank, file or diagonal
+    between: [[u64; 64]; 64],
+    // Every square of the rank, file or diagonal through two squares
+    line: [[u64; 64]; 64],
+}
+
+impl LineMasks {
+    fn new() -> Self {
+        let mut lm = LineMasks {
+            between: [[0; 64]; 64],
+            line: [[0; 64]; 64],
+        };
+        for a in 0u8..64 {
+            for b in 0u8..64 {
+                let (rank_delta, file_delta) = (
+                    (b / 8) as isize - (a / 8) as isize,
+                    (b % 8) as isize - (a % 8) as isize,
+                );
+                let aligned =
+                    rank_delta == 0 || file_delta == 0 || rank_delta.abs() == file_delta.abs();
+                if a == b || !aligned {
+                    continue;
+                }
+                let step = (rank_delta.signum(), file_delta.signum());
+                let mut square = step_square(a, step);
+                while square != Some(b) {
+                    let s = square.expect("aligned squares are reached before the edge");
+                    lm.between[a as usize][b as usize].set_bit(s);
+                    square = step_square(s, step);
+                }
+                // Walk from a to the edge in both directions
+                lm.line[a as usize][b as usize].set_bit(a);
+                for direction in [step, (-step.0, -step.1)] {
+                    let mut square = step_square(a, direction);
+                    while let Some(s) = square {
+                        lm.line[a as usize][b as usize].set_bit(s);
+                        square = step_square(s, direction);
+                    }
+                }
+            }
+        }
+        lm
+    }
+}
+
+// The square one step of (rank, file) away, None if it's off the board
+fn step_square(square: u8, (rank_step, file_step): (isize, isize)) -> Option<u8> {
+    let rank = (square / 8) as isize + rank_step;
+    let file = (square % 8) as isize + file_step;
+    match (0..8).contains(&rank) && (0..8).contains(&file) {
+        true => Some((rank * 8 + file) as u8),
+        false => None,
+    }
+}
+
+// Squares strictly between two squares on the same rank, file or diagonal, empty otherwise
+#[inline(always)]
+fn between(a: u8, b: u8) -> u64 {
+    LINE_MASKS.between[a as usize][b as usize]
+}
+
+// Every square of the rank, file or diagonal through two squares, empty if they aren't aligned
+#[inline(always)]
+fn line(a: u8, b: u8) -> u64 {
+    LINE_MASKS.line[a as usize][b as usize]
+}
+
+// Position of the count of `color`'s `piece`s in Board::material_key, four bits each are enough
+// for nine queens or ten of any other promoted piece
+fn material_shift(piece: Piece, color: Color) -> u32 {
+    let slot = match color {
+        Color::White => piece as u32,
+        Color::Black => piece as u32 + 6,
+    };
+    slot * 4
+}
+
+// Index into Board::castle_rooks
+fn castle_index(color: Color, king_side: bool) -> usize {
+    match (color, king_side) {
+        (Color::White, true) => 0,
+        (Color::White, false) => 1,
+        (Color::Black, true) => 2,
+        (Color::Black, false) => 3,
+    }
+}
+
+// Where the king and rook end up after castling, the same in Chess960 as in standard chess
+fn castle_destinations(color: Color, king_side: bool) -> (u8, u8) {
+    match (color, king_side) {
+        (Color::White, true) => (G1, F1),
+        (Color::White, false) => (C1, D1),
+        (Color::Black, true) => (G8, F8),
+        (Color::Black, false) => (C8, D8),
+    }
+}
+
+/// A legal move in a position
+///
+/// The UCI and SAN strings are only formatted when requested.
+#[derive(Debug, Copy, Clone)]
+pub struct LegalMove<'a> {
+    play: Play,
+    board: &'a Board,
+}
+
+impl<'a> LegalMove<'a> {
+    pub fn play(&self) -> Play {
+        self.play
+    }
+
+    pub fn uci(&self) -> String {
+        format!("{}", self.play)
+    }
+
+    pub fn san(&self) -> String {
+        self.play.to_san(self.board)
+    }
+}
+
+/// Whether the game is over and why, see Board::game_state
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameState {
+    Ongoing,
+    /// The side to move is checkmated, the other side wins
+    Checkmate(Color),
+    Stalemate,
+    DrawByFiftyMove,
+    DrawByRepetition,
+    DrawByInsufficientMaterial,
+}
+
+impl GameState {
+    pub fn is_over(&self) -> bool {
+        *self != GameState::Ongoing
+    }
+
+    /// The side which won, None for a draw or an unfinished game
+    pub fn winner(&self) -> Option<Color> {
+        match self {
+            GameState::Checkmate(winner) => Some(*winner),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GameState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameState::Ongoing => write!(f, "game in progress"),
+            GameState::Checkmate(Color::White) => write!(f, "white wins by checkmate"),
+            GameState::Checkmate(Color::Black) => write!(f, "black wins by checkmate"),
+            GameState::Stalemate => write!(f, "draw by stalemate"),
+            GameState::DrawByFiftyMove => write!(f, "draw by the fifty move rule"),
+            GameState::DrawByRepetition => write!(f, "draw by repetition"),
+            GameState::DrawByInsufficientMaterial => write!(f, "draw by insufficient material"),
+        }
+    }
+}
+
+/// A reason a position couldn't arise in a game, see Board::validate
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PositionError {
+    /// The side doesn't have exactly one king, the number it has is given
+    KingCount(Color, u32),
+    /// The side has more than sixteen pieces or more than eight pawns
+    TooManyPieces(Color),
+    /// A pawn on the first or eighth rank
+    PawnOnBackRank(u8),
+    /// The en passant square isn't behind a pawn which just moved two squares
+    EnPassant(u8),
+    /// Both sides can castle but their kings or castling rooks start on different files
+    Castling,
+    /// The side which isn't moving is in check
+    OpponentInCheck,
+}
+
+impl fmt::Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = |color: &Color| match color {
+            Color::White => "white",
+            Color::Black => "black",
+        };
+        let square = |index: &u8| {
+            let (rank, file) = index_to_coordinate(*index);
+            format!("{}{}", file, rank)
+        };
+        match self {
+            PositionError::KingCount(c, n) => write!(f, "{} has {} kings", side(c), n),
+            PositionError::TooManyPieces(c) => write!(f, "{} has too many pieces", side(c)),
+            PositionError::PawnOnBackRank(s) => write!(f, "pawn on the back rank: {}", square(s)),
+            PositionError::EnPassant(s) => write!(f, "impossible en passant square: {}", square(s)),
+            PositionError::Castling => write!(f, "castling rights don't match between the sides"),
+            PositionError::OpponentInCheck => write!(f, "the side not to move is in check"),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+/// Iterator over the legal moves in a position, see Board::legal_moves
+pub struct LegalMoves<'a> {
+    board: &'a Board,
+    moves: std::vec::IntoIter<Play>,
+}
+
+impl<'a> Iterator for LegalMoves<'a> {
+    type Item = LegalMove<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.moves.next().map(|play| LegalMove {
+            play,
+            board: self.board,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Eq, Hash)]
 pub struct Board {
     pawns: u64,
     knights: u64,
@@ -244,9 +495,17 @@ pub struct Board {
 
     white: u64,
     black: u64,
+    // The piece on each square, kept in step with the bitboards for cheap lookups
+    squares: [Option<(Piece, Color)>; 64],
 
     pub active_color: Color,
     castle: CastlePermissions,
+    // Starting squares of the castling rooks, see castle_index. Only Chess960 positions have
+    // rooks which don't start in the corners.
+    castle_rooks: [u8; 4],
+    // The castling permissions kept when a piece moves from or to each square, clearing those of
+    // the king and castling rooks on their starting squares
+    castle_masks: [u8; 64],
     en_passant: Option<Coordinate>,
 
     pub ply: usize,
@@ -257,83 +516,189 @@ pub struct Board {
     pub white_value: u32,
     pub black_value: u32,
 
-    //history: Vec<PlayState>,
-    history: [Option<PlayState>; MAX_GAME_SIZE],
+    // Middlegame piece-square values of each side's pieces, kept up to date like the material
+    pub white_pst: isize,
+    pub black_pst: isize,
+    phase_weight: u32, // Sum of the phase weights of both sides' pieces
+    material_key: u64, // Count of each side's pieces of each type, see material_shift
+    params: Arc<EvalParams>,
+
+    history: Vec<PlayState>,
     pub key: u64,
 }
 
 impl Default for Board {
     fn default() -> Self {
-        lazy_static::initialize(&MAGIC); // TODO move this to engine/parse fen?
-        Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
+        Board::new()
     }
 }
 
 impl Board {
     pub fn new() -> Board {
-        lazy_static::initialize(&MAGIC); // TODO move this to engine/parse fen?
         Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
     }
 
+    /// Set up the position given in Forsyth-Edwards Notation
+    ///
+    /// Any position with eight ranks of eight files is accepted, see from_fen_strict to also
+    /// check the position could arise in a game. The castling rights can be given as KQkq or as
+    /// the files of the rooks (Shredder-FEN) for Chess960.
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let mut fen_iter = fen.split(' ');
+        let mut field = |field| fen_iter.next().ok_or(FenError::MissingField(field));
+        let position = field(FenField::Placement)?;
+        let active_color_token = field(FenField::ActiveColor)?;
+        let castle = field(FenField::Castling)?;
+        let en_passant = field(FenField::EnPassant)?;
+        let half_move_clock = field(FenField::HalfMoveClock)?;
+        let full_move_clock = field(FenField::FullMoveNumber)?;
+
+        let number = |field, token: &str| {
+            token
+                .parse::<usize>()
+                .map_err(|_| FenError::InvalidField(field, token.to_string()))
+        };
+        let move_number = number(FenField::FullMoveNumber, full_move_clock)?;
+        let active_color = match active_color_token.chars().collect::<Vec<_>>()[..] {
+            [c] => Color::from_char(c),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            FenError::InvalidField(FenField::ActiveColor, active_color_token.to_string())
+        })?;
+
+        let mut board = Board {
+            pawns: 0,
+            knights: 0,
+            bishops: 0,
+            rooks: 0,
+            queens: 0,
+            kings: 0,
+            white: 0,
+            black: 0,
+            squares: [None; 64],
+
+            active_color,
+            castle: CastlePermissions::new(),
+            castle_rooks: [H1, A1, H8, A8],
+            castle_masks: [CastlePermissions::ALL; 64],
+
+            ply: move_number * 2,
+            line_ply: 0,
+            move_number,
+            en_passant: Coordinate::from_string(en_passant)?,
+            fifty_move_rule: number(FenField::HalfMoveClock, half_move_clock)?,
+            white_value: 0,
+            black_value: 0,
+            white_pst: 0,
+            black_pst: 0,
+            phase_weight: 0,
+            material_key: 0,
+            params: default_params(),
+
+            history: Vec::new(),
+            key: EMPTY_KEY,
+        };
+        if matches!(board.active_color, Color::Black) {
+            board.ply += 1;
+        }
+
+        // parse out the pieces on the board, every rank must fill all eight files
+        let ranks: Vec<&str> = position.split('/').collect();
+        if ranks.len() != 8 {
+            let token = position.to_string();
+            return Err(FenError::InvalidField(FenField::Placement, token));
+        }
+        for (rank, token) in Rank::VARIANTS.into_iter().rev().zip(ranks) {
+            let wrong_length = || FenError::InvalidField(FenField::Placement, token.to_string());
+            let mut files = 0;
+            for c in token.chars() {
+                if let Some(empty @ 1..=8) = c.to_digit(10) {
+                    files += empty as usize;
+                    continue;
+                }
+                let (piece, color) = Piece::from_fen_char(c).ok_or(FenError::InvalidPiece(c))?;
+                if files >= 8 {
+                    return Err(wrong_length());
+                }
+                board.set_piece(piece, color, rank, File::VARIANTS[files]);
+                files += 1;
+            }
+            if files != 8 {
+                return Err(wrong_length());
+            }
+        }
+        board.set_castling(castle)?;
+        board.key = board.compute_key_from_scratch();
+        (board.white_value, board.black_value) = board.material_value();
+        (board.white_pst, board.black_pst) = board.pst_value();
+        Ok(board)
+    }
+
     pub fn generate_captures(&self) -> Vec<Play> {
         let mut moves = Vec::with_capacity(25);
+        self.add_captures(&mut moves);
+        moves
+    }
+
+    /// Like generate_captures, but fills a reusable list, replacing any moves already in it
+    pub fn generate_captures_into(&self, moves: &mut MoveList) {
+        moves.clear();
+        self.add_captures(moves);
+    }
+
+    fn add_captures<B: MoveBuffer>(&self, moves: &mut B) {
         let (color_mask, capture_mask) = match self.active_color {
             Color::Black => (self.black, self.white),
             Color::White => (self.white, self.black),
         };
         let all_pieces = self.black | self.white;
         // knights
-        let knights = (self.knights & color_mask).get_set_bits();
+        let knights = (self.knights & color_mask).iter_bits();
         for from in knights {
             // Only include moves which don't have another piece of our color at the to square
-            let kmoves = ATTACK_MASKS.knights[from as usize] & (capture_mask);
-            for to in kmoves.get_set_bits() {
+            let kmoves = knight_attacks(from) & (capture_mask);
+            for to in kmoves.iter_bits() {
                 let capture = self.get_piece_index(to);
-                moves.push(Play::new(from as u8, to as u8, capture, None, false, false));
+                moves.push(Play::new(from, to, capture, None, false, false));
             }
         }
         // queens and rooks
-        let queens_and_rooks = ((self.queens | self.rooks) & color_mask).get_set_bits();
+        let queens_and_rooks = ((self.queens | self.rooks) & color_mask).iter_bits();
         for from in queens_and_rooks {
-            let move_mask = MAGIC.get_straight_move(from, all_pieces) & capture_mask;
-            for to in move_mask.get_set_bits() {
+            let move_mask = rook_attacks(from, all_pieces) & capture_mask;
+            for to in move_mask.iter_bits() {
                 let capture = self.get_piece_index(to);
                 moves.push(Play::new(from, to, capture, None, false, false));
             }
         }
         // queens and bishops
-        let queens_and_bishops = ((self.queens | self.bishops) & color_mask).get_set_bits();
+        let queens_and_bishops = ((self.queens | self.bishops) & color_mask).iter_bits();
         for from in queens_and_bishops {
-            let move_mask = MAGIC.get_diagonal_move(from, all_pieces) & capture_mask;
-            for to in move_mask.get_set_bits() {
+            let move_mask = bishop_attacks(from, all_pieces) & capture_mask;
+            for to in move_mask.iter_bits() {
                 let capture = self.get_piece_index(to);
                 moves.push(Play::new(from, to, capture, None, false, false));
             }
         }
         // kings
-        let kings = (self.kings & color_mask).get_set_bits();
+        let kings = (self.kings & color_mask).iter_bits();
         for from in kings {
             // Only include moves which don't have another piece of our color at the to square
-            let kmove = ATTACK_MASKS.kings[from as usize] & capture_mask;
-            for to in kmove.get_set_bits() {
+            let kmove = king_attacks(from) & capture_mask;
+            for to in kmove.iter_bits() {
                 let capture = self.get_piece_index(to);
                 moves.push(Play::new(from, to, capture, None, false, false));
             }
         }
         //pawns
-        let pawns = (self.pawns & color_mask).get_set_bits();
+        let pawns = (self.pawns & color_mask).iter_bits();
         for from in pawns {
             let (rank, _) = index_to_coordinate(from);
-            let can_promote = match self.active_color {
-                Color::White => rank == 7,
-                Color::Black => rank == 2,
-            };
+            let can_promote = rank.relative(self.active_color) == Rank::Seven;
             // move diagonally and capture
-            let pmoves: u64 = match self.active_color {
-                Color::White => ATTACK_MASKS.black_pawns[from as usize] & capture_mask,
-                Color::Black => ATTACK_MASKS.white_pawns[from as usize] & capture_mask,
-            };
-            for to in pmoves.get_set_bits() {
+            let pmoves = pawn_attacks(from, self.active_color) & capture_mask;
+            for to in pmoves.iter_bits() {
                 let capture = self.get_piece_index(to);
                 if can_promote {
                     for p in PromotePiece::VARIANTS {
@@ -346,126 +711,130 @@ impl Board {
             // en passant
             if let Some(en_passant) = &self.en_passant {
                 let i = en_passant.as_index();
-                let can_en_passant = match self.active_color {
-                    Color::White => ATTACK_MASKS.black_pawns[from as usize].is_bit_set(i),
-                    Color::Black => ATTACK_MASKS.white_pawns[from as usize].is_bit_set(i),
-                };
-                if can_en_passant {
+                if pawn_attacks(from, self.active_color).is_bit_set(i) {
                     moves.push(Play::new(from, i, Some(Piece::Pawn), None, true, false));
                 }
             }
         }
-        moves
+    }
+
+    // Castling with the rook on one side of the king, if it's allowed and possible
+    //
+    // Apart from the rook and king themselves every square either piece crosses or lands on must
+    // be empty, and the king can't be in check, pass through or land on an attacked square. The
+    // rook is ignored when looking for attacks as in Chess960 it could be shielding the king.
+    fn castle_move(&self, king: u8, king_side: bool) -> Option<Play> {
+        let color = self.active_color;
+        if !self.castle.allowed(color, king_side) {
+            return None;
+        }
+        let own = match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        let rook = self.castle_rooks[castle_index(color, king_side)];
+        if !(self.rooks & own).is_bit_set(rook) {
+            return None;
+        }
+        let (king_to, rook_to) = castle_destinations(color, king_side);
+        let (king_mask, rook_mask) = (1u64 << king, 1u64 << rook);
+        let king_path = between(king, king_to) | (1u64 << king_to) | king_mask;
+        let rook_path = between(rook, rook_to) | (1u64 << rook_to);
+        let occupied = (self.white | self.black) & !king_mask & !rook_mask;
+        if ((king_path | rook_path) & occupied) != 0 {
+            return None;
+        }
+        let occupied = occupied | king_mask;
+        for square in king_path.iter_bits() {
+            if self.attackers(square, !color, occupied) != 0 {
+                return None;
+            }
+        }
+        Some(Play::new(king, king_to, None, None, false, true))
+    }
+
+    // The start and end squares of the rook when the king castles to `king_to`
+    fn castle_rook_squares(&self, king_to: u8) -> (u8, u8) {
+        let (color, king_side) = match king_to {
+            G1 => (Color::White, true),
+            C1 => (Color::White, false),
+            G8 => (Color::Black, true),
+            C8 => (Color::Black, false),
+            _ => unreachable!(),
+        };
+        let (_, rook_to) = castle_destinations(color, king_side);
+        (self.castle_rooks[castle_index(color, king_side)], rook_to)
     }
 
     pub fn generate_moves(&self) -> Vec<Play> {
         let mut moves = Vec::with_capacity(50);
+        self.add_moves(&mut moves);
+        moves
+    }
+
+    /// Like generate_moves, but fills a reusable list, replacing any moves already in it
+    pub fn generate_moves_into(&self, moves: &mut MoveList) {
+        moves.clear();
+        self.add_moves(moves);
+    }
+
+    fn add_moves<B: MoveBuffer>(&self, moves: &mut B) {
         let (color_mask, capture_mask) = match self.active_color {
             Color::Black => (self.black, self.white),
             Color::White => (self.white, self.black),
         };
         let all_pieces = self.black | self.white;
         // knights
-        let knights = (self.knights & color_mask).get_set_bits();
+        let knights = (self.knights & color_mask).iter_bits();
         for from in knights {
             // Only include moves which don't have another piece of our color at the to square
-            let kmoves = ATTACK_MASKS.knights[from as usize] & (!color_mask);
-            for to in kmoves.get_set_bits() {
+            let kmoves = knight_attacks(from) & (!color_mask);
+            for to in kmoves.iter_bits() {
                 let capture = self.get_piece_index(to);
-                moves.push(Play::new(from as u8, to as u8, capture, None, false, false));
+                moves.push(Play::new(from, to, capture, None, false, false));
             }
         }
         // queens and rooks
-        let queens_and_rooks = ((self.queens | self.rooks) & color_mask).get_set_bits();
+        let queens_and_rooks = ((self.queens | self.rooks) & color_mask).iter_bits();
         for from in queens_and_rooks {
-            let move_mask = MAGIC.get_straight_move(from, all_pieces) & !color_mask;
-            for to in move_mask.get_set_bits() {
+            let move_mask = rook_attacks(from, all_pieces) & !color_mask;
+            for to in move_mask.iter_bits() {
                 let capture = self.get_piece_index(to);
                 moves.push(Play::new(from, to, capture, None, false, false));
             }
         }
         // queens and bishops
-        let queens_and_bishops = ((self.queens | self.bishops) & color_mask).get_set_bits();
+        let queens_and_bishops = ((self.queens | self.bishops) & color_mask).iter_bits();
         for from in queens_and_bishops {
-            let move_mask = MAGIC.get_diagonal_move(from, all_pieces) & !color_mask;
-            for to in move_mask.get_set_bits() {
+            let move_mask = bishop_attacks(from, all_pieces) & !color_mask;
+            for to in move_mask.iter_bits() {
                 let capture = self.get_piece_index(to);
                 moves.push(Play::new(from, to, capture, None, false, false));
             }
         }
         // kings
-        let kings = (self.kings & color_mask).get_set_bits();
+        let kings = (self.kings & color_mask).iter_bits();
         for from in kings {
             // Only include moves which don't have another piece of our color at the to square
-            let kmove = ATTACK_MASKS.kings[from as usize] & (!color_mask);
-            for to in kmove.get_set_bits() {
+            let kmove = king_attacks(from) & (!color_mask);
+            for to in kmove.iter_bits() {
                 let capture = self.get_piece_index(to);
                 moves.push(Play::new(from, to, capture, None, false, false));
             }
-            // 1. castle permission is available
-            // 2. king is not in check
-            // 3. movement squares are not occupied
-            // 4. None of the squares are in check
-            if matches!(self.active_color, Color::White)
-                && (self.castle.white_king_side || self.castle.white_queen_side)
-            {
-                let check = self.square_attacked(E1, Color::Black);
-                if !check {
-                    if self.castle.white_queen_side
-                        && (*B1_C1_D1 & all_pieces) == 0
-                        && [C1, D1]
-                            .iter()
-                            .all(|i| !self.square_attacked(*i, Color::Black))
-                    {
-                        moves.push(Play::new(from, C1, None, None, false, true));
-                    }
-                    if self.castle.white_king_side
-                        && (*F1_G1 & all_pieces) == 0
-                        && [F1, G1]
-                            .iter()
-                            .all(|i| !self.square_attacked(*i, Color::Black))
-                    {
-                        moves.push(Play::new(from, G1, None, None, false, true));
-                    }
-                }
-            } else if matches!(self.active_color, Color::Black)
-                && (self.castle.black_king_side || self.castle.black_queen_side)
-            {
-                let check = self.square_attacked(E8, Color::White);
-                if !check {
-                    if self.castle.black_queen_side
-                        && (*B8_C8_D8 & all_pieces) == 0
-                        && [C8, D8]
-                            .iter()
-                            .all(|i| !self.square_attacked(*i, Color::White))
-                    {
-                        moves.push(Play::new(from, C8, None, None, false, true));
-                    }
-                    if self.castle.black_king_side
-                        && (*F8_G8 & all_pieces) == 0
-                        && [F8, G8]
-                            .iter()
-                            .all(|i| !self.square_attacked(*i, Color::White))
-                    {
-                        moves.push(Play::new(from, G8, None, None, false, true));
-                    }
+            for king_side in [true, false] {
+                if let Some(play) = self.castle_move(from, king_side) {
+                    moves.push(play);
                 }
             }
         }
         //pawns
-        let pawns = (self.pawns & color_mask).get_set_bits();
+        let pawns = (self.pawns & color_mask).iter_bits();
         for from in pawns {
             let (rank, _) = index_to_coordinate(from);
-            let can_promote = match self.active_color {
-                Color::White => rank == 7,
-                Color::Black => rank == 2,
-            };
+            let can_promote = rank.relative(self.active_color) == Rank::Seven;
             // move diagonally and capture
-            let pmoves: u64 = match self.active_color {
-                Color::White => ATTACK_MASKS.black_pawns[from as usize] & capture_mask,
-                Color::Black => ATTACK_MASKS.white_pawns[from as usize] & capture_mask,
-            };
-            for to in pmoves.get_set_bits() {
+            let pmoves = pawn_attacks(from, self.active_color) & capture_mask;
+            for to in pmoves.iter_bits() {
                 let capture = self.get_piece_index(to);
                 if can_promote {
                     for p in PromotePiece::VARIANTS {
@@ -489,10 +858,7 @@ impl Board {
                     }
                 } else {
                     moves.push(Play::new(from, to, None, None, false, false));
-                    if match self.active_color {
-                        Color::White => rank == 2,
-                        Color::Black => rank == 7,
-                    } {
+                    if rank.relative(self.active_color) == Rank::Two {
                         let to = match self.active_color {
                             Color::White => to as isize + 8,
                             Color::Black => to as isize - 8,
@@ -507,144 +873,813 @@ impl Board {
             // en passant
             if let Some(en_passant) = &self.en_passant {
                 let i = en_passant.as_index();
-                let can_en_passant = match self.active_color {
-                    Color::White => ATTACK_MASKS.black_pawns[from as usize].is_bit_set(i),
-                    Color::Black => ATTACK_MASKS.white_pawns[from as usize].is_bit_set(i),
-                };
-                if can_en_passant {
+                if pawn_attacks(from, self.active_color).is_bit_set(i) {
                     moves.push(Play::new(from, i, Some(Piece::Pawn), None, true, false));
                 }
             }
         }
-        moves
     }
 
-    fn piece_value(&self, index: u8) -> isize {
-        match self.get_piece_and_color_index(index) {
-            Some((p, Color::White)) => PVT.get_value(index as usize, p, Color::White),
-            Some((p, Color::Black)) => -PVT.get_value(index as usize, p, Color::Black),
-            None => 0,
-        }
+    /// Promotions which don't capture, queen promotions first
+    pub fn generate_promotions(&self) -> Vec<Play> {
+        let mut moves = Vec::new();
+        self.add_promotions(&mut moves);
+        moves
     }
 
-    pub fn eval(&self) -> i64 {
-        // TODO should this return white value & black value as separate numbers instead?
-        // TODO should this return i32 or isize instead
-        let eval = i64::from(self.white_value) - i64::from(self.black_value);
-
-        let mut score = 0i64;
-        for i in (self.black | self.white).get_set_bits() {
-            score += self.piece_value(i) as i64;
+    fn add_promotions<B: MoveBuffer>(&self, moves: &mut B) {
+        let color_mask = match self.active_color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        let all_pieces = self.black | self.white;
+        for from in (self.pawns & color_mask).iter_bits() {
+            let (rank, _) = index_to_coordinate(from);
+            let to = match self.active_color {
+                Color::White => from + 8,
+                Color::Black => from.wrapping_sub(8),
+            };
+            if rank.relative(self.active_color) != Rank::Seven || all_pieces.is_bit_set(to) {
+                continue;
+            }
+            for p in PromotePiece::VARIANTS.into_iter().rev() {
+                moves.push(Play::new(from, to, None, Some(p), false, false));
+            }
         }
-        let eval = eval + score;
+    }
 
-        match self.active_color {
-            Color::White => eval,
-            Color::Black => -eval,
-        }
+    /// Generate the quiet (non capture, non promotion) moves which give direct check
+    ///
+    /// Discovered checks and castling into check aren't included. Like generate_moves this
+    /// doesn't check if the move leaves our own king in check.
+    pub fn generate_quiet_checks(&self) -> Vec<Play> {
+        let mut moves = Vec::new();
+        self.add_quiet_checks(&mut moves);
+        moves
     }
 
-    pub fn square_attacked(&self, index: u8, color: Color) -> bool {
-        let all = self.black | self.white;
-        let attack_masks = &ATTACK_MASKS;
-        let (color_mask, pawn_masks) = match color {
-            Color::Black => (self.black, &attack_masks.black_pawns),
-            Color::White => (self.white, &attack_masks.white_pawns),
+    fn add_quiet_checks<B: MoveBuffer>(&self, moves: &mut B) {
+        let (color_mask, opposing_mask) = match self.active_color {
+            Color::Black => (self.black, self.white),
+            Color::White => (self.white, self.black),
         };
-        // pawns
-        if (pawn_masks[index as usize] & self.pawns & color_mask) > 0 {
-            return true;
-        }
+        let all_pieces = self.black | self.white;
+        let king = match (self.kings & opposing_mask).iter_bits().next() {
+            Some(king) => king,
+            None => return,
+        };
+        // The squares a piece gives check from are the squares it would attack from the king
+        let knight_checks = knight_attacks(king) & !all_pieces;
+        let diagonal_checks = bishop_attacks(king, all_pieces) & !all_pieces;
+        let straight_checks = rook_attacks(king, all_pieces) & !all_pieces;
+        // A pawn checks the king from the squares a pawn of the king's colour would attack
+        let pawn_checks = pawn_attacks(king, !self.active_color) & !all_pieces;
 
-        // knights
-        if (attack_masks.knights[index as usize] & self.knights & color_mask) > 0 {
-            return true;
+        for from in (self.knights & color_mask).iter_bits() {
+            for to in (knight_attacks(from) & knight_checks).iter_bits() {
+                moves.push(Play::new(from, to, None, None, false, false));
+            }
         }
-
-        // bishops & queens
-        let bishop_or_queen = (self.bishops | self.queens) & color_mask;
-        if (attack_masks.diagonal[index as usize] & bishop_or_queen) > 0 {
-            let move_mask = MAGIC.get_diagonal_move(index, all);
-            if (move_mask & bishop_or_queen) > 0 {
-                return true;
+        for from in ((self.bishops | self.queens) & color_mask).iter_bits() {
+            let checks = if self.queens.is_bit_set(from) {
+                diagonal_checks | straight_checks
+            } else {
+                diagonal_checks
+            };
+            for to in (bishop_attacks(from, all_pieces) & checks).iter_bits() {
+                moves.push(Play::new(from, to, None, None, false, false));
             }
         }
-
-        // rooks & queens
-        let rook_or_queen = (self.rooks | self.queens) & color_mask;
-        if (attack_masks.straight[index as usize] & rook_or_queen) > 0 {
-            let move_mask = MAGIC.get_straight_move(index, all);
-            if (move_mask & rook_or_queen) > 0 {
-                return true;
+        for from in ((self.rooks | self.queens) & color_mask).iter_bits() {
+            let checks = if self.queens.is_bit_set(from) {
+                diagonal_checks | straight_checks
+            } else {
+                straight_checks
+            };
+            for to in (rook_attacks(from, all_pieces) & checks).iter_bits() {
+                moves.push(Play::new(from, to, None, None, false, false));
+            }
+        }
+        for from in (self.pawns & color_mask).iter_bits() {
+            let rank = index_to_coordinate(from).0.relative(self.active_color);
+            let (single, double) = match self.active_color {
+                Color::White => (from + 8, from + 16),
+                Color::Black => (from - 8, from.wrapping_sub(16)),
+            };
+            if rank == Rank::Seven || all_pieces.is_bit_set(single) {
+                continue;
+            }
+            if pawn_checks.is_bit_set(single) {
+                moves.push(Play::new(from, single, None, None, false, false));
+            }
+            if rank == Rank::Two && pawn_checks.is_bit_set(double) {
+                moves.push(Play::new(from, double, None, None, false, false));
             }
         }
+    }
 
-        // kings
-        if (attack_masks.kings[index as usize] & self.kings & color_mask) > 0 {
-            return true;
-        };
+    /// Iterate over the legal moves in the current position
+    ///
+    /// Unlike generate_moves this only yields moves which don't leave the king in check.
+    pub fn legal_moves(&self) -> LegalMoves<'_> {
+        LegalMoves {
+            board: self,
+            moves: self.generate_legal_moves().into_iter(),
+        }
+    }
 
-        false
+    /// Generate the moves which don't leave the king in check, without making them
+    ///
+    /// Pseudo-legal moves are filtered using the pieces pinned to the king and the pieces giving
+    /// check, so make_move never rejects these.
+    pub fn generate_legal_moves(&self) -> Vec<Play> {
+        let mut moves = self.generate_moves();
+        self.retain_legal(&mut moves);
+        moves
     }
 
-    pub fn is_repetition(&self) -> bool {
-        let i = self.ply - self.fifty_move_rule;
-        let matching = self.history[i..=self.ply]
-            .iter()
-            .filter_map(|h| h.map(|hh| hh.position_key))
-            .filter(|k| *k == self.key)
-            .count();
-        matching >= 2
+    /// Like generate_legal_moves, but fills a reusable list, replacing any moves already in it
+    pub fn generate_legal_moves_into(&self, moves: &mut MoveList) {
+        self.generate_moves_into(moves);
+        self.retain_legal(moves);
     }
 
-    pub fn make_move(&mut self, play: &Play) -> bool {
-        self.history[self.ply] = Some(PlayState {
-            play: *play,
-            en_passant: self.en_passant,
-            castle: self.castle,
-            fifty_move_rule: self.fifty_move_rule,
-            position_key: self.key,
-        });
+    fn retain_legal<B: MoveBuffer>(&self, moves: &mut B) {
+        let (own, opposing_color) = match self.active_color {
+            Color::White => (self.white, Color::Black),
+            Color::Black => (self.black, Color::White),
+        };
+        let king = match self.kings & own {
+            0 => return,
+            k => k.trailing_zeros() as u8,
+        };
+        let all = self.white | self.black;
+        let pinned = self.pinned_pieces(self.active_color);
 
-        let opposing_color = !self.active_color;
-        // update castling permissions
-        match play.from {
-            A1 => self.castle.white_queen_side = false,
-            E1 => {
-                self.castle.white_queen_side = false;
-                self.castle.white_king_side = false;
-            }
-            H1 => self.castle.white_king_side = false,
-            A8 => self.castle.black_queen_side = false,
-            E8 => {
-                self.castle.black_queen_side = false;
-                self.castle.black_king_side = false;
-            }
-            H8 => self.castle.black_king_side = false,
-            _ => (),
-        }
-        match play.to {
-            // This covers the case where a rook which hasn't moved is captured
-            // since it would end the game we don't need to check the same for king
-            A1 => self.castle.white_queen_side = false,
-            H1 => self.castle.white_king_side = false,
-            A8 => self.castle.black_queen_side = false,
-            H8 => self.castle.black_king_side = false,
-            _ => (),
-        }
-        self.en_passant = None;
-        self.fifty_move_rule += 1;
+        // Out of check the king must be moved, or the checker captured or blocked
+        let checkers = self.attackers(king, opposing_color, all);
+        let evasions = match checkers.count_ones() {
+            0 => u64::MAX,
+            1 => checkers | between(king, checkers.trailing_zeros() as u8),
+            _ => 0,
+        };
+
+        moves.retain(|play| {
+            if play.from == king {
+                // Castling was already checked for attacked squares
+                return play.castle
+                    || self.attackers(play.to, opposing_color, all ^ (1u64 << king)) == 0;
+            }
+            if play.en_passant {
+                // Two pawns leave the rank at once which can uncover an attack on the king, so
+                // check the position after the capture directly
+                let captured = match self.active_color {
+                    Color::White => play.to - 8,
+                    Color::Black => play.to + 8,
+                };
+                let occupied = (all ^ (1u64 << play.from) ^ (1u64 << captured)) | (1u64 << play.to);
+                return self.attackers(king, opposing_color, occupied) & !(1u64 << captured) == 0;
+            }
+            if !evasions.is_bit_set(play.to) {
+                return false;
+            }
+            // A pinned piece can only move along the line through it and the king
+            !pinned.is_bit_set(play.from) || line(king, play.from).is_bit_set(play.to)
+        });
+    }
+
+    /// True if a pseudo-legal move checks the opposing king, without making the move
+    ///
+    /// Direct checks, including from a promoted piece or the rook after castling, and checks
+    /// discovered by moving a piece or by capturing en passant are all found.
+    pub fn gives_check(&self, play: &Play) -> bool {
+        let (own, their) = match self.active_color {
+            Color::White => (self.white, self.black),
+            Color::Black => (self.black, self.white),
+        };
+        let king = match self.kings & their {
+            0 => return false,
+            k => k.trailing_zeros() as u8,
+        };
+        let piece = match (play.promote, self.get_piece_index(play.from)) {
+            (Some(promote), _) => (&promote).into(),
+            (None, Some(piece)) => piece,
+            (None, None) => return false,
+        };
+        let mut vacated = 1u64 << play.from;
+        let mut occupied = (self.white | self.black) & !vacated | (1u64 << play.to);
+        if play.en_passant {
+            let captured = match self.active_color {
+                Color::White => play.to - 8,
+                Color::Black => play.to + 8,
+            };
+            occupied &= !(1u64 << captured);
+        }
+
+        let attacks = |piece: Piece, from: u8| match piece {
+            Piece::Pawn => pawn_attacks(from, self.active_color),
+            Piece::Knight => knight_attacks(from),
+            Piece::Bishop => bishop_attacks(from, occupied),
+            Piece::Rook => rook_attacks(from, occupied),
+            Piece::Queen => queen_attacks(from, occupied),
+            Piece::King => 0,
+        };
+        if attacks(piece, play.to).is_bit_set(king) {
+            return true;
+        }
+        if play.castle {
+            let (rook_from, rook_to) = self.castle_rook_squares(play.to);
+            vacated |= 1u64 << rook_from;
+            occupied = (self.white | self.black) & !vacated | (1u64 << play.to) | (1u64 << rook_to);
+            if rook_attacks(rook_to, occupied).is_bit_set(king) {
+                return true;
+            }
+        }
+
+        // Our sliders which the move uncovered
+        let diagonal = (self.bishops | self.queens) & own & !vacated;
+        let straight = (self.rooks | self.queens) & own & !vacated;
+        ((bishop_attacks(king, occupied) & diagonal) | (rook_attacks(king, occupied) & straight))
+            != 0
+    }
+
+    /// Pieces of `color` which are the only piece between their king and an enemy rook, bishop
+    /// or queen, and so can't leave that line without exposing the king
+    pub fn pinned_pieces(&self, color: Color) -> u64 {
+        let (own, their) = match color {
+            Color::White => (self.white, self.black),
+            Color::Black => (self.black, self.white),
+        };
+        let king = match self.kings & own {
+            0 => return 0,
+            k => k.trailing_zeros() as u8,
+        };
+        let all = own | their;
+        // Enemy sliders which would attack the king if our pieces were removed
+        let snipers = ((rook_attacks(king, their) & (self.rooks | self.queens))
+            | (bishop_attacks(king, their) & (self.bishops | self.queens)))
+            & their;
+        let mut pinned = 0;
+        for sniper in snipers.get_set_bits() {
+            let blockers = between(king, sniper) & all;
+            if blockers.count_ones() == 1 {
+                pinned |= blockers & own;
+            }
+        }
+        pinned
+    }
+
+    /// Pieces of `color` attacking a square, with sliding attacks blocked by `occupied`
+    fn attackers(&self, index: u8, color: Color, occupied: u64) -> u64 {
+        let color_mask = match color {
+            Color::Black => self.black,
+            Color::White => self.white,
+        };
+        ((pawn_attacks(index, !color) & self.pawns)
+            | (knight_attacks(index) & self.knights)
+            | (bishop_attacks(index, occupied) & (self.bishops | self.queens))
+            | (rook_attacks(index, occupied) & (self.rooks | self.queens))
+            | (king_attacks(index) & self.kings))
+            & color_mask
+            & occupied
+    }
+
+    /// Decode a move in UCI notation, e.g. "e2e4" or "e7e8q", and check it's legal here
+    ///
+    /// Only the squares are given, whether the move captures, castles or takes en passant is
+    /// worked out from the position.
+    pub fn parse_uci_move(&self, uci: &str) -> Result<Play, MoveError> {
+        let invalid = || MoveError::InvalidFormat(uci.to_string());
+        let chars: Vec<char> = uci.chars().collect();
+        if chars.len() != 4 && chars.len() != 5 {
+            return Err(invalid());
+        }
+        let square = |file: char, rank: char| -> Result<u8, MoveError> {
+            let file = File::try_from(file.to_ascii_lowercase()).map_err(|_| invalid())?;
+            let rank = Rank::try_from(rank).map_err(|_| invalid())?;
+            Ok(coordinate_to_index(rank, file))
+        };
+        let from = square(chars[0], chars[1])?;
+        let to = square(chars[2], chars[3])?;
+        let promote = match chars.get(4) {
+            Some(&c) => Some(PromotePiece::from_char(c).ok_or_else(invalid)?),
+            None => None,
+        };
+
+        let own = match self.active_color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        let piece = match self.get_piece_index(from) {
+            Some(piece) if own.is_bit_set(from) => piece,
+            _ => return Err(MoveError::NoPiece(uci.to_string())),
+        };
+        let en_passant = piece == Piece::Pawn
+            && from % 8 != to % 8
+            && self.en_passant.map(|c| c.as_index()) == Some(to);
+        let capture = match en_passant {
+            true => Some(Piece::Pawn),
+            false => self.get_piece_index(to),
+        };
+        // Chess960 castling is written as the king capturing its own rook
+        let onto_rook = piece == Piece::King && (self.rooks & own).is_bit_set(to);
+        let castle = onto_rook || (piece == Piece::King && from.abs_diff(to) == 2);
+        let (to, capture) = match onto_rook {
+            true => (castle_destinations(self.active_color, to > from).0, None),
+            false => (to, capture),
+        };
+        let play = Play::new(from, to, capture, promote, en_passant, castle);
+
+        match self.is_legal(&play) {
+            true => Ok(play),
+            false => Err(MoveError::Illegal(uci.to_string())),
+        }
+    }
+
+    // True if the move could be generated here and doesn't leave the king in check
+    fn is_legal(&self, play: &Play) -> bool {
+        if !self.is_pseudo_legal(play) {
+            return false;
+        }
+        let mut legal = vec![*play];
+        self.retain_legal(&mut legal);
+        !legal.is_empty()
+    }
+
+    /// Find the legal move written in standard algebraic notation, see Play::from_san
+    pub fn parse_san(&self, san: &str) -> Result<Play, MoveError> {
+        Play::from_san(self, san)
+    }
+
+    /// Cheaply check that a move (e.g. from the transposition table) could have been generated
+    /// in this position
+    ///
+    /// Like generate_moves this doesn't check if the move leaves the king in check.
+    pub fn is_pseudo_legal(&self, play: &Play) -> bool {
+        let (color_mask, capture_mask) = match self.active_color {
+            Color::Black => (self.black, self.white),
+            Color::White => (self.white, self.black),
+        };
+        // Castling is rare enough that it isn't worth duplicating the checks in generate_moves.
+        // In Chess960 the king can castle onto its own rook, or not move at all.
+        if play.castle {
+            return (self.kings & color_mask).is_bit_set(play.from)
+                && self.generate_moves().contains(play);
+        }
+        if !color_mask.is_bit_set(play.from) || color_mask.is_bit_set(play.to) {
+            return false;
+        }
+        let piece = match self.get_piece_index(play.from) {
+            Some(piece) => piece,
+            None => return false,
+        };
+
+        let all_pieces = self.black | self.white;
+        let pawn_captures = pawn_attacks(play.from, self.active_color);
+        if play.en_passant {
+            return piece == Piece::Pawn
+                && play.capture == Some(Piece::Pawn)
+                && play.promote.is_none()
+                && self.en_passant.map(|c| c.as_index()) == Some(play.to)
+                && pawn_captures.is_bit_set(play.to);
+        }
+        if play.capture != self.get_piece_index(play.to) {
+            return false;
+        }
+        let (to_rank, _) = index_to_coordinate(play.to);
+        let promotion = piece == Piece::Pawn && to_rank.relative(self.active_color) == Rank::Eight;
+        if play.promote.is_some() != promotion {
+            return false;
+        }
+
+        let reachable = match piece {
+            Piece::Knight => knight_attacks(play.from),
+            Piece::Bishop => bishop_attacks(play.from, all_pieces),
+            Piece::Rook => rook_attacks(play.from, all_pieces),
+            Piece::Queen => queen_attacks(play.from, all_pieces),
+            Piece::King => king_attacks(play.from),
+            Piece::Pawn => {
+                let (rank, _) = index_to_coordinate(play.from);
+                let (single, double) = match self.active_color {
+                    Color::White => (play.from + 8, play.from + 16),
+                    Color::Black => (play.from - 8, play.from.wrapping_sub(16)),
+                };
+                let mut pushes = 0u64;
+                if !all_pieces.is_bit_set(single) {
+                    pushes |= 1 << single;
+                    let start_rank = rank.relative(self.active_color) == Rank::Two;
+                    if start_rank && !all_pieces.is_bit_set(double) {
+                        pushes |= 1 << double;
+                    }
+                }
+                (pawn_captures & capture_mask) | pushes
+            }
+        };
+        reachable.is_bit_set(play.to)
+    }
+
+    /// Change in the piece-square value of the moving piece, so quiet moves which centralize or
+    /// advance pieces can be ordered ahead of other quiet moves
+    pub fn piece_square_delta(&self, play: &Play) -> i64 {
+        match self.get_piece_and_color_index(play.from) {
+            Some((piece, color)) => {
+                let to = self.params.get_value(play.to as usize, piece, color);
+                let from = self.params.get_value(play.from as usize, piece, color);
+                (to - from) as i64
+            }
+            None => 0,
+        }
+    }
+
+    /// Middlegame piece-square values of each side, calculated from scratch
+    fn pst_value(&self) -> (isize, isize) {
+        let side_value = |side: u64, color| {
+            side.get_set_bits()
+                .into_iter()
+                .map(|i| {
+                    self.params
+                        .get_value(i as usize, self.get_piece_index(i).unwrap(), color)
+                })
+                .sum()
+        };
+        (
+            side_value(self.white, Color::White),
+            side_value(self.black, Color::Black),
+        )
+    }
+
+    /// Change to a side's piece-square value from using the endgame king table
+    fn king_endgame_adjustment(&self, color: Color) -> isize {
+        let side = match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        (self.kings & side)
+            .get_set_bits()
+            .into_iter()
+            .map(|i| {
+                self.params
+                    .get_endgame_value(i as usize, Piece::King, color)
+                    - self.params.get_value(i as usize, Piece::King, color)
+            })
+            .sum()
+    }
+
+    /// How far the game is from the endgame, based on the non-pawn material left
+    ///
+    /// MAX_PHASE with all the pieces on the board (or more after promotions), 0 when only kings
+    /// and pawns are left.
+    pub fn game_phase(&self) -> u32 {
+        self.phase_weight.min(STARTING_PHASE_WEIGHT) * MAX_PHASE / STARTING_PHASE_WEIGHT
+    }
+
+    /// True if neither side has a queen, or every side with a queen has at most one minor piece
+    /// besides it
+    pub fn is_endgame(&self) -> bool {
+        [Color::White, Color::Black].into_iter().all(|color| {
+            let count = |piece| self.piece_count(piece, color);
+            count(Piece::Queen) == 0
+                || (count(Piece::Rook) == 0 && count(Piece::Knight) + count(Piece::Bishop) <= 1)
+        })
+    }
+
+    /// Bonus for a side's passed pawns
+    ///
+    /// The bonus grows as the pawn advances and is halved when an enemy piece blocks it. A rook
+    /// behind the pawn supports it, and in the endgame it's worth more the further the enemy king
+    /// is from the pawn's path compared to our king.
+    fn passed_pawn_score(&self, color: Color, endgame: bool) -> isize {
+        let (own, their, passed_masks, behind_masks) = match color {
+            Color::White => (
+                self.white,
+                self.black,
+                &PAWN_MASKS.white_passed,
+                &PAWN_MASKS.black_front,
+            ),
+            Color::Black => (
+                self.black,
+                self.white,
+                &PAWN_MASKS.black_passed,
+                &PAWN_MASKS.white_front,
+            ),
+        };
+        let all = self.white | self.black;
+        let kings = match ((self.kings & own), (self.kings & their)) {
+            (0, _) | (_, 0) => None,
+            (own_king, their_king) => Some((
+                own_king.trailing_zeros() as u8,
+                their_king.trailing_zeros() as u8,
+            )),
+        };
+        let mut score = 0;
+        for square in (self.pawns & own).get_set_bits() {
+            if (passed_masks[square as usize] & self.pawns & their) != 0 {
+                continue;
+            }
+            let rank = rank_of(square).relative(color);
+            let stop = match color {
+                Color::White => square + 8,
+                Color::Black => square - 8,
+            };
+            let mut bonus = self.params.passed_pawn[rank as usize];
+            if their.is_bit_set(stop) {
+                bonus /= 2;
+            }
+            let behind = rook_attacks(square, all) & behind_masks[square as usize];
+            if (behind & self.rooks & own) != 0 {
+                bonus += self.params.passed_pawn_rook_behind;
+            }
+            if let (true, Some((own_king, their_king))) = (endgame, kings) {
+                let distance = chebyshev_distance(their_king, stop) as isize
+                    - chebyshev_distance(own_king, stop) as isize;
+                bonus += self.params.passed_pawn_king_distance * distance * rank as isize / 4;
+            }
+            score += bonus;
+        }
+        score
+    }
+
+    /// Penalty for the pawns around a side's king: our pawns having advanced or gone, leaving
+    /// the king open, and enemy pawns coming towards it
+    fn king_pawn_safety(&self, color: Color) -> isize {
+        let (own, their, front_masks) = match color {
+            Color::White => (self.white, self.black, &PAWN_MASKS.white_passed),
+            Color::Black => (self.black, self.white, &PAWN_MASKS.black_passed),
+        };
+        let king = match self.kings & own {
+            0 => return 0,
+            k => k.trailing_zeros() as u8,
+        };
+        // The nearest pawn in front of the king on a file
+        let nearest = |pawns: u64| match (pawns, color) {
+            (0, _) => None,
+            (p, Color::White) => Some(p.trailing_zeros() as u8),
+            (p, Color::Black) => Some(63 - p.leading_zeros() as u8),
+        };
+        let ranks_ahead = |square: u8| ((square / 8) as isize - (king / 8) as isize).unsigned_abs();
+
+        let mut penalty = 0;
+        let file = king % 8;
+        for f in file.saturating_sub(1)..=(file + 1).min(7) {
+            let in_front = front_masks[king as usize] & (A_FILE << f) & self.pawns;
+            penalty += match nearest(in_front & own) {
+                Some(pawn) => self.params.king_shelter[ranks_ahead(pawn)],
+                None => self.params.king_open_file,
+            };
+            if let Some(pawn) = nearest(in_front & their) {
+                penalty += self.params.pawn_storm[ranks_ahead(pawn)];
+            }
+        }
+        penalty
+    }
+
+    pub fn eval(&self) -> i64 {
+        // TODO should this return white value & black value as separate numbers instead?
+        // TODO should this return i32 or isize instead
+        let eval = i64::from(self.white_value) - i64::from(self.black_value);
+
+        let mut score = (self.white_pst - self.black_pst) as i64;
+        let endgame = self.is_endgame();
+        if endgame {
+            score += (self.king_endgame_adjustment(Color::White)
+                - self.king_endgame_adjustment(Color::Black)) as i64;
+        }
+        score += (self.passed_pawn_score(Color::White, endgame)
+            - self.passed_pawn_score(Color::Black, endgame)) as i64;
+        if !endgame {
+            let safety = self.king_pawn_safety(Color::Black) - self.king_pawn_safety(Color::White);
+            score += safety as i64 * i64::from(self.game_phase()) / i64::from(MAX_PHASE);
+        }
+        let eval = self.scale_drawish(eval + score);
+
+        match self.active_color {
+            Color::White => eval,
+            Color::Black => -eval,
+        }
+    }
+
+    pub fn square_attacked(&self, index: u8, color: Color) -> bool {
+        let all = self.black | self.white;
+        let attack_masks = &ATTACK_MASKS;
+        let (color_mask, pawn_masks) = match color {
+            Color::Black => (self.black, &attack_masks.black_pawns),
+            Color::White => (self.white, &attack_masks.white_pawns),
+        };
+        // pawns
+        if (pawn_masks[index as usize] & self.pawns & color_mask) > 0 {
+            return true;
+        }
+
+        // knights
+        if (attack_masks.knights[index as usize] & self.knights & color_mask) > 0 {
+            return true;
+        }
+
+        // bishops & queens
+        let bishop_or_queen = (self.bishops | self.queens) & color_mask;
+        if (attack_masks.diagonal[index as usize] & bishop_or_queen) > 0 {
+            let move_mask = bishop_attacks(index, all);
+            if (move_mask & bishop_or_queen) > 0 {
+                return true;
+            }
+        }
+
+        // rooks & queens
+        let rook_or_queen = (self.rooks | self.queens) & color_mask;
+        if (attack_masks.straight[index as usize] & rook_or_queen) > 0 {
+            let move_mask = rook_attacks(index, all);
+            if (move_mask & rook_or_queen) > 0 {
+                return true;
+            }
+        }
+
+        // kings
+        if (attack_masks.kings[index as usize] & self.kings & color_mask) > 0 {
+            return true;
+        };
+
+        false
+    }
+
+    /// True if neither side can checkmate by any series of legal moves: king against king, a
+    /// single minor piece, or only bishops all on the same color squares
+    pub fn is_insufficient_material(&self) -> bool {
+        let count =
+            |piece| self.piece_count(piece, Color::White) + self.piece_count(piece, Color::Black);
+        if count(Piece::Pawn) + count(Piece::Rook) + count(Piece::Queen) != 0 {
+            return false;
+        }
+        if count(Piece::Knight) + count(Piece::Bishop) <= 1 {
+            return true;
+        }
+        count(Piece::Knight) == 0
+            && ((self.bishops & LIGHT_SQUARES) == 0 || (self.bishops & !LIGHT_SQUARES) == 0)
+    }
+
+    /// Pull the evaluation (from white's point of view) towards a draw in endings the side ahead
+    /// will struggle to win, e.g. a minor piece against pawns, a rook against a minor piece or
+    /// bishops on opposite colours
+    fn scale_drawish(&self, eval: i64) -> i64 {
+        eval * self.drawish_scale(eval > 0) / SCALE_NORMAL
+    }
+
+    fn drawish_scale(&self, white_ahead: bool) -> i64 {
+        let (strong, strong_material, weak_material) = match white_ahead {
+            true => (self.white, self.white_value, self.black_value),
+            false => (self.black, self.black_value, self.white_value),
+        };
+        if (self.pawns & strong) == 0 {
+            let king = self.params.material(Piece::King);
+            let strong_material = strong_material.saturating_sub(king);
+            let weak_material = weak_material.saturating_sub(king);
+            if strong_material <= self.params.material(Piece::Bishop) {
+                return SCALE_MINOR_ONLY;
+            }
+            if strong_material.saturating_sub(weak_material) < self.params.drawish_advantage {
+                return SCALE_SMALL_ADVANTAGE;
+            }
+        }
+        if self.has_opposite_bishops() && (self.knights | self.queens) == 0 {
+            return match self.rooks {
+                0 => SCALE_OPPOSITE_BISHOPS,
+                _ => SCALE_OPPOSITE_BISHOPS_WITH_ROOKS,
+            };
+        }
+        SCALE_NORMAL
+    }
+
+    /// Each side has a single bishop and they're on different coloured squares
+    fn has_opposite_bishops(&self) -> bool {
+        (self.bishops & self.white).count_ones() == 1
+            && (self.bishops & self.black).count_ones() == 1
+            && (self.bishops & LIGHT_SQUARES).count_ones() == 1
+    }
+
+    /// True if the position has occurred twice before, used by the search
+    pub fn is_repetition(&self) -> bool {
+        let matching = self
+            .earlier_positions()
+            .filter(|h| h.position_key == self.key)
+            .count();
+        matching >= 2
+    }
+
+    /// True if the same position, with the same castling rights and en passant captures, has
+    /// occurred three times with the same side to move, so either side may claim a draw
+    ///
+    /// The key covers all of those, so this is the same as is_repetition.
+    pub fn is_threefold_repetition(&self) -> bool {
+        self.is_repetition()
+    }
+
+    /// True if fifty moves by each side have been played without a capture or pawn move, unless
+    /// the last of them was checkmate
+    pub fn can_claim_fifty_move_draw(&self) -> bool {
+        self.fifty_move_rule >= 100 && !self.is_checkmate()
+    }
+
+    // Earlier positions which could be repeated by this one, most recent first. Only positions
+    // since the last capture or pawn move, with the same side to move, can match. The last entry
+    // is the position before our opponent's move so it's skipped.
+    fn earlier_positions(&self) -> impl Iterator<Item = &PlayState> {
+        self.history
+            .iter()
+            .rev()
+            .take(self.fifty_move_rule)
+            .skip(1)
+            .step_by(2)
+    }
+
+    /// The move which reached this position, None if no move has been made since it was set up
+    pub fn last_move(&self) -> Option<Play> {
+        self.history.last().map(|h| h.play)
+    }
+
+    /// The moves made since the position was set up, oldest first
+    ///
+    /// Together with the starting position this is enough to replay the game, e.g. to write out
+    /// a PGN.
+    pub fn played_moves(&self) -> impl DoubleEndedIterator<Item = Play> + ExactSizeIterator + '_ {
+        self.history.iter().map(|h| h.play)
+    }
+
+    /// Play a move after checking it's legal in this position, unlike make_move which trusts
+    /// that the move was generated here
+    pub fn make_move_checked(&mut self, play: &Play) -> Result<(), MoveError> {
+        let own = match self.active_color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        if !own.is_bit_set(play.from) {
+            return Err(MoveError::NoPiece(play.to_string()));
+        }
+        if !self.is_legal(play) {
+            return Err(MoveError::Illegal(play.to_string()));
+        }
+        self.make_legal_move(play);
+        Ok(())
+    }
+
+    pub fn make_move(&mut self, play: &Play) -> bool {
+        self.make_legal_move(play);
+        // return false if king in check
+        if self.left_in_check() {
+            self.undo_move().unwrap();
+            false
+        } else {
+            true
+        }
+    }
+
+    // True if the side which just moved left its king in check
+    fn left_in_check(&self) -> bool {
+        let own = match self.active_color {
+            Color::White => self.black,
+            Color::Black => self.white,
+        };
+        let king_index = (self.kings & own).trailing_zeros() as u8;
+        self.square_attacked(king_index, self.active_color)
+    }
+
+    // Make a move which is known to be legal, e.g. from generate_legal_moves, without make_move's
+    // check that it doesn't leave the king in check
+    fn make_legal_move(&mut self, play: &Play) {
+        self.history.push(PlayState {
+            play: *play,
+            en_passant: self.en_passant,
+            castle: self.castle,
+            fifty_move_rule: self.fifty_move_rule,
+            position_key: self.key,
+        });
+
+        let opposing_color = !self.active_color;
+        // update castling permissions, they're lost when the king or rook moves or the rook is
+        // captured
+        let castle = self.castle.bits();
+        self.castle
+            .retain(self.castle_masks[play.from as usize] & self.castle_masks[play.to as usize]);
+        self.key ^= ZORB.castle_key(castle ^ self.castle.bits());
+        if let Some(square) = self.en_passant {
+            if self.can_capture_en_passant(square.as_index(), self.active_color) {
+                self.key ^= ZORB.en_passant_key(square.as_index());
+            }
+        }
+        self.en_passant = None;
+        self.fifty_move_rule += 1;
 
         if self.pawns.is_bit_set(play.from) {
             // pawn moves reset the fifty move rule
             self.fifty_move_rule = 0;
             if (play.from as isize - play.to as isize).abs() == 16 {
                 // if a pawn moved two squares forward then we must update the en_passant square
-                self.en_passant = match self.active_color {
-                    Color::White => Some(Coordinate::from_index(play.to - 8)),
-                    Color::Black => Some(Coordinate::from_index(play.to + 8)),
+                let square = match self.active_color {
+                    Color::White => play.to - 8,
+                    Color::Black => play.to + 8,
                 };
-                self.key ^= ZORB.en_passant_key(play.to);
+                self.en_passant = Some(Coordinate::from_index(square));
+                if self.can_capture_en_passant(square, opposing_color) {
+                    self.key ^= ZORB.en_passant_key(square);
+                }
             }
             if play.en_passant {
                 let clear_index = match self.active_color {
@@ -662,26 +1697,25 @@ impl Board {
                 self.clear_piece_index(play.to, capture, opposing_color);
             }
         }
-        let from_piece = self
-            .get_piece_index(play.from)
-            .expect("The from square must always be occupied");
-        self.move_piece(
-            play.from,
-            play.to,
-            from_piece,
-            play.promote,
-            self.active_color,
-        );
-
         if play.castle {
-            // move rook if castling
-            match play.to {
-                C1 => self.move_piece(A1, D1, Piece::Rook, None, self.active_color),
-                C8 => self.move_piece(A8, D8, Piece::Rook, None, self.active_color),
-                G1 => self.move_piece(H1, F1, Piece::Rook, None, self.active_color),
-                G8 => self.move_piece(H8, F8, Piece::Rook, None, self.active_color),
-                _ => unreachable!(),
-            }
+            // In Chess960 the king or rook can end up where the other started, so both are lifted
+            // before either is put down
+            let (rook_from, rook_to) = self.castle_rook_squares(play.to);
+            self.clear_piece_index(play.from, Piece::King, self.active_color);
+            self.clear_piece_index(rook_from, Piece::Rook, self.active_color);
+            self.set_piece_index(play.to, Piece::King, self.active_color);
+            self.set_piece_index(rook_to, Piece::Rook, self.active_color);
+        } else {
+            let from_piece = self
+                .get_piece_index(play.from)
+                .expect("The from square must always be occupied");
+            self.move_piece(
+                play.from,
+                play.to,
+                from_piece,
+                play.promote,
+                self.active_color,
+            );
         }
 
         // update the ply
@@ -692,36 +1726,173 @@ impl Board {
             self.move_number += 1;
         }
 
-        // return false if king in check
-        let king_index = match self.active_color {
-            Color::White => (self.kings & self.white).get_set_bits()[0],
-            Color::Black => (self.kings & self.black).get_set_bits()[0],
-        };
         self.active_color = opposing_color;
         self.key ^= ZORB.side;
-        return if self.square_attacked(king_index as u8, opposing_color) {
-            self.undo_move().unwrap();
-            false
-        } else {
-            true
+        debug_assert_eq!(self.key, self.compute_key_from_scratch(), "after {}", play);
+    }
+
+    // Set the castling permissions and rooks from a FEN token. KQkq (X-FEN) refers to the
+    // outermost rook on that side of the king, Shredder-FEN letters such as HAha give the file of
+    // the rook for Chess960 positions with more than one rook on a side.
+    fn set_castling(&mut self, token: &str) -> Result<(), FenError> {
+        let valid = |c: char| matches!(c.to_ascii_lowercase(), 'k' | 'q' | 'a'..='h');
+        if token != "-" && !token.chars().all(valid) {
+            return Err(FenError::InvalidField(
+                FenField::Castling,
+                token.to_string(),
+            ));
+        }
+        let mut permissions = String::new();
+        for c in token.chars().filter(|&c| c != '-') {
+            let color = match c.is_ascii_uppercase() {
+                true => Color::White,
+                false => Color::Black,
+            };
+            let king = self
+                .castling_king(color)
+                .ok_or(FenError::NoCastlingPieces(c))?;
+            let rook = match c.to_ascii_lowercase() {
+                'k' => self.outermost_rook(color, true),
+                'q' => self.outermost_rook(color, false),
+                file => {
+                    let rook = (king & !7) + (file as u8 - b'a');
+                    Some(rook).filter(|&r| self.castling_rooks(color).is_bit_set(r))
+                }
+            }
+            .ok_or(FenError::NoCastlingPieces(c))?;
+            let king_side = rook > king;
+            self.castle_rooks[castle_index(color, king_side)] = rook;
+            permissions.push(match (color, king_side) {
+                (Color::White, true) => 'K',
+                (Color::White, false) => 'Q',
+                (Color::Black, true) => 'k',
+                (Color::Black, false) => 'q',
+            });
+        }
+        self.castle = CastlePermissions::from_fen(&permissions)?;
+        self.update_castle_masks();
+        Ok(())
+    }
+
+    // Set castle_masks from the castling rights and rooks, the king must still be on its starting
+    // square for any right to remain
+    fn update_castle_masks(&mut self) {
+        self.castle_masks = [CastlePermissions::ALL; 64];
+        for (color, king_side, rook) in self.castling_rights().collect::<Vec<_>>() {
+            let flag = CastlePermissions::flag(color, king_side);
+            self.castle_masks[rook as usize] &= !flag;
+            if let Some(king) = self.castling_king(color) {
+                self.castle_masks[king as usize] &= !flag;
+            }
+        }
+    }
+
+    /// The castling permissions in FEN
+    ///
+    /// Shredder-FEN file letters are used if any castling rook isn't the outermost rook on its
+    /// side of the king, KQkq can't tell which rook was meant then.
+    pub fn castle_fen(&self) -> String {
+        let outermost = self
+            .castling_rights()
+            .all(|(color, king_side, rook)| self.outermost_rook(color, king_side) == Some(rook));
+        match outermost {
+            true => self.castle.as_fen(),
+            false => self.shredder_castle_fen(),
+        }
+    }
+
+    /// The castling permissions as Shredder-FEN file letters, e.g. HAha for the starting position
+    ///
+    /// Some Chess960 GUIs and databases expect file letters even when KQkq would do.
+    pub fn shredder_castle_fen(&self) -> String {
+        let letters: String = self
+            .castling_rights()
+            .map(|(color, _, rook)| {
+                let file = (b'a' + rook % 8) as char;
+                match color {
+                    Color::White => file.to_ascii_uppercase(),
+                    Color::Black => file,
+                }
+            })
+            .collect();
+        match letters.is_empty() {
+            true => String::from("-"),
+            false => letters,
+        }
+    }
+
+    // The side and rook of each castle still allowed, in KQkq order
+    fn castling_rights(&self) -> impl Iterator<Item = (Color, bool, u8)> + '_ {
+        [Color::White, Color::Black]
+            .into_iter()
+            .flat_map(|color| [(color, true), (color, false)])
+            .filter(|&(color, king_side)| self.castle.allowed(color, king_side))
+            .map(|(color, king_side)| {
+                let rook = self.castle_rooks[castle_index(color, king_side)];
+                (color, king_side, rook)
+            })
+    }
+
+    // The king of `color` if it's on its back rank
+    fn castling_king(&self, color: Color) -> Option<u8> {
+        let (own, back_rank) = match color {
+            Color::White => (self.white, 0xFFu64),
+            Color::Black => (self.black, 0xFFu64 << 56),
         };
+        match self.kings & own & back_rank {
+            0 => None,
+            k => Some(k.trailing_zeros() as u8),
+        }
     }
 
-    pub fn undo_move(&mut self) -> Result<(), &str> {
-        let history = self.history[self.ply - 1].unwrap();
-        self.history[self.ply - 1] = None;
+    // The rooks of `color` on its back rank
+    fn castling_rooks(&self, color: Color) -> u64 {
+        let (own, back_rank) = match color {
+            Color::White => (self.white, 0xFFu64),
+            Color::Black => (self.black, 0xFFu64 << 56),
+        };
+        self.rooks & own & back_rank
+    }
+
+    // The back rank rook furthest from the king on one side of it
+    fn outermost_rook(&self, color: Color, king_side: bool) -> Option<u8> {
+        let king = self.castling_king(color)?;
+        let rooks = self.castling_rooks(color);
+        let beyond_king = match king_side {
+            true => rooks & !((2u64 << king) - 1),
+            false => rooks & ((1u64 << king) - 1),
+        };
+        match (beyond_king, king_side) {
+            (0, _) => None,
+            (r, true) => Some(63 - r.leading_zeros() as u8),
+            (r, false) => Some(r.trailing_zeros() as u8),
+        }
+    }
+
+    // True if a pawn of `color` attacks the en passant square. Only then is the square part of
+    // the key, so positions where the capture isn't possible transpose with each other
+    pub(crate) fn can_capture_en_passant(&self, square: u8, color: Color) -> bool {
+        let own = match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        let attackers = pawn_attacks(square, !color);
+        (self.pawns & own & attackers) != 0
+    }
+
+    /// Take back the last move played, returning it
+    pub fn undo_move(&mut self) -> Result<Play, NoMoveToUndo> {
+        let history = self.history.pop().ok_or(NoMoveToUndo)?;
         let play = history.play;
 
         let opposing_color = !self.active_color;
-        if self.en_passant.is_some() {
-            self.key ^= ZORB.en_passant_key(play.to);
-        }
         // update castling permissions
         self.castle = history.castle;
         self.en_passant = history.en_passant;
         self.fifty_move_rule = history.fifty_move_rule;
         self.ply -= 1;
-        self.line_ply -= 1;
+        // Moves from before the search root can be taken back, e.g. by a front-end
+        self.line_ply = self.line_ply.saturating_sub(1);
         if matches!(opposing_color, Color::Black) {
             self.move_number -= 1;
         }
@@ -738,15 +1909,23 @@ impl Board {
         }
 
         // move piece
-        let from_piece = self
-            .get_piece_index(play.to)
-            .expect("The to square must always be occupied when undoing");
-        if let Some(promote) = play.promote {
-            self.clear_piece_index(play.to, (&promote).into(), opposing_color);
-            self.set_piece_index(play.from, Piece::Pawn, opposing_color);
+        if play.castle {
+            let (rook_from, rook_to) = self.castle_rook_squares(play.to);
+            self.clear_piece_index(play.to, Piece::King, opposing_color);
+            self.clear_piece_index(rook_to, Piece::Rook, opposing_color);
+            self.set_piece_index(play.from, Piece::King, opposing_color);
+            self.set_piece_index(rook_from, Piece::Rook, opposing_color);
         } else {
-            self.clear_piece_index(play.to, from_piece, opposing_color);
-            self.set_piece_index(play.from, from_piece, opposing_color);
+            let from_piece = self
+                .get_piece_index(play.to)
+                .expect("The to square must always be occupied when undoing");
+            if let Some(promote) = play.promote {
+                self.clear_piece_index(play.to, (&promote).into(), opposing_color);
+                self.set_piece_index(play.from, Piece::Pawn, opposing_color);
+            } else {
+                self.clear_piece_index(play.to, from_piece, opposing_color);
+                self.set_piece_index(play.from, from_piece, opposing_color);
+            }
         }
 
         if let Some(capture) = play.capture {
@@ -754,20 +1933,17 @@ impl Board {
                 self.set_piece_index(play.to, capture, self.active_color);
             }
         }
-        if play.castle {
-            // move rook if castling
-            match play.to {
-                C1 => self.move_piece(D1, A1, Piece::Rook, None, opposing_color),
-                C8 => self.move_piece(D8, A8, Piece::Rook, None, opposing_color),
-                G1 => self.move_piece(F1, H1, Piece::Rook, None, opposing_color),
-                G8 => self.move_piece(F8, H8, Piece::Rook, None, opposing_color),
-                _ => unreachable!(),
-            }
-        }
 
         self.active_color = opposing_color;
-        self.key ^= ZORB.side;
-        Ok(())
+        // Restoring the key also undoes the en passant and side to move keys
+        self.key = history.position_key;
+        debug_assert_eq!(
+            self.key,
+            self.compute_key_from_scratch(),
+            "undoing {}",
+            play
+        );
+        Ok(play)
     }
 
     #[inline]
@@ -789,6 +1965,116 @@ impl Board {
         }
     }
 
+    /// True if the side to move is in check
+    pub fn in_check(&self) -> bool {
+        self.is_king_attacked()
+    }
+
+    /// True if the side to move is in check and has no legal moves
+    pub fn is_checkmate(&self) -> bool {
+        self.in_check() && self.generate_legal_moves().is_empty()
+    }
+
+    /// True if the side to move isn't in check but has no legal moves
+    pub fn is_stalemate(&self) -> bool {
+        !self.in_check() && self.generate_legal_moves().is_empty()
+    }
+
+    /// Like from_fen, but the position must also pass validate
+    ///
+    /// from_fen sets up any position with eight ranks of eight files, as GUIs send positions
+    /// without kings or with pawns on the back rank when setting up boards.
+    pub fn from_fen_strict(fen: &str) -> Result<Board, FenError> {
+        let board = Board::from_fen(fen)?;
+        board.validate().map_err(FenError::InvalidPosition)?;
+        Ok(board)
+    }
+
+    /// Check the position could arise in a game, returning every problem found
+    pub fn validate(&self) -> Result<(), Vec<PositionError>> {
+        let mut errors = Vec::new();
+        for (color, pieces) in [(Color::White, self.white), (Color::Black, self.black)] {
+            let kings = (self.kings & pieces).count_ones();
+            if kings != 1 {
+                errors.push(PositionError::KingCount(color, kings));
+            }
+            if pieces.count_ones() > 16 || (self.pawns & pieces).count_ones() > 8 {
+                errors.push(PositionError::TooManyPieces(color));
+            }
+        }
+        let back_ranks = 0xFF00_0000_0000_00FFu64;
+        for square in (self.pawns & back_ranks).get_set_bits() {
+            errors.push(PositionError::PawnOnBackRank(square));
+        }
+
+        if let Some(c) = self.en_passant {
+            // The pawn moved from behind the square to the one in front of it
+            let square = c.as_index();
+            let (pawn, behind, theirs) = match self.active_color {
+                Color::White => (square.wrapping_sub(8), square + 8, self.black),
+                Color::Black => (square + 8, square.wrapping_sub(8), self.white),
+            };
+            let occupied = self.white | self.black;
+            if rank_of(square).relative(self.active_color) != Rank::Six
+                || !(self.pawns & theirs).is_bit_set(pawn)
+                || occupied.is_bit_set(square)
+                || occupied.is_bit_set(behind)
+            {
+                errors.push(PositionError::EnPassant(square));
+            }
+        }
+
+        // In Chess960 both sides start with their kings and rooks on the same files
+        let kings = [Color::White, Color::Black].map(|c| self.castling_king(c).map(|k| k % 8));
+        let can_castle = |c| self.castle.allowed(c, true) || self.castle.allowed(c, false);
+        let mut castling_matches =
+            !can_castle(Color::White) || !can_castle(Color::Black) || kings[0] == kings[1];
+        for king_side in [true, false] {
+            if self.castle.allowed(Color::White, king_side)
+                && self.castle.allowed(Color::Black, king_side)
+            {
+                let white_rook = self.castle_rooks[castle_index(Color::White, king_side)];
+                let black_rook = self.castle_rooks[castle_index(Color::Black, king_side)];
+                castling_matches &= white_rook % 8 == black_rook % 8;
+            }
+        }
+        if !castling_matches {
+            errors.push(PositionError::Castling);
+        }
+
+        // Attacks on a missing king can't be checked
+        if (self.kings & self.white).count_ones() == 1
+            && (self.kings & self.black).count_ones() == 1
+            && self.left_in_check()
+        {
+            errors.push(PositionError::OpponentInCheck);
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    /// Whether the game has ended, checkmate and stalemate take precedence over the draw rules
+    pub fn game_state(&self) -> GameState {
+        if self.generate_legal_moves().is_empty() {
+            return match self.in_check() {
+                true => GameState::Checkmate(!self.active_color),
+                false => GameState::Stalemate,
+            };
+        }
+        if self.is_insufficient_material() {
+            GameState::DrawByInsufficientMaterial
+        } else if self.can_claim_fifty_move_draw() {
+            GameState::DrawByFiftyMove
+        } else if self.is_threefold_repetition() {
+            GameState::DrawByRepetition
+        } else {
+            GameState::Ongoing
+        }
+    }
+
     pub fn is_king_attacked(&self) -> bool {
         let (index, opposing_color) = match self.active_color {
             Color::White => ((self.kings & self.white).get_set_bits(), Color::Black),
@@ -800,11 +2086,11 @@ impl Board {
     pub fn attacked_print(&self, color: Color) {
         println!("   a|b|c|d|e|f|g|h|");
         println!("  ----------------");
-        for rank in (1..=8).rev() {
+        for rank in Rank::VARIANTS.into_iter().rev() {
             print!("{} |", rank);
             for file in File::VARIANTS {
                 let index = coordinate_to_index(rank, file);
-                if self.square_attacked(index as u8, color) {
+                if self.square_attacked(index, color) {
                     print!("x|");
                 } else {
                     print!(".|");
@@ -819,6 +2105,9 @@ impl Board {
         debug_assert!(!self.black.is_bit_set(index));
         debug_assert!(!self.white.is_bit_set(index));
         self.key ^= ZORB.get_piece_key(index, piece, color);
+        self.phase_weight += piece.phase_weight();
+        self.material_key += 1 << material_shift(piece, color);
+        self.squares[index as usize] = Some((piece, color));
         match piece {
             Piece::Pawn => self.pawns.set_bit(index),
             Piece::Knight => self.knights.set_bit(index),
@@ -830,129 +2119,295 @@ impl Board {
         match color {
             Color::Black => {
                 self.black.set_bit(index);
-                self.black_value += piece.material_value();
+                self.black_value += self.params.material(piece);
+                self.black_pst += self.params.get_value(index as usize, piece, color);
             }
             Color::White => {
                 self.white.set_bit(index);
-                self.white_value += piece.material_value();
+                self.white_value += self.params.material(piece);
+                self.white_pst += self.params.get_value(index as usize, piece, color);
             }
         };
     }
 
-    fn set_piece(&mut self, piece: Piece, color: Color, rank: u8, file: File) {
+    fn set_piece(&mut self, piece: Piece, color: Color, rank: Rank, file: File) {
         let index = coordinate_to_index(rank, file);
         self.set_piece_index(index, piece, color);
     }
 
-    fn clear_piece_index(&mut self, index: u8, piece: Piece, color: Color) {
-        debug_assert!((self.black | self.white).is_bit_set(index));
-        self.key ^= ZORB.get_piece_key(index, piece, color);
-        match piece {
-            Piece::Pawn => self.pawns.clear_bit(index),
-            Piece::Knight => self.knights.clear_bit(index),
-            Piece::Bishop => self.bishops.clear_bit(index),
-            Piece::Rook => self.rooks.clear_bit(index),
-            Piece::Queen => self.queens.clear_bit(index),
-            Piece::King => self.kings.clear_bit(index),
-        };
-        match color {
-            Color::Black => {
-                self.black.clear_bit(index);
-                self.black_value -= piece.material_value();
-            }
-            Color::White => {
-                self.white.clear_bit(index);
-                self.white_value -= piece.material_value();
-            }
+    fn clear_piece_index(&mut self, index: u8, piece: Piece, color: Color) {
+        debug_assert!((self.black | self.white).is_bit_set(index));
+        self.key ^= ZORB.get_piece_key(index, piece, color);
+        self.phase_weight -= piece.phase_weight();
+        self.material_key -= 1 << material_shift(piece, color);
+        self.squares[index as usize] = None;
+        match piece {
+            Piece::Pawn => self.pawns.clear_bit(index),
+            Piece::Knight => self.knights.clear_bit(index),
+            Piece::Bishop => self.bishops.clear_bit(index),
+            Piece::Rook => self.rooks.clear_bit(index),
+            Piece::Queen => self.queens.clear_bit(index),
+            Piece::King => self.kings.clear_bit(index),
+        };
+        match color {
+            Color::Black => {
+                self.black.clear_bit(index);
+                self.black_value -= self.params.material(piece);
+                self.black_pst -= self.params.get_value(index as usize, piece, color);
+            }
+            Color::White => {
+                self.white.clear_bit(index);
+                self.white_value -= self.params.material(piece);
+                self.white_pst -= self.params.get_value(index as usize, piece, color);
+            }
+        };
+    }
+
+    pub fn get_piece_index(&self, index: u8) -> Option<Piece> {
+        self.squares[index as usize].map(|(piece, _)| piece)
+    }
+
+    pub fn get_piece_and_color_index(&self, index: u8) -> Option<(Piece, Color)> {
+        self.squares[index as usize]
+    }
+
+    /// The key of the position worked out from the pieces, side to move, castling permissions and
+    /// en passant square
+    ///
+    /// The key is updated as moves are made and taken back, this is the slow reference it must
+    /// always equal. The en passant square only counts when a pawn can capture there, so positions
+    /// which only differ by an unusable en passant square are repetitions.
+    pub fn compute_key_from_scratch(&self) -> u64 {
+        let mut key = EMPTY_KEY;
+        for (index, piece, color) in self.pieces() {
+            key ^= ZORB.get_piece_key(index, piece, color);
+        }
+        if matches!(self.active_color, Color::Black) {
+            key ^= ZORB.side;
+        }
+        key ^= ZORB.castle_key(self.castle.bits());
+        if let Some(square) = self.en_passant {
+            if self.can_capture_en_passant(square.as_index(), self.active_color) {
+                key ^= ZORB.en_passant_key(square.as_index());
+            }
+        }
+        key
+    }
+
+    /// The key of the position after a pseudo-legal move, without making it
+    ///
+    /// This lets the search start loading the transposition table entry for the move while it
+    /// does other work.
+    pub fn key_after(&self, play: &Play) -> u64 {
+        let color = self.active_color;
+        let mut key = self.key ^ ZORB.side;
+        let castle = self.castle_masks[play.from as usize] & self.castle_masks[play.to as usize];
+        key ^= ZORB.castle_key(self.castle.bits() & !castle);
+        if let Some(square) = self.en_passant {
+            if self.can_capture_en_passant(square.as_index(), color) {
+                key ^= ZORB.en_passant_key(square.as_index());
+            }
+        }
+        let piece = match self.get_piece_index(play.from) {
+            Some(piece) => piece,
+            None => return key,
+        };
+        if play.castle {
+            let (rook_from, rook_to) = self.castle_rook_squares(play.to);
+            return key
+                ^ ZORB.get_piece_key(play.from, Piece::King, color)
+                ^ ZORB.get_piece_key(play.to, Piece::King, color)
+                ^ ZORB.get_piece_key(rook_from, Piece::Rook, color)
+                ^ ZORB.get_piece_key(rook_to, Piece::Rook, color);
+        }
+        if let Some(capture) = play.capture {
+            let square = match (play.en_passant, color) {
+                (false, _) => play.to,
+                (true, Color::White) => play.to - 8,
+                (true, Color::Black) => play.to + 8,
+            };
+            key ^= ZORB.get_piece_key(square, capture, !color);
+        }
+        let to_piece = play.promote.as_ref().map_or(piece, Piece::from);
+        key ^= ZORB.get_piece_key(play.from, piece, color);
+        key ^= ZORB.get_piece_key(play.to, to_piece, color);
+        if piece == Piece::Pawn && play.from.abs_diff(play.to) == 16 {
+            let square = (play.from + play.to) / 2;
+            if self.can_capture_en_passant(square, !color) {
+                key ^= ZORB.en_passant_key(square);
+            }
+        }
+        key
+    }
+
+    /// The square, piece and color of every piece on the board, from a1 to h8
+    pub fn pieces(&self) -> impl Iterator<Item = (u8, Piece, Color)> + '_ {
+        self.squares
+            .iter()
+            .enumerate()
+            .filter_map(|(index, square)| square.map(|(piece, color)| (index as u8, piece, color)))
+    }
+
+    /// Key made of the number of each side's pieces of each type, without a popcount
+    ///
+    /// Positions with the same material have the same key, whatever the squares, so it can be
+    /// used to recognise endgames such as KRK or KBNK.
+    pub fn material_key(&self) -> u64 {
+        self.material_key
+    }
+
+    /// Number of `color`'s pieces of type `piece`, read from the material key
+    pub fn piece_count(&self, piece: Piece, color: Color) -> u32 {
+        ((self.material_key >> material_shift(piece, color)) & 0xF) as u32
+    }
+
+    /// Bitboard of the squares holding `color`'s pieces of type `piece`
+    pub fn piece_bitboard(&self, piece: Piece, color: Color) -> u64 {
+        let pieces = match piece {
+            Piece::Pawn => self.pawns,
+            Piece::Knight => self.knights,
+            Piece::Bishop => self.bishops,
+            Piece::Rook => self.rooks,
+            Piece::Queen => self.queens,
+            Piece::King => self.kings,
         };
+        pieces & self.occupancy(color)
     }
 
-    pub fn get_piece_index(&self, index: u8) -> Option<Piece> {
-        // TODO this should also return color
-        let mask = 1u64 << index;
-        if (self.pawns & mask) > 0 {
-            Some(Piece::Pawn)
-        } else if (self.knights & mask) > 0 {
-            Some(Piece::Knight)
-        } else if (self.bishops & mask) > 0 {
-            Some(Piece::Bishop)
-        } else if (self.rooks & mask) > 0 {
-            Some(Piece::Rook)
-        } else if (self.queens & mask) > 0 {
-            Some(Piece::Queen)
-        } else if (self.kings & mask) > 0 {
-            Some(Piece::King)
-        } else {
-            None
+    /// Bitboard of the squares holding any of `color`'s pieces
+    pub fn occupancy(&self, color: Color) -> u64 {
+        match color {
+            Color::White => self.white,
+            Color::Black => self.black,
         }
     }
 
-    pub fn get_piece_and_color_index(&self, index: u8) -> Option<(Piece, Color)> {
-        let mask = 1u64 << index;
-        let piece = if (self.pawns & mask) > 0 {
-            Piece::Pawn
-        } else if (self.knights & mask) > 0 {
-            Piece::Knight
-        } else if (self.bishops & mask) > 0 {
-            Piece::Bishop
-        } else if (self.rooks & mask) > 0 {
-            Piece::Rook
-        } else if (self.queens & mask) > 0 {
-            Piece::Queen
-        } else if (self.kings & mask) > 0 {
-            Piece::King
-        } else {
-            return None;
-        };
-        let color = if (self.black & mask) > 0 {
-            Color::Black
-        } else if (self.white & mask) > 0 {
-            Color::White
-        } else {
-            return None;
-        };
-        Some((piece, color))
+    /// The square a pawn can be captured en passant on, set after any double pawn move even if
+    /// no pawn can make the capture
+    pub fn en_passant(&self) -> Option<u8> {
+        self.en_passant.map(|square| square.as_index())
     }
 
-    fn get_piece(&self, rank: u8, file: File) -> (Option<Piece>, Option<Color>) {
-        let index = coordinate_to_index(rank, file);
-        let mask = 1u64 << index;
-        let color = if (self.black & mask) > 0 {
-            Some(Color::Black)
-        } else if (self.white & mask) > 0 {
-            Some(Color::White)
-        } else {
-            None
+    /// Win, draw or loss for the side to move from the Syzygy tables, see init_tablebases
+    ///
+    /// None if the position has castling rights or more pieces than the loaded tables cover.
+    pub fn probe_tb_wdl(&self) -> Option<Wdl> {
+        syzygy::probe_wdl(self)
+    }
+
+    /// Like probe_tb_wdl, with the distance to zeroing and taking the fifty move rule into account
+    pub fn probe_tb_dtz(&self) -> Option<Dtz> {
+        syzygy::probe_dtz(self)
+    }
+
+    pub fn castle_permissions(&self) -> CastlePermissions {
+        self.castle
+    }
+
+    /// The full move number, starting at 1 and increased after each black move
+    pub fn move_number(&self) -> usize {
+        self.move_number
+    }
+
+    /// Moves since the last capture or pawn move, for the fifty move rule
+    pub fn halfmove_clock(&self) -> usize {
+        self.fifty_move_rule
+    }
+
+    /// The position flipped vertically with the colors swapped, so the evaluation and the moves
+    /// should be the same for the new side to move. The move history isn't kept.
+    pub fn flip(&self) -> Board {
+        let mut board = self.transform(|square| square ^ 56, true);
+        board.castle = self.castle.swap_colors();
+        board.castle_rooks = [2, 3, 0, 1].map(|i| self.castle_rooks[i] ^ 56);
+        board.update_castle_masks();
+        board.key = board.compute_key_from_scratch();
+        board
+    }
+
+    /// The position mirrored horizontally, the a-file swapped with the h-file. The move history
+    /// isn't kept.
+    ///
+    /// Castling rights are dropped, castling still takes the king to the c- or g-file so it
+    /// isn't the mirror of castling in this position.
+    pub fn mirror(&self) -> Board {
+        let mut board = self.transform(|square| square ^ 7, false);
+        board.castle = CastlePermissions::from_bits(0);
+        board.castle_rooks = [H1, A1, H8, A8];
+        board.update_castle_masks();
+        board.key = board.compute_key_from_scratch();
+        board
+    }
+
+    // Move every piece to a new square, as if the position was loaded from a FEN. The caller sets
+    // the castling permissions and then the key.
+    fn transform(&self, square: impl Fn(u8) -> u8, swap_colors: bool) -> Board {
+        let swap = |color: Color| match swap_colors {
+            true => !color,
+            false => color,
         };
-        let piece = self.get_piece_index(index);
-        (piece, color)
+        let mut board = self.clone();
+        for (index, piece, color) in self.pieces() {
+            board.clear_piece_index(index, piece, color);
+        }
+        for (index, piece, color) in self.pieces() {
+            board.set_piece_index(square(index), piece, swap(color));
+        }
+
+        board.active_color = swap(self.active_color);
+        board.en_passant = self
+            .en_passant
+            .map(|c| Coordinate::from_index(square(c.as_index())));
+        board.ply = board.move_number * 2;
+        if matches!(board.active_color, Color::Black) {
+            board.ply += 1;
+        }
+        board.line_ply = 0;
+        board.history.clear();
+        board
+    }
+
+    fn get_piece(&self, rank: Rank, file: File) -> (Option<Piece>, Option<Color>) {
+        let square = self.squares[coordinate_to_index(rank, file) as usize];
+        (
+            square.map(|(piece, _)| piece),
+            square.map(|(_, color)| color),
+        )
     }
 
     fn material_value(&self) -> (u32, u32) {
         let mut black_value = 0;
         let mut white_value = 0;
 
-        white_value += (self.pawns & self.white).count_ones() * Piece::Pawn.material_value();
-        black_value += (self.pawns & self.black).count_ones() * Piece::Pawn.material_value();
+        white_value += (self.pawns & self.white).count_ones() * self.params.material(Piece::Pawn);
+        black_value += (self.pawns & self.black).count_ones() * self.params.material(Piece::Pawn);
 
-        white_value += (self.knights & self.white).count_ones() * Piece::Knight.material_value();
-        black_value += (self.knights & self.black).count_ones() * Piece::Knight.material_value();
+        white_value +=
+            (self.knights & self.white).count_ones() * self.params.material(Piece::Knight);
+        black_value +=
+            (self.knights & self.black).count_ones() * self.params.material(Piece::Knight);
 
-        white_value += (self.bishops & self.white).count_ones() * Piece::Bishop.material_value();
-        black_value += (self.bishops & self.black).count_ones() * Piece::Bishop.material_value();
+        white_value +=
+            (self.bishops & self.white).count_ones() * self.params.material(Piece::Bishop);
+        black_value +=
+            (self.bishops & self.black).count_ones() * self.params.material(Piece::Bishop);
 
-        white_value += (self.rooks & self.white).count_ones() * Piece::Rook.material_value();
-        black_value += (self.rooks & self.black).count_ones() * Piece::Rook.material_value();
+        white_value += (self.rooks & self.white).count_ones() * self.params.material(Piece::Rook);
+        black_value += (self.rooks & self.black).count_ones() * self.params.material(Piece::Rook);
 
-        white_value += (self.queens & self.white).count_ones() * Piece::Queen.material_value();
-        black_value += (self.queens & self.black).count_ones() * Piece::Queen.material_value();
+        white_value += (self.queens & self.white).count_ones() * self.params.material(Piece::Queen);
+        black_value += (self.queens & self.black).count_ones() * self.params.material(Piece::Queen);
 
-        white_value += (self.kings & self.white).count_ones() * Piece::King.material_value();
-        black_value += (self.kings & self.black).count_ones() * Piece::King.material_value();
+        white_value += (self.kings & self.white).count_ones() * self.params.material(Piece::King);
+        black_value += (self.kings & self.black).count_ones() * self.params.material(Piece::King);
 
         (white_value, black_value)
     }
 
+    /// Count the leaf nodes of the move tree to `depth`
+    ///
+    /// Only legal moves are generated, so the last ply is counted without making the moves and
+    /// the moves which are made skip make_move's check for leaving the king in check.
     pub fn perft(&mut self, depth: u8) -> u64 {
         // Based on psedocode at https://www.chessprogramming.org/Perft
         let mut nodes = 0;
@@ -961,180 +2416,365 @@ impl Board {
             return 1;
         }
 
-        for m in &self.generate_moves() {
-            let mut branch = 0;
-            if self.make_move(m) {
-                branch = self.perft(depth - 1);
-                nodes += branch;
-                //println!("{}", m);
+        let mut moves = MoveList::new();
+        self.generate_legal_moves_into(&mut moves);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        for m in &moves {
+            self.make_legal_move(m);
+            debug_assert!(!self.left_in_check(), "generated an illegal move {}", m);
+            nodes += self.perft(depth - 1);
+            self.undo_move().unwrap();
+        }
+        nodes
+    }
+
+    /// The perft count to `depth` below each legal move, which helps find where a wrong total
+    /// comes from
+    pub fn perft_divide(&mut self, depth: u8) -> Vec<(Play, u64)> {
+        if depth == 0 {
+            return Vec::new();
+        }
+        self.generate_legal_moves()
+            .into_iter()
+            .map(|m| {
+                self.make_legal_move(&m);
+                let nodes = self.perft(depth - 1);
                 self.undo_move().unwrap();
+                (m, nodes)
+            })
+            .collect()
+    }
+
+    /// Like perft, but subtree counts are kept in a table of `entries` entries so transpositions
+    /// are only counted once
+    ///
+    /// Each count is stored under the position's key, so a key which doesn't identify its
+    /// position shows up as a wrong total.
+    pub fn perft_hashed(&mut self, depth: u8, entries: usize) -> u64 {
+        let mut table = vec![None; entries.max(1)];
+        self.perft_with_table(depth, &mut table)
+    }
+
+    fn perft_with_table(&mut self, depth: u8, table: &mut [Option<PerftEntry>]) -> u64 {
+        if depth <= 1 {
+            return self.perft(depth);
+        }
+        let index = (self.key % table.len() as u64) as usize;
+        if let Some(entry) = table[index] {
+            if entry.key == self.key && entry.depth == depth {
+                return entry.nodes;
             }
-            // TODO remove this debug
-            //if depth == 2 {
-            //    println!("m {} => {}", m, branch); // perft divide
-            //};
         }
+
+        let mut nodes = 0;
+        let mut moves = MoveList::new();
+        self.generate_legal_moves_into(&mut moves);
+        for m in &moves {
+            self.make_legal_move(m);
+            nodes += self.perft_with_table(depth - 1, table);
+            self.undo_move().unwrap();
+        }
+        table[index] = Some(PerftEntry {
+            key: self.key,
+            depth,
+            nodes,
+        });
         nodes
     }
 }
 
 impl Game for Board {
-    fn from_fen(fen: &str) -> Result<Self, String> {
-        let mut fen_iter = fen.split(' ');
-        let position = fen_iter
-            .next()
-            .ok_or("Error parsing FEN: could not find position block")?;
-        let active_color_token = match fen_iter.next() {
-            Some(c) => {
-                if c.len() == 1 {
-                    c.chars().next().ok_or("Expected a single character token")
-                } else {
-                    Err("Expected a single character token")
-                }
+    type Move = Play;
+
+    fn from_fen(fen: &str) -> Result<Self, FenError> {
+        Board::from_fen(fen)
+    }
+
+    fn key(&self) -> u64 {
+        self.key
+    }
+
+    // The keys come from a seeded generator, which could change with the rand version
+    fn key_check() -> u64 {
+        Board::new().key
+    }
+
+    fn polyglot_key(&self) -> Option<u64> {
+        Some(polyglot_key(self))
+    }
+
+    fn encode_move(play: &Play) -> Option<u32> {
+        Some(play.to_bits())
+    }
+
+    fn decode_move(bits: u32) -> Option<Play> {
+        Play::from_bits(bits)
+    }
+
+    fn set_eval_params(&mut self, params: Arc<EvalParams>) {
+        self.params = params;
+        (self.white_value, self.black_value) = self.material_value();
+        (self.white_pst, self.black_pst) = self.pst_value();
+    }
+
+    fn active_color(&self) -> Color {
+        self.active_color
+    }
+
+    fn line_ply(&self) -> usize {
+        self.line_ply
+    }
+
+    fn reset_line_ply(&mut self) {
+        self.line_ply = 0;
+    }
+
+    fn is_draw(&self) -> bool {
+        self.fifty_move_rule >= 100 || self.is_repetition() || self.is_insufficient_material()
+    }
+
+    fn in_check(&self) -> bool {
+        Board::in_check(self)
+    }
+
+    fn generate_moves(&self) -> Vec<Play> {
+        Board::generate_moves(self)
+    }
+
+    fn generate_captures(&self) -> Vec<Play> {
+        Board::generate_captures(self)
+    }
+
+    fn generate_quiets(&self) -> Vec<Play> {
+        let mut moves = Board::generate_moves(self);
+        moves.retain(|m| m.capture.is_none());
+        moves
+    }
+
+    fn generate_promotions(&self) -> Vec<Play> {
+        Board::generate_promotions(self)
+    }
+
+    fn generate_quiet_checks(&self) -> Vec<Play> {
+        Board::generate_quiet_checks(self)
+    }
+
+    fn generate_captures_into(&self, moves: &mut Vec<Play>) {
+        moves.clear();
+        self.add_captures(moves);
+    }
+
+    fn generate_quiets_into(&self, moves: &mut Vec<Play>) {
+        moves.clear();
+        self.add_moves(moves);
+        moves.retain(|m| m.capture.is_none());
+    }
+
+    fn generate_promotions_into(&self, moves: &mut Vec<Play>) {
+        moves.clear();
+        self.add_promotions(moves);
+    }
+
+    fn generate_quiet_checks_into(&self, moves: &mut Vec<Play>) {
+        moves.clear();
+        self.add_quiet_checks(moves);
+    }
+
+    fn legal_plays(&self) -> Vec<Play> {
+        self.generate_legal_moves()
+    }
+
+    fn is_pseudo_legal(&self, play: &Play) -> bool {
+        Board::is_pseudo_legal(self, play)
+    }
+
+    fn parse_uci_move(&self, uci: &str) -> Result<Play, MoveError> {
+        Board::parse_uci_move(self, uci)
+    }
+
+    fn parse_san(&self, san: &str) -> Result<Play, MoveError> {
+        Board::parse_san(self, san)
+    }
+
+    /// A capture by a more valuable piece of a defended piece
+    fn is_losing_capture(&self, play: &Play) -> bool {
+        match (play.capture, self.get_piece_index(play.from)) {
+            (Some(victim), Some(attacker)) => {
+                play.promote.is_none()
+                    && self.params.material(attacker) > self.params.material(victim)
+                    && self.square_attacked(play.to, !self.active_color)
             }
-            None => Err("Error parsing FEN: expected active color token found none"),
-        }?;
-        let castle = fen_iter
-            .next()
-            .ok_or("Error parsing FEN: Could not find castle permissions")?;
-        let en_passant = fen_iter
-            .next()
-            .ok_or("Error parsing FEN: Could not find en passant square")?;
-        let half_move_clock = fen_iter
-            .next()
-            .ok_or("Error parsing FEN: Could not find half move clock")?;
-        let full_move_clock = fen_iter
-            .next()
-            .ok_or("Error parsing FEN: Could not find full move clock")?;
+            _ => false,
+        }
+    }
 
-        let mut board = Board {
-            pawns: 0,
-            knights: 0,
-            bishops: 0,
-            rooks: 0,
-            queens: 0,
-            kings: 0,
-            white: 0,
-            black: 0,
+    fn is_quiet(&self, play: &Play) -> bool {
+        play.capture.is_none() && play.promote.is_none()
+    }
 
-            active_color: Color::from_char(active_color_token)
-                .ok_or("Failed to parse active color from token")?,
-            castle: CastlePermissions::from_fen(castle)?,
+    fn make_move(&mut self, play: &Play) -> bool {
+        Board::make_move(self, play)
+    }
 
-            ply: (full_move_clock
-                .parse::<usize>()
-                .map_err(|e| e.to_string())?)
-                * 2,
-            line_ply: 0,
-            move_number: full_move_clock
-                .parse::<usize>()
-                .map_err(|e| e.to_string())?,
-            en_passant: Coordinate::from_string(en_passant)?,
-            fifty_move_rule: half_move_clock
-                .parse::<usize>()
-                .map_err(|e| e.to_string())?,
-            white_value: 0,
-            black_value: 0,
+    fn make_move_checked(&mut self, play: &Play) -> Result<(), MoveError> {
+        Board::make_move_checked(self, play)
+    }
 
-            history: EMPTY_HISTORY,
-            key: 2340980257093, // TODO start with random number?
-        };
-        if matches!(board.active_color, Color::Black) {
-            board.ply += 1;
+    fn undo_move(&mut self) -> Result<Play, NoMoveToUndo> {
+        Board::undo_move(self)
+    }
+
+    fn pretty(&self, options: DisplayOptions) -> String {
+        Board::pretty(self, options)
+    }
+
+    fn key_after(&self, play: &Play) -> u64 {
+        Board::key_after(self, play)
+    }
+
+    fn eval(&self) -> i64 {
+        Board::eval(self)
+    }
+
+    fn order_score(&self, play: &Play) -> i64 {
+        match Game::is_quiet(self, play) {
+            true => self.piece_square_delta(play),
+            false => play.mmv_lva(self),
         }
+    }
 
-        // parse out the pieces on the board
-        let mut rank = 8;
-        let mut file = File::A;
-        for c in position.chars() {
-            if rank < 1 {
-                return Err("Too many ranks found".to_string());
-            }
-            // TODO change piece to PieceType and implement a Piece with from char and to char
-            // methods
-            let piece = match c {
-                'p' | 'P' => Some(Piece::Pawn),
-                'n' | 'N' => Some(Piece::Knight),
-                'b' | 'B' => Some(Piece::Bishop),
-                'r' | 'R' => Some(Piece::Rook),
-                'q' | 'Q' => Some(Piece::Queen),
-                'k' | 'K' => Some(Piece::King),
-                '/' => None,
-                '1'..='8' => None,
-                _ => return Err("unexpected character in fen".to_string()),
-            };
-            if let Some(p) = piece {
-                let color = if c.is_uppercase() {
-                    Color::White
-                } else {
-                    Color::Black
-                };
-                board.set_piece(p, color, rank, file);
-            }
+    fn perft(&mut self, depth: u8) -> u64 {
+        Board::perft(self, depth)
+    }
 
-            file = match c {
-                '1'..='8' => file.add(c.to_digit(10).unwrap()),
-                'r' | 'b' | 'n' | 'k' | 'q' | 'p' => file.add(1),
-                'R' | 'B' | 'N' | 'K' | 'Q' | 'P' => file.add(1),
-                '/' => {
-                    rank -= 1;
-                    File::A
-                }
-                _ => return Err("unexpected character in fen".to_string()),
-            };
+    fn perft_divide(&mut self, depth: u8) -> Vec<(Play, u64)> {
+        Board::perft_divide(self, depth)
+    }
+}
+
+/// How Board::pretty draws the board
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DisplayOptions {
+    pub unicode: bool, // Chess symbols such as ♔ rather than letters
+    pub colors: bool,  // ANSI colours for the squares and pieces, for terminals which support them
+    pub labels: bool,  // The rank and file of each row and column
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            unicode: false,
+            colors: false,
+            labels: true,
         }
-        (board.white_value, board.black_value) = board.material_value();
-        Ok(board)
     }
 }
 
-impl fmt::Display for Board {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "    a b c d e f g h")?;
-        writeln!(f, "  -----------------")?;
-        for rank in (1..=8).rev() {
-            write!(f, "{} |", rank)?;
-            for file in File::VARIANTS {
+const LIGHT_SQUARE: &str = "\x1b[48;5;180m";
+const DARK_SQUARE: &str = "\x1b[48;5;137m";
+const WHITE_PIECE: &str = "\x1b[1;97m";
+const BLACK_PIECE: &str = "\x1b[1;30m";
+const RESET: &str = "\x1b[0m";
+
+impl Board {
+    /// The board drawn for a terminal, followed by the side to move, castling and clocks
+    ///
+    /// With the default options this is the Display output. The alternate format `{:#}` uses
+    /// chess symbols.
+    pub fn pretty(&self, options: DisplayOptions) -> String {
+        let mut out = String::new();
+        // Coloured squares are three characters wide so the pieces sit in the middle
+        let width = if options.colors { 3 } else { 2 };
+        if options.labels {
+            out.push_str("   ");
+            for file in 'a'..='h' {
+                out.push_str(&format!("{:>1$}", file, width));
+            }
+            out.push_str(&format!("\n  {}\n", "-".repeat(8 * width + 1)));
+        }
+        for rank in Rank::VARIANTS.into_iter().rev() {
+            if options.labels {
+                out.push_str(&format!("{} |", rank));
+            }
+            for (i, file) in File::VARIANTS.into_iter().enumerate() {
                 let (piece, color) = self.get_piece(rank, file);
-                let c = match piece {
-                    Some(Piece::Pawn) => 'p',
-                    Some(Piece::Knight) => 'n',
-                    Some(Piece::Bishop) => 'b',
-                    Some(Piece::Rook) => 'r',
-                    Some(Piece::Queen) => 'q',
-                    Some(Piece::King) => 'k',
-                    None => '.',
+                let c = match (piece.zip(color), options.unicode) {
+                    (Some((piece, color)), true) => piece_symbol(piece, color),
+                    (Some((piece, color)), false) => piece.to_fen_char(color),
+                    (None, _) if options.colors => ' ',
+                    (None, true) => '·',
+                    (None, false) => '.',
                 };
-                match color {
-                    Some(Color::White) => write!(f, " {}", c.to_uppercase())?,
-                    _ => write!(f, " {}", c)?,
+                if !options.colors {
+                    out.push_str(&format!(" {}", c));
+                    continue;
+                }
+                // a1 is a dark square
+                let square = match (rank as usize + i) % 2 {
+                    0 => DARK_SQUARE,
+                    _ => LIGHT_SQUARE,
                 };
+                let piece = match color {
+                    Some(Color::White) => WHITE_PIECE,
+                    _ => BLACK_PIECE,
+                };
+                out.push_str(&format!("{}{} {} {}", square, piece, c, RESET));
             }
-            writeln!(f)?;
+            out.push('\n');
         }
-        writeln!(f)?;
-        writeln!(
-            f,
-            "{:?} to play.  | {} {:?} ply: {} move: {} last capture: {} material: {}",
+        out.push_str(&format!(
+            "\n{:?} to play.  | {} {} ply: {} move: {} last capture: {} material: {}\n\n",
             self.active_color,
-            self.castle.as_fen(),
-            self.en_passant,
+            self.castle_fen(),
+            self.en_passant.map_or("-".to_string(), |c| c.to_string()),
             self.ply,
             self.move_number,
             self.fifty_move_rule,
             (i64::from(self.white_value) - i64::from(self.black_value)),
-        )?;
-        writeln!(f)?;
-        Ok(())
+        ));
+        out
+    }
+}
+
+fn piece_symbol(piece: Piece, color: Color) -> char {
+    match (color, piece) {
+        (Color::White, Piece::Pawn) => '♙',
+        (Color::White, Piece::Knight) => '♘',
+        (Color::White, Piece::Bishop) => '♗',
+        (Color::White, Piece::Rook) => '♖',
+        (Color::White, Piece::Queen) => '♕',
+        (Color::White, Piece::King) => '♔',
+        (Color::Black, Piece::Pawn) => '♟',
+        (Color::Black, Piece::Knight) => '♞',
+        (Color::Black, Piece::Bishop) => '♝',
+        (Color::Black, Piece::Rook) => '♜',
+        (Color::Black, Piece::Queen) => '♛',
+        (Color::Black, Piece::King) => '♚',
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let options = DisplayOptions {
+            unicode: f.alternate(),
+            ..DisplayOptions::default()
+        };
+        f.write_str(&self.pretty(options))
     }
 }
 
 #[cfg(test)]
 mod evaluate {
+    use super::BitBoard;
     use super::Board;
-    use super::Color;
     use super::Game;
+    use super::MAX_PHASE;
+    use crate::eval_params::EvalParams;
+    use crate::misc::{Color, Piece};
     use pretty_assertions::assert_eq;
+    use std::sync::Arc;
 
     macro_rules! test_fen {
         ($func:ident, $f:expr) => {
@@ -1147,6 +2787,19 @@ mod evaluate {
                             (board.white_value, board.black_value),
                             board.material_value()
                         );
+                        assert_eq!((board.white_pst, board.black_pst), board.pst_value());
+                        let phase_weight: u32 = (board.white | board.black)
+                            .get_set_bits()
+                            .into_iter()
+                            .map(|i| board.get_piece_index(i).unwrap().phase_weight())
+                            .sum();
+                        assert_eq!(board.phase_weight, phase_weight);
+                        for piece in Piece::VARIANTS {
+                            for color in [Color::White, Color::Black] {
+                                let bits = board.piece_bitboard(piece, color).count_ones();
+                                assert_eq!(board.piece_count(piece, color), bits);
+                            }
+                        }
                         let score = board.eval();
                         board.active_color = !board.active_color;
                         let opp_score = board.eval();
@@ -1159,28 +2812,222 @@ mod evaluate {
         };
     }
 
-    test_fen!(
-        initial_position,
-        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
-    );
-    test_fen!(
-        promotion,
-        "rnbqkbnr/pp1ppppp/8/2p5/3Pp3/8/PPPP1PpP/RNBQKB1R b KQkq e5 0 2"
-    );
-    test_fen!(
-        castling,
-        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10"
-    );
-    test_fen!(position_3, "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
+    test_fen!(
+        initial_position,
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+    );
+    test_fen!(
+        promotion,
+        "rnbqkbnr/pp1ppppp/8/2p5/3Pp3/8/PPPP1PpP/RNBQKB1R b KQkq e5 0 2"
+    );
+    test_fen!(
+        castling,
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10"
+    );
+    test_fen!(position_3, "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
+
+    fn order_score(board: &Board, play: &str) -> i64 {
+        let play = board
+            .generate_moves()
+            .into_iter()
+            .find(|p| p.to_string() == play)
+            .unwrap();
+        board.order_score(&play)
+    }
+
+    #[test]
+    fn test_insufficient_material() {
+        for fen in [
+            "8/8/4k3/8/8/3K4/8/8 w - - 0 1",
+            "8/8/4k3/8/8/3KB3/8/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/2n5/8 b - - 0 1",
+            "8/8/3bk3/8/8/3KB3/8/8 w - - 0 1", // Both bishops on dark squares
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            assert!(board.is_insufficient_material(), "{}", fen);
+            assert!(board.is_draw(), "{}", fen);
+        }
+        for fen in [
+            "8/8/4k3/8/8/3K4/4P3/8 w - - 0 1",
+            "8/8/4k3/8/8/3KBB2/8/8 w - - 0 1", // Bishops on both colors
+            "8/8/4k3/8/8/3KBN2/8/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/2n5/2n5 b - - 0 1",
+            "8/8/2b1k3/8/8/3KB3/8/8 w - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            assert!(!board.is_insufficient_material(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_drawish_endings_scaled() {
+        // A bishop can't win against pawns
+        let bishop = Board::from_fen("8/6p1/4k3/8/8/3KB3/8/8 w - - 0 1").unwrap();
+        assert!(bishop.eval().abs() < 50);
+
+        // A rook against a bishop is hard to win, but a rook against nothing isn't
+        let rook = Board::from_fen("8/8/2b1k3/8/8/3KR3/8/8 w - - 0 1").unwrap();
+        let rook_alone = Board::from_fen("8/8/4k3/8/8/3KR3/8/8 w - - 0 1").unwrap();
+        assert!(rook.eval() < 100);
+        assert!(rook_alone.eval() > 400);
+
+        // As is a rook and bishop against a rook
+        let rook_bishop = Board::from_fen("8/8/2r1k3/8/8/3KRB2/8/8 w - - 0 1").unwrap();
+        assert!(rook_bishop.eval() < 100);
+
+        // A pawn up with bishops on opposite colours is usually a draw, but not with the same
+        // coloured bishops
+        let opposite = Board::from_fen("8/5p2/2b1kp2/8/8/3KBPP1/5P2/8 w - - 0 1").unwrap();
+        let same = Board::from_fen("8/5p2/3bkp2/8/8/3KBPP1/5P2/8 w - - 0 1").unwrap();
+        assert!(opposite.has_opposite_bishops());
+        assert!(!same.has_opposite_bishops());
+        assert!(opposite.eval() < same.eval());
+        let with_rooks = Board::from_fen("8/5p2/2b1kp2/r7/R7/3KBPP1/5P2/8 w - - 0 1").unwrap();
+        assert!(with_rooks.eval() > opposite.eval());
+    }
+
+    #[test]
+    fn test_set_eval_params() {
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/PPP5/4K3 w - - 0 1").unwrap();
+        let before = board.eval();
+        let mut params = EvalParams::default();
+        params.material[Piece::Pawn as usize] += 100;
+        board.set_eval_params(Arc::new(params));
+        assert_eq!(board.eval(), before + 300);
+        assert_eq!(
+            (board.white_value, board.black_value),
+            board.material_value()
+        );
+    }
+
+    #[test]
+    fn test_passed_pawns() {
+        let params = EvalParams::default();
+        let score = |fen: &str, color, endgame| {
+            Board::from_fen(fen)
+                .unwrap()
+                .passed_pawn_score(color, endgame)
+        };
+        let passed = "4k3/8/8/3P4/8/8/8/4K3 w - - 0 1";
+        assert_eq!(score(passed, Color::White, false), params.passed_pawn[4]);
+        let black = "4k3/8/8/8/3p4/8/8/4K3 b - - 0 1";
+        assert_eq!(score(black, Color::Black, false), params.passed_pawn[4]);
+        // An enemy pawn on an adjacent file in front stops it being passed
+        let stopped = "4k3/2p5/8/3P4/8/8/8/4K3 w - - 0 1";
+        assert_eq!(score(stopped, Color::White, false), 0);
+
+        let blockaded = "4k3/8/3n4/3P4/8/8/8/4K3 w - - 0 1";
+        assert_eq!(
+            score(blockaded, Color::White, false),
+            params.passed_pawn[4] / 2
+        );
+
+        let rook_behind = "4k3/8/8/3P4/8/8/8/3RK3 w - - 0 1";
+        let rook_blocked = "4k3/8/8/3P4/8/3N4/8/3RK3 w - - 0 1";
+        assert_eq!(
+            score(rook_behind, Color::White, false),
+            params.passed_pawn[4] + params.passed_pawn_rook_behind
+        );
+        assert_eq!(
+            score(rook_blocked, Color::White, false),
+            params.passed_pawn[4]
+        );
+
+        // In the endgame the pawn is worth more when our king is closer to its path
+        let escorted = "8/8/4K3/3P4/8/8/8/7k w - - 0 1";
+        let chased = "8/8/4k3/3P4/8/8/8/7K w - - 0 1";
+        assert!(score(escorted, Color::White, true) > params.passed_pawn[4]);
+        assert!(score(chased, Color::White, true) < params.passed_pawn[4]);
+    }
+
+    #[test]
+    fn test_king_pawn_safety() {
+        let params = EvalParams::default();
+        let safety = |fen: &str, color| Board::from_fen(fen).unwrap().king_pawn_safety(color);
+        let castled = "r4rk1/pppq1ppp/8/8/8/8/PPPQ1PPP/R4RK1 w - - 0 1";
+        assert_eq!(safety(castled, Color::White), 0);
+        assert_eq!(safety(castled, Color::Black), 0);
+
+        // Pushing a pawn in front of the king weakens it, losing it leaves the file open
+        let pushed = "r4rk1/pppq1ppp/8/8/8/6P1/PPPQ1P1P/R4RK1 w - - 0 1";
+        let open = "r4rk1/pppq1ppp/8/8/8/8/PPPQ1P1P/R4RK1 w - - 0 1";
+        assert_eq!(safety(pushed, Color::White), params.king_shelter[2]);
+        assert_eq!(safety(open, Color::White), params.king_open_file);
+
+        // Enemy pawns storming towards the king
+        let storm = "r4rk1/pppq1p1p/8/8/8/6p1/PPPQ1PP1/R4RK1 b - - 0 1";
+        assert_eq!(
+            safety(storm, Color::White),
+            params.pawn_storm[2] + params.king_open_file
+        );
+        assert!(Board::from_fen(storm).unwrap().eval() > Board::from_fen(castled).unwrap().eval());
+    }
+
+    #[test]
+    fn test_game_phase() {
+        assert_eq!(Board::new().game_phase(), MAX_PHASE);
+        let rooks = Board::from_fen("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w - - 0 1").unwrap();
+        assert_eq!(rooks.game_phase(), MAX_PHASE / 3);
+        let pawns = Board::from_fen("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1").unwrap();
+        assert_eq!(pawns.game_phase(), 0);
+    }
+
+    #[test]
+    fn test_king_tables() {
+        // Castled kings are safer in the middlegame
+        let castled =
+            Board::from_fen("r2qk2r/pppppppp/8/8/8/8/PPPPPPPP/R2Q1RK1 w - - 0 1").unwrap();
+        let central = Board::from_fen("r2qk2r/pppppppp/8/8/8/8/PPPPPPPP/R2QK2R w - - 0 1").unwrap();
+        assert!(!castled.is_endgame());
+        assert!(castled.eval() > central.eval());
+
+        // But should come to the centre in the endgame
+        let corner = Board::from_fen("4k3/8/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let centre = Board::from_fen("4k3/8/8/8/4K3/8/8/8 w - - 0 1").unwrap();
+        assert!(corner.is_endgame());
+        assert!(centre.eval() > corner.eval());
+    }
+
+    #[test]
+    fn test_piece_square_tables_read_from_each_side() {
+        // A pawn about to promote is worth more than one on its starting square, for both colors
+        let advanced = Board::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let home = Board::from_fen("4k3/8/8/8/8/8/P7/4K3 w - - 0 1").unwrap();
+        assert!(advanced.eval() > home.eval());
+        let advanced = Board::from_fen("4k3/8/8/8/8/8/p7/4K3 b - - 0 1").unwrap();
+        let home = Board::from_fen("4k3/p7/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+        assert!(advanced.eval() > home.eval());
+    }
+
+    #[test]
+    fn test_quiet_moves_ordered_by_piece_square_delta() {
+        let mut board = Board::new();
+        assert!(order_score(&board, "b1c3") > order_score(&board, "b1a3"));
+        assert!(order_score(&board, "e2e4") > order_score(&board, "a2a3"));
+
+        // The same for black, whose tables are mirrored
+        let white_knight = order_score(&board, "b1c3");
+        assert!(board.make_move(&board.generate_moves()[0]));
+        assert_eq!(order_score(&board, "b8c6"), white_knight);
+    }
 }
 
 #[cfg(test)]
 mod make_move {
+    use super::index_to_coordinate;
+    use super::BitBoard;
     use super::Board;
+    use super::Color;
     use super::Game;
+    use super::GameState;
+    use super::MoveList;
+    use super::Piece;
     use super::Play;
-    use super::{A1, A8, B1, B8};
+    use super::{between, bishop_attacks, line, rook_attacks};
+    use super::{MoveError, NoMoveToUndo};
+    use super::{A1, A8};
     use pretty_assertions::{assert_eq, assert_ne};
+    use std::collections::HashSet;
 
     macro_rules! test_fen_reversible {
         ($func:ident, $f:expr) => {
@@ -1248,8 +3095,748 @@ mod make_move {
     );
     test_fen_captures!(position_3, "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1");
 
+    #[test]
+    fn test_legal_moves_matches_perft() {
+        let mut board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        assert_eq!(board.legal_moves().count() as u64, board.perft(1));
+    }
+
+    #[test]
+    fn test_generate_legal_moves_matches_make_move() {
+        // Pins, checks, double checks and en passant captures which expose the king
+        let mut boards: Vec<Board> = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+            "4k3/8/8/8/1b6/8/3P4/4K2r w - - 0 1",
+        ]
+        .iter()
+        .map(|f| Board::from_fen(f).unwrap())
+        .collect();
+        for board in boards.clone() {
+            for m in board.generate_moves() {
+                let mut next = board.clone();
+                if next.make_move(&m) {
+                    boards.push(next);
+                }
+            }
+        }
+        for board in boards {
+            let mut expected: Vec<String> = board
+                .generate_moves()
+                .iter()
+                .filter(|m| {
+                    let mut next = board.clone();
+                    next.make_move(m)
+                })
+                .map(|m| m.to_string())
+                .collect();
+            let mut legal: Vec<String> = board
+                .generate_legal_moves()
+                .iter()
+                .map(|m| m.to_string())
+                .collect();
+            expected.sort();
+            legal.sort();
+            assert_eq!(legal, expected, "{}", board);
+        }
+    }
+
+    #[test]
+    fn test_parse_uci_move() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for m in board.generate_legal_moves() {
+                assert_eq!(board.parse_uci_move(&m.to_string()), Ok(m), "{}", fen);
+            }
+        }
+
+        let board =
+            Board::from_fen("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8").unwrap();
+        assert_eq!(
+            board.parse_uci_move("d7c8Q").map(|m| m.to_string()),
+            Ok("d7c8q".to_string())
+        );
+        let error = |m: &str| board.parse_uci_move(m).unwrap_err();
+        for m in ["", "e2", "e2e4qq", "i2i4", "e0e1", "e2e9", "d7c8k"] {
+            assert_eq!(error(m), MoveError::InvalidFormat(m.to_string()));
+        }
+        assert_eq!(error("e3e4"), MoveError::NoPiece("e3e4".to_string()));
+        assert_eq!(error("e8d8"), MoveError::NoPiece("e8d8".to_string()));
+        // Pawns must promote and only move forward one or two squares
+        assert_eq!(error("d7c8"), MoveError::Illegal("d7c8".to_string()));
+        assert_eq!(error("a2a5"), MoveError::Illegal("a2a5".to_string()));
+
+        // The king can't castle out of check or be left in check
+        let board = Board::from_fen("4k3/8/8/8/8/8/4r3/R3K2R w KQ - 0 1").unwrap();
+        assert_eq!(
+            board.parse_uci_move("e1g1"),
+            Err(MoveError::Illegal("e1g1".to_string()))
+        );
+        assert_eq!(
+            board.parse_uci_move("a1a5"),
+            Err(MoveError::Illegal("a1a5".to_string()))
+        );
+        assert_eq!(
+            board.parse_uci_move("e1e2").map(|m| m.capture),
+            Ok(Some(Piece::Rook))
+        );
+    }
+
+    #[test]
+    fn test_make_move_checked() {
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/4r3/R3K2R w KQ - 0 1").unwrap();
+        let before = board.clone();
+        // e3e4, e8d8, a1a5 and a capture of the rook without saying so
+        let moves = [
+            Play::new(20, 28, None, None, false, false),
+            Play::new(60, 59, None, None, false, false),
+            Play::new(0, 32, None, None, false, false),
+            Play::new(4, 12, None, None, false, false),
+        ];
+        assert_eq!(
+            board.make_move_checked(&moves[0]),
+            Err(MoveError::NoPiece("e3e4".to_string()))
+        );
+        assert_eq!(
+            board.make_move_checked(&moves[1]),
+            Err(MoveError::NoPiece("e8d8".to_string()))
+        );
+        assert_eq!(
+            board.make_move_checked(&moves[2]),
+            Err(MoveError::Illegal("a1a5".to_string()))
+        );
+        assert_eq!(
+            board.make_move_checked(&moves[3]),
+            Err(MoveError::Illegal("e1e2".to_string()))
+        );
+        assert_eq!(board, before);
+
+        let capture = Play::new(4, 12, Some(Piece::Rook), None, false, false);
+        assert_eq!(board.make_move_checked(&capture), Ok(()));
+        assert_eq!(board.get_piece_index(12), Some(Piece::King));
+    }
+
+    #[test]
+    fn test_game_end_predicates() {
+        let states = |fen: &str| {
+            let board = Board::from_fen(fen).unwrap();
+            (board.in_check(), board.is_checkmate(), board.is_stalemate())
+        };
+        assert_eq!(
+            states("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+            (false, false, false)
+        );
+        // Scholar's mate, and the same check when the king can escape
+        let mate = "r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4";
+        assert_eq!(states(mate), (true, true, false));
+        let check = "r1bqkb1r/pppp1Qpp/2n2n2/4p3/4P3/8/PPPP1PPP/RNB1KBNR b KQkq - 0 4";
+        assert_eq!(states(check), (true, false, false));
+        assert_eq!(
+            states("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"),
+            (false, false, true)
+        );
+        // The pawn is pinned and the king's squares are covered by knights
+        let pinned = "4k2b/8/8/8/8/8/1P1n4/K1n5 w - - 0 1";
+        assert_eq!(states(pinned), (false, false, true));
+    }
+
+    #[test]
+    fn test_gives_check_matches_make_move() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            // Discovered checks, checks by castling and by capturing en passant
+            "4k3/8/2N5/8/B7/8/8/4K3 w - - 0 1",
+            "5k2/8/8/8/8/8/8/R3K2R w KQ - 0 1",
+            "8/8/8/1k1pP2R/8/8/8/4K3 w - d6 0 1",
+            "2k5/8/8/8/8/8/3p4/6K1 b - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for m in board.generate_moves() {
+                let mut after = board.clone();
+                if after.make_move(&m) {
+                    assert_eq!(board.gives_check(&m), after.in_check(), "{} {}", fen, m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_claimable_draws() {
+        // Moving the kings out and back loses the castling rights, so the starting position
+        // doesn't count towards a repetition
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let shuffle = |board: &mut Board| {
+            for m in ["e1e2", "e8e7", "e2e1", "e7e8"] {
+                assert!(board.make_move(&board.parse_uci_move(m).unwrap()));
+            }
+        };
+        shuffle(&mut board);
+        shuffle(&mut board);
+        assert!(!board.is_repetition());
+        assert!(!board.is_threefold_repetition());
+        assert_eq!(board.game_state(), GameState::Ongoing);
+        shuffle(&mut board);
+        assert!(board.is_threefold_repetition());
+        assert_eq!(board.game_state(), GameState::DrawByRepetition);
+
+        let claim = |fen: &str| Board::from_fen(fen).unwrap().can_claim_fifty_move_draw();
+        assert!(claim("4k3/8/8/8/8/8/8/4KR2 w - - 100 80"));
+        assert!(!claim("4k3/8/8/8/8/8/8/4KR2 w - - 99 80"));
+        assert!(!claim("R5k1/5ppp/8/8/8/8/8/6K1 b - - 100 80"));
+    }
+
+    #[test]
+    fn test_line_masks() {
+        // The masks agree with slider attacks on an empty board
+        for a in 0..64 {
+            for b in 0..64 {
+                let (a_mask, b_mask) = (1u64 << a, 1u64 << b);
+                let (mut expected_between, mut expected_line) = (0, 0);
+                for slide in [
+                    |s, occupied| rook_attacks(s, occupied),
+                    |s, occupied| bishop_attacks(s, occupied),
+                ] {
+                    if a != b && slide(a, 0).is_bit_set(b) {
+                        expected_between = slide(a, b_mask) & slide(b, a_mask);
+                        expected_line = (slide(a, 0) & slide(b, 0)) | a_mask | b_mask;
+                    }
+                }
+                assert_eq!(between(a, b), expected_between, "between {} {}", a, b);
+                assert_eq!(line(a, b), expected_line, "line {} {}", a, b);
+            }
+        }
+        // a1 and h8 are on the long diagonal, b1 and c3 aren't aligned
+        assert_eq!(between(0, 63), 0x0040_2010_0804_0200);
+        assert_eq!(line(9, 18), 0x8040_2010_0804_0201);
+        assert_eq!(line(1, 18), 0);
+    }
+
+    #[test]
+    fn test_squares_match_bitboards() {
+        let check = |board: &Board| {
+            for index in 0..64 {
+                let piece = [
+                    (board.pawns, Piece::Pawn),
+                    (board.knights, Piece::Knight),
+                    (board.bishops, Piece::Bishop),
+                    (board.rooks, Piece::Rook),
+                    (board.queens, Piece::Queen),
+                    (board.kings, Piece::King),
+                ]
+                .into_iter()
+                .find(|(bitboard, _)| bitboard.is_bit_set(index))
+                .map(|(_, piece)| piece);
+                let color = match board.white.is_bit_set(index) {
+                    true => Color::White,
+                    false => Color::Black,
+                };
+                assert_eq!(
+                    board.get_piece_and_color_index(index),
+                    piece.map(|p| (p, color))
+                );
+            }
+        };
+        // Castling both ways, en passant and promotions with and without captures
+        let mut board =
+            Board::from_fen("r3k2r/pPppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        for play in board.generate_legal_moves() {
+            board.make_move(&play);
+            check(&board);
+            for reply in board.generate_legal_moves() {
+                board.make_move(&reply);
+                check(&board);
+                board.undo_move().unwrap();
+            }
+            board.undo_move().unwrap();
+            check(&board);
+        }
+    }
+
+    #[test]
+    fn test_key_from_scratch() {
+        fn walk(board: &mut Board, depth: u8) {
+            assert_eq!(board.key, board.compute_key_from_scratch(), "{}", board);
+            if depth == 0 {
+                return;
+            }
+            for play in board.generate_legal_moves() {
+                let key_after = board.key_after(&play);
+                board.make_move(&play);
+                assert_eq!(board.key, key_after, "{}", play);
+                walk(board, depth - 1);
+                board.undo_move().unwrap();
+            }
+        }
+        let mut board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        walk(&mut board, 3);
+        // Promotions with and without captures, and Chess960 castling
+        for fen in [
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9",
+        ] {
+            walk(&mut Board::from_fen(fen).unwrap(), 2);
+        }
+
+        // Positions reached by moves have the same key as when loaded from a FEN, with black to
+        // move and an en passant capture possible
+        let mut board = Board::from_fen("4k3/8/8/8/3p4/8/4P3/4K3 w - - 0 1").unwrap();
+        board.make_move(&board.parse_uci_move("e2e4").unwrap());
+        let fen = Board::from_fen("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1").unwrap();
+        assert_eq!(board.key, fen.key);
+        assert_ne!(
+            board.key,
+            Board::from_fen("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1")
+                .unwrap()
+                .key
+        );
+
+        // Castling rights are part of the key, a king which moves away and back loses them
+        let key = |fen| Board::from_fen(fen).unwrap().key;
+        assert_ne!(
+            key("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"),
+            key("r3k2r/8/8/8/8/8/8/R3K2R w Kkq - 0 1")
+        );
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        for uci in ["e1e2", "e8e7", "e2e1", "e7e8"] {
+            board.make_move(&board.parse_uci_move(uci).unwrap());
+        }
+        assert_eq!(board.key, key("r3k2r/8/8/8/8/8/8/R3K2R w - - 4 3"));
+        assert_ne!(board.key, key("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"));
+    }
+
+    #[test]
+    fn test_pieces() {
+        let board = Board::new();
+        let pieces: Vec<_> = board.pieces().collect();
+        assert_eq!(pieces.len(), 32);
+        assert_eq!(pieces[0], (0, Piece::Rook, Color::White));
+        assert_eq!(pieces[4], (4, Piece::King, Color::White));
+        assert_eq!(pieces[16], (48, Piece::Pawn, Color::Black));
+        assert_eq!(pieces[31], (63, Piece::Rook, Color::Black));
+
+        let board = Board::from_fen("8/8/3k4/8/8/8/8/Q3K3 b - - 0 1").unwrap();
+        assert_eq!(
+            board.pieces().collect::<Vec<_>>(),
+            [
+                (0, Piece::Queen, Color::White),
+                (4, Piece::King, Color::White),
+                (43, Piece::King, Color::Black),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_played_moves() {
+        let mut board = Board::new();
+        assert_eq!(board.last_move(), None);
+        assert_eq!(board.played_moves().len(), 0);
+        for uci in ["e2e4", "e7e5", "g1f3"] {
+            board.make_move(&board.parse_uci_move(uci).unwrap());
+        }
+        assert_eq!(
+            board.last_move().map(|m| m.to_string()),
+            Some("g1f3".to_string())
+        );
+        let played: Vec<String> = board.played_moves().map(|m| m.to_string()).collect();
+        assert_eq!(played, ["e2e4", "e7e5", "g1f3"]);
+
+        // Illegal moves and undone moves aren't kept
+        assert!(!board.make_move(&Play::new(60, 4, Some(Piece::King), None, false, false)));
+        board.undo_move().unwrap();
+        assert_eq!(
+            board.last_move().map(|m| m.to_string()),
+            Some("e7e5".to_string())
+        );
+        assert_eq!(board.played_moves().next_back(), board.last_move());
+    }
+
+    #[test]
+    fn test_accessors() {
+        let board = Board::from_fen("r3k3/8/8/3pP3/8/8/8/4K2R w Kq d6 3 42").unwrap();
+        assert_eq!(board.piece_bitboard(Piece::Pawn, Color::White), 1 << 36);
+        assert_eq!(board.piece_bitboard(Piece::Pawn, Color::Black), 1 << 35);
+        assert_eq!(board.piece_bitboard(Piece::Queen, Color::White), 0);
+        assert_eq!(
+            board.occupancy(Color::White),
+            (1 << 4) | (1 << 7) | (1 << 36)
+        );
+        assert_eq!(
+            board.occupancy(Color::Black),
+            (1 << 56) | (1 << 60) | (1 << 35)
+        );
+        assert_eq!(board.en_passant(), Some(43));
+        let castle = board.castle_permissions();
+        assert!(castle.allowed(Color::White, true) && castle.allowed(Color::Black, false));
+        assert!(!castle.allowed(Color::White, false) && !castle.allowed(Color::Black, true));
+        assert_eq!(board.move_number(), 42);
+        assert_eq!(board.halfmove_clock(), 3);
+        assert_eq!(Board::new().en_passant(), None);
+
+        // The material key ignores the squares, promoting changes the counts
+        let krk = Board::from_fen("8/8/3k4/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let other = Board::from_fen("k7/8/8/8/8/6R1/8/7K b - - 0 1").unwrap();
+        assert_eq!(krk.material_key(), other.material_key());
+        assert_ne!(krk.material_key(), Board::new().material_key());
+        let mut board = Board::from_fen("8/P7/3k4/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(board.piece_count(Piece::Pawn, Color::White), 1);
+        board.make_move(&board.parse_uci_move("a7a8q").unwrap());
+        assert_eq!(board.piece_count(Piece::Pawn, Color::White), 0);
+        assert_eq!(board.piece_count(Piece::Queen, Color::White), 1);
+        assert_eq!(Board::new().piece_count(Piece::Pawn, Color::Black), 8);
+    }
+
+    #[test]
+    fn test_flip_and_mirror() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let board = Board::from_fen(kiwipete).unwrap();
+        let flipped =
+            Board::from_fen("r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b KQkq - 0 1")
+                .unwrap();
+        assert_eq!(board.flip(), flipped);
+        assert_eq!(flipped.flip(), board);
+
+        let mirrored =
+            Board::from_fen("r2k3r/1bpqpp1p/1pnp2nb/3NP3/3P2p1/p1Q2N2/PPPBBPPP/R2K3R w - - 0 1")
+                .unwrap();
+        assert_eq!(board.mirror(), mirrored);
+
+        // En passant, a Chess960 castle and black to move
+        let fens = [
+            "rnbqkbnr/ppp2ppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3",
+            "8/2p5/3p4/KP5r/1R2Pp1k/8/6P1/8 b - e3 0 1",
+            "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9",
+            kiwipete,
+        ];
+        for fen in fens {
+            let mut board = Board::from_fen(fen).unwrap();
+            let mut flipped = board.flip();
+            assert_eq!(flipped.flip(), board);
+            assert_eq!(flipped.eval(), board.eval());
+            assert_eq!(flipped.perft(3), board.perft(3));
+
+            let mut no_castling = board.mirror().mirror();
+            let mut mirrored = no_castling.mirror();
+            assert_eq!(mirrored.mirror(), no_castling);
+            assert_eq!(mirrored.perft(3), no_castling.perft(3));
+        }
+    }
+
+    #[test]
+    fn test_long_game_history() {
+        // Late move numbers and games longer than any fixed history used to overflow it
+        let fen = "4k1n1/8/8/8/8/8/8/4K1N1 w - - 0 300";
+        let mut board = Board::from_fen(fen).unwrap();
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        for m in moves.iter().cycle().take(1000) {
+            assert!(board.make_move(&board.parse_uci_move(m).unwrap()));
+        }
+        assert!(board.is_repetition());
+        // Taking back moves played before the search root, as a front-end might
+        board.line_ply = 0;
+        for i in (0..1000).rev() {
+            assert_eq!(board.undo_move().unwrap().to_string(), moves[i % 4]);
+        }
+        assert_eq!(board, Board::from_fen(fen).unwrap());
+        assert_eq!(board.undo_move(), Err(NoMoveToUndo));
+    }
+
+    #[test]
+    fn test_chess960_castling() {
+        let castles = |board: &Board| -> Vec<String> {
+            let mut moves: Vec<String> = board
+                .generate_legal_moves()
+                .iter()
+                .filter(|m| m.castle)
+                .map(|m| m.to_string())
+                .collect();
+            moves.sort();
+            moves
+        };
+        // KQkq means the outermost rook, file letters are needed for an inner rook
+        for (token, fen) in [("KQ", "KQ"), ("HA", "KQ"), ("GA", "GA"), ("C", "C")] {
+            let board =
+                Board::from_fen(&format!("4k3/8/8/8/8/8/8/R1R1K1RR w {} - 0 1", token)).unwrap();
+            assert_eq!(board.castle_fen(), fen);
+        }
+        // Shredder-FEN always gives the files, and reads back as the same castling rights. The
+        // key doesn't include the castling rights, so they're compared directly.
+        let board = Board::new();
+        assert_eq!(board.shredder_castle_fen(), "HAha");
+        let shredder = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1";
+        let read = Board::from_fen(shredder).unwrap();
+        assert_eq!(read.castle_permissions(), board.castle_permissions());
+        assert_eq!(read.castle_fen(), "KQkq");
+        assert_eq!(read.key(), board.key());
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1").unwrap();
+        assert_eq!(board.shredder_castle_fen(), "Ha");
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.shredder_castle_fen(), "-");
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w K - 0 1").is_err());
+        assert!(Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w D - 0 1").is_err());
+
+        // The king and rook swap squares, then the king castles without moving
+        let fen = "6k1/8/8/8/8/8/8/1R3KR1 w GB - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        assert_eq!(castles(&board), ["f1c1", "f1g1"]);
+        let play = board.parse_uci_move("f1g1").unwrap();
+        assert!(play.castle);
+        assert_eq!(board.parse_uci_move("f1b1").map(|m| m.castle), Ok(true));
+        assert!(board.make_move(&play));
+        assert_eq!(board.get_piece_index(6), Some(Piece::King));
+        assert_eq!(board.get_piece_index(5), Some(Piece::Rook));
+        assert_eq!(board.castle_fen(), "-");
+        board.undo_move().unwrap();
+        assert_eq!(board.key(), Board::from_fen(fen).unwrap().key());
+        assert_eq!(board.shredder_castle_fen(), "GB");
+        assert_eq!(board.get_piece_index(5), Some(Piece::King));
+        assert_eq!(board.get_piece_index(6), Some(Piece::Rook));
+
+        let board = Board::from_fen("6k1/8/8/8/8/8/8/6KR w K - 0 1").unwrap();
+        assert_eq!(castles(&board), ["g1g1"]);
+        assert!(!board.gives_check(&board.parse_uci_move("g1h1").unwrap()));
+
+        // The rook shields the king from the queen, so castling would put the king in check
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/q1R1K3 w C - 0 1").unwrap();
+        assert_eq!(castles(&board), Vec::<String>::new());
+        // Taking the castling rook loses the right to castle with it
+        let mut board = Board::from_fen("1r2k3/8/8/8/8/8/8/1R2K2R b KQ - 0 1").unwrap();
+        assert!(board.make_move(&board.parse_uci_move("b8b1").unwrap()));
+        assert_eq!(board.castle_fen(), "K");
+    }
+
+    #[test]
+    fn test_pinned_pieces() {
+        let pinned = |fen: &str, color: Color| {
+            let board = Board::from_fen(fen).unwrap();
+            let mut squares: Vec<String> = board
+                .pinned_pieces(color)
+                .get_set_bits()
+                .iter()
+                .map(|&i| {
+                    let (rank, file) = index_to_coordinate(i);
+                    format!("{}{}", file, rank)
+                })
+                .collect();
+            squares.sort();
+            squares
+        };
+        // Pins on a file, a rank and a diagonal, the pieces on f2 and g3 shield each other
+        let fen = "4r2k/8/8/8/1b2N2q/6N1/3P1P2/r2BK3 w - - 0 1";
+        assert_eq!(pinned(fen, Color::White), ["d1", "d2", "e4"]);
+        assert_eq!(pinned(fen, Color::Black), Vec::<String>::new());
+
+        let fen = "k7/1p6/8/8/8/5B2/8/K7 b - - 0 1";
+        assert_eq!(pinned(fen, Color::Black), ["b7"]);
+        // An enemy piece in between isn't pinned and doesn't pin
+        let fen = "k7/1p6/2P5/8/8/5B2/8/K7 b - - 0 1";
+        assert_eq!(pinned(fen, Color::Black), Vec::<String>::new());
+        assert_eq!(pinned(fen, Color::White), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_en_passant_key() {
+        let key = |fen: &str| Board::from_fen(fen).unwrap().key();
+        // The square is only hashed if a pawn can capture on it
+        assert_eq!(
+            key("4k3/8/8/p7/4P3/8/8/4K3 b - e3 0 1"),
+            key("4k3/8/8/p7/4P3/8/8/4K3 b - - 0 1")
+        );
+        assert_ne!(
+            key("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1"),
+            key("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1")
+        );
+
+        let play = |fen: &str, moves: &[&str]| {
+            let mut board = Board::from_fen(fen).unwrap();
+            let start = board.key();
+            for m in moves {
+                assert!(board.make_move(&board.parse_uci_move(m).unwrap()));
+            }
+            let end = board.key();
+            for _ in moves {
+                board.undo_move().unwrap();
+            }
+            assert_eq!(board.key(), start);
+            end
+        };
+        // After a double push and a king move each, the same position reached without the
+        // double push has the same key once the en passant square has gone
+        for (before, after) in [("3p4", "3pP3"), ("p7", "p3P3")] {
+            let pushed = play(
+                &format!("4k3/8/8/8/{}/8/4P3/4K3 w - - 0 1", before),
+                &["e2e4", "e8d8", "e1d1", "d8e8"],
+            );
+            let walked = play(
+                &format!("4k3/8/8/8/{}/8/8/4K3 w - - 0 1", after),
+                &["e1d2", "e8d8", "d2d1", "d8e8"],
+            );
+            assert_eq!(pushed, walked);
+        }
+        // Undoing an en passant capture
+        play(
+            "4k3/8/8/8/3p4/8/4P3/4K3 w - - 0 1",
+            &["e2e4", "d4e3", "e1e2"],
+        );
+    }
+
+    #[test]
+    fn test_game_state() {
+        let state = |fen: &str| Board::from_fen(fen).unwrap().game_state();
+        assert_eq!(Board::new().game_state(), GameState::Ongoing);
+        assert_eq!(
+            state("r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4"),
+            GameState::Checkmate(Color::White)
+        );
+        assert_eq!(
+            state("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"),
+            GameState::Stalemate
+        );
+        assert_eq!(
+            state("4k3/8/8/8/8/8/8/4KB2 w - - 0 1"),
+            GameState::DrawByInsufficientMaterial
+        );
+        assert_eq!(
+            state("4k3/8/8/8/8/8/8/4KR2 w - - 100 80"),
+            GameState::DrawByFiftyMove
+        );
+        // Mate on the hundredth half move still wins
+        assert_eq!(
+            state("R5k1/5ppp/8/8/8/8/8/6K1 b - - 100 80"),
+            GameState::Checkmate(Color::White)
+        );
+
+        let mut board = Board::new();
+        for _ in 0..2 {
+            for m in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                assert_eq!(board.game_state(), GameState::Ongoing);
+                assert!(board.make_move(&board.parse_uci_move(m).unwrap()));
+            }
+        }
+        assert_eq!(board.game_state(), GameState::DrawByRepetition);
+        assert!(board.game_state().is_over());
+        assert_eq!(board.game_state().winner(), None);
+    }
+
+    #[test]
+    fn test_generate_into_reused_list() {
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        // The list starts with the moves of another position, which are replaced
+        let mut moves = MoveList::new();
+        Board::new().generate_moves_into(&mut moves);
+        board.generate_moves_into(&mut moves);
+        assert_eq!(moves.to_vec(), board.generate_moves());
+        board.generate_captures_into(&mut moves);
+        assert_eq!(moves.to_vec(), board.generate_captures());
+        board.generate_legal_moves_into(&mut moves);
+        assert_eq!(moves.to_vec(), board.generate_legal_moves());
+    }
+
+    #[test]
+    fn test_legal_moves_excludes_pinned() {
+        // The bishop on e2 is pinned to the king by the rook and has no legal moves
+        let board = Board::from_fen("4r1k1/8/8/8/8/8/4B3/4K3 w - - 0 1").unwrap();
+        let uci: Vec<String> = board.legal_moves().map(|m| m.uci()).collect();
+        assert!(!uci.is_empty());
+        assert!(uci.iter().all(|m| !m.starts_with("e2")));
+    }
+
+    #[test]
+    fn test_is_pseudo_legal_matches_generate_moves() {
+        // Check moves generated in one position against every other position (and the positions
+        // after one move so both colors are covered)
+        let mut boards = Vec::new();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for m in board.generate_moves() {
+                let mut next = board.clone();
+                if next.make_move(&m) {
+                    boards.push(next);
+                }
+            }
+            boards.push(board);
+        }
+        let plays: HashSet<Play> = boards.iter().flat_map(|b| b.generate_moves()).collect();
+
+        for board in &boards {
+            let generated = board.generate_moves();
+            for play in &plays {
+                assert_eq!(
+                    board.is_pseudo_legal(play),
+                    generated.contains(play),
+                    "{} in {}",
+                    play,
+                    board
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_quiet_checks() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R b KQ - 1 8",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let checks = board.generate_quiet_checks();
+            let moves = board.generate_moves();
+            for play in &checks {
+                assert!(moves.contains(play), "{} in {}", play, fen);
+                assert!(play.capture.is_none() && play.promote.is_none());
+                let mut next = board.clone();
+                next.make_move(play);
+                assert!(
+                    next.is_king_attacked(),
+                    "{} should give check in {}",
+                    play,
+                    fen
+                );
+            }
+        }
+
+        // Rook along the back rank, queen on the diagonal and e-file, knight to c7 and f6 (the
+        // queen's d-file and f3 diagonal checks are blocked)
+        let board = Board::from_fen("4k3/8/8/3N4/8/5P2/8/R2QK3 w - - 0 1").unwrap();
+        let uci: HashSet<String> = board
+            .generate_quiet_checks()
+            .iter()
+            .map(|p| format!("{}", p))
+            .collect();
+        let expected: HashSet<String> = ["a1a8", "d1a4", "d1e2", "d5c7", "d5f6"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(uci, expected);
+    }
+
     #[test]
     fn test_is_repetition() {
+        const B1: u8 = 1;
+        const B8: u8 = 57;
         let mut board = Board::from_fen(
             "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 b - - 3 19",
         )
@@ -1279,7 +3866,6 @@ mod make_move {
 #[cfg(test)]
 mod perft {
     use super::Board;
-    use super::Game;
     use pretty_assertions::assert_eq;
     // TODO convert these tests to use macros
     // Positions and perft results taken from https://www.chessprogramming.org/Perft_Results
@@ -1337,6 +3923,71 @@ mod perft {
         assert_eq!(board.perft(4), 2103487);
     }
 
+    #[test]
+    fn test_perft_divide() {
+        let mut board = Board::new();
+        let divide = board.perft_divide(3);
+        assert_eq!(divide.len(), 20);
+        assert_eq!(divide.iter().map(|(_, n)| n).sum::<u64>(), 8902);
+        let mut counts: Vec<(String, u64)> = divide
+            .into_iter()
+            .map(|(m, n)| (m.to_string(), n))
+            .filter(|(m, _)| ["e2e4", "g1f3", "a2a3"].contains(&m.as_str()))
+            .collect();
+        counts.sort();
+        assert_eq!(
+            counts,
+            [
+                ("a2a3".to_string(), 380),
+                ("e2e4".to_string(), 600),
+                ("g1f3".to_string(), 440)
+            ]
+        );
+        assert_eq!(board, Board::new());
+        assert!(board.perft_divide(0).is_empty());
+    }
+
+    #[test]
+    fn test_perft_hashed() {
+        let mut board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+        assert_eq!(board.perft_hashed(6, 1 << 16), 11030083);
+        // Every lookup misses or finds a different position
+        assert_eq!(board.perft_hashed(4, 1), 43238);
+
+        // Positions reached with and without the castling rights have the same key
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(board.perft_hashed(5, 1 << 16), 7594526);
+
+        let mut board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        assert_eq!(board.perft_hashed(4, 1 << 16), 4085603);
+    }
+
+    // Chess960 positions and results from the chessprogramming.org Chess960 perft suite
+    #[test]
+    fn test_perft_chess960() {
+        for (fen, expected) in [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                [21, 528, 12189, 326672],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                [21, 807, 18002, 667366],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                [20, 479, 10471, 273318],
+            ),
+        ] {
+            let mut board = Board::from_fen(fen).unwrap();
+            for (depth, nodes) in expected.into_iter().enumerate() {
+                assert_eq!(board.perft(depth as u8 + 1), nodes, "{}", fen);
+            }
+        }
+    }
+
     #[test]
     fn test_perft_position_6() {
         let mut board = Board::from_fen(
@@ -1353,7 +4004,10 @@ mod perft {
 #[cfg(test)]
 mod test_fen {
     use super::Board;
-    use super::Game;
+    use super::Color;
+    use super::PositionError;
+    use super::{DisplayOptions, RESET};
+    use super::{FenError, FenField};
     use proptest::prelude::*;
 
     proptest! {
@@ -1397,14 +4051,132 @@ mod test_fen {
                 .is_err()
         );
     }
-    // TODO uncomment this test and fix
-    //#[test]
-    //fn test_invalid_extra_file() {
-    //    assert!(Board::from_fen(
-    //        "rnbqkbnr/ppppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1".to_string()
-    //    )
-    //    .is_err());
-    //}
+    #[test]
+    fn test_invalid_extra_file() {
+        assert!(
+            Board::from_fen("rnbqkbnr/ppppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1")
+                .is_err()
+        );
+        assert!(
+            Board::from_fen("rnbqkbnr/pppppppp/9/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").is_err()
+        );
+        assert!(
+            Board::from_fen("rnbqkbnr/pppppppp/8/5/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").is_err()
+        );
+        assert!(Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP b kq - 0 1").is_err());
+    }
+    #[test]
+    fn test_strict() {
+        let errors = |fen: &str| Board::from_fen(fen).unwrap().validate().unwrap_err();
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "1rqbkrbn/1ppppp1p/1n6/p1N3p1/8/2P4P/PP1PPPP1/1RQBKRBN w FBfb - 0 9",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 0 1",
+        ] {
+            assert!(Board::from_fen_strict(fen).is_ok(), "{}", fen);
+        }
+
+        assert_eq!(
+            errors("8/8/8/8/8/8/8/4K3 w - - 0 1"),
+            [PositionError::KingCount(Color::Black, 0)]
+        );
+        assert_eq!(
+            errors("k7/8/8/8/8/8/8/KK6 w - - 0 1"),
+            [PositionError::KingCount(Color::White, 2)]
+        );
+        assert_eq!(
+            errors("k7/8/8/8/8/8/PPPPPPPP/KP6 w - - 0 1"),
+            [
+                PositionError::TooManyPieces(Color::White),
+                PositionError::PawnOnBackRank(1)
+            ]
+        );
+        assert_eq!(
+            errors("k6p/8/8/8/8/8/8/K7 w - - 0 1"),
+            [PositionError::PawnOnBackRank(63)]
+        );
+        // No pawn in front of the square, the square is on the wrong rank, the pawn couldn't
+        // have come from an occupied square
+        for fen in [
+            "4k3/8/8/8/8/8/8/4K3 b - e3 0 1",
+            "4k3/8/8/8/4P3/8/8/4K3 w - e3 0 1",
+            "4k3/8/8/8/4P3/8/4N3/4K3 b - e3 0 1",
+        ] {
+            assert_eq!(errors(fen), [PositionError::EnPassant(20)], "{}", fen);
+        }
+        assert_eq!(
+            errors("1r2k1r1/8/8/8/8/8/8/R3K2R w KQkq - 0 1"),
+            [PositionError::Castling]
+        );
+        assert_eq!(
+            errors("4k2R/8/8/8/8/8/8/4K3 w - - 0 1"),
+            [PositionError::OpponentInCheck]
+        );
+
+        let error = Board::from_fen_strict("8/8/8/8/8/8/8/4K3 w - e6 0 1").unwrap_err();
+        assert_eq!(
+            error,
+            FenError::InvalidPosition(vec![
+                PositionError::KingCount(Color::Black, 0),
+                PositionError::EnPassant(44)
+            ])
+        );
+        assert_eq!(
+            error.to_string(),
+            "invalid position: black has 0 kings, impossible en passant square: e6"
+        );
+    }
+
+    #[test]
+    fn test_fen_errors() {
+        let error = |fen: &str| Board::from_fen(fen).unwrap_err();
+        let invalid = |field, token: &str| FenError::InvalidField(field, token.to_string());
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w - -"),
+            FenError::MissingField(FenField::HalfMoveClock)
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 x - - 0 1"),
+            invalid(FenField::ActiveColor, "x")
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/4K3 w - - 0 1"),
+            invalid(FenField::Placement, "4k3/8/8/8/8/8/4K3")
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K2 w - - 0 1"),
+            invalid(FenField::Placement, "4K2")
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4X3 w - - 0 1"),
+            FenError::InvalidPiece('X')
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w Kx - 0 1"),
+            invalid(FenField::Castling, "Kx")
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w K - 0 1"),
+            FenError::NoCastlingPieces('K')
+        );
+        for square in ["e9", "x3", "e33"] {
+            let fen = format!("4k3/8/8/8/8/8/8/4K3 w - {} 0 1", square);
+            assert_eq!(error(&fen), invalid(FenField::EnPassant, square));
+        }
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w - - -1 1"),
+            invalid(FenField::HalfMoveClock, "-1")
+        );
+        assert_eq!(
+            error("4k3/8/8/8/8/8/8/4K3 w - - 0 one"),
+            invalid(FenField::FullMoveNumber, "one")
+        );
+        assert_eq!(
+            invalid(FenField::EnPassant, "e9").to_string(),
+            "invalid en passant square: e9"
+        );
+    }
+
     #[test]
     fn test_invalid_bad_piece() {
         assert!(
@@ -1412,4 +4184,28 @@ mod test_fen {
                 .is_err()
         );
     }
+    #[test]
+    fn test_pretty() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+        let plain = board.to_string();
+        assert_eq!(plain, board.pretty(DisplayOptions::default()));
+        let lines: Vec<&str> = plain.lines().collect();
+        assert_eq!(lines[0], "    a b c d e f g h");
+        assert_eq!(lines[2], "8 | . . . . k . . .");
+        assert_eq!(lines[9], "1 | R . . . K . . .");
+
+        let unicode = format!("{:#}", board);
+        assert_eq!(unicode.lines().nth(9), Some("1 | ♖ · · · ♔ · · ·"));
+
+        let options = DisplayOptions {
+            unicode: true,
+            colors: true,
+            labels: false,
+        };
+        let colored = board.pretty(options);
+        let first_rank = colored.lines().nth(7).unwrap();
+        assert!(first_rank.starts_with("\x1b[48;5;137m\x1b[1;97m ♖ \x1b[0m"));
+        assert_eq!(first_rank.matches(RESET).count(), 8);
+        assert!(!colored.contains("a b c"));
+    }
 }
@@ -1,212 +1,770 @@
-use crate::board::Board;
+use crate::board::{Board, DisplayOptions};
+use crate::eval_params::{default_params, EvalParams};
+use crate::evaluator::{ClassicalEval, Evaluator};
+use crate::instant::Instant;
 use crate::misc::Color;
-use crate::play::Play;
+use crate::move_picker::{MoveBuffers, MovePicker};
+use crate::time_manager::{with_buffer, Clock, TimeManager};
 use crate::Game;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
 use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
 use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time;
 
 const CHECKMATE_SCORE: i64 = 800_000;
+// Scores within this distance of CHECKMATE_SCORE are checkmates, the distance is the mate ply
+const MATE_PLY_LIMIT: i64 = 300;
 const MAX_DEPTH: u8 = 20;
+const MAX_PV_LENGTH: usize = 16;
+// Margin below alpha (indexed by depth) at which a static evaluation triggers razoring. Above
+// depth 1 the margin is more than a queen so sacrifices leading to mate are still searched.
+const RAZOR_MARGINS: [i64; 4] = [0, 300, 1200, 1500];
+// Minimum depth at which a PV node with no hash move runs an internal iterative deepening search
+const IID_MIN_DEPTH: u8 = 4;
+// Depth reduction used for the internal iterative deepening search
+const IID_REDUCTION: u8 = 2;
+// Number of legal moves (indexed by depth) searched before the remaining quiet moves are pruned,
+// halved when the static evaluation isn't improving
+const LATE_MOVE_COUNTS: [usize; 4] = [0, 8, 12, 18];
+// Depth below which the static evaluation is recorded to check if a position is improving
+const IMPROVING_MAX_DEPTH: usize = LATE_MOVE_COUNTS.len() + 2;
+// Added to the razoring margin when the static evaluation is improving
+const IMPROVING_RAZOR_MARGIN: i64 = 100;
+// Number of entries in each thread's evaluation cache, a power of two so the key can be masked
+const EVAL_CACHE_SIZE: usize = 1 << 16;
+pub const DEFAULT_HASH_SIZE_MB: usize = 500;
+/// Full strength, lower skill levels sometimes play a move other than the best one
+pub const MAX_SKILL_LEVEL: u8 = 20;
+/// Largest contempt in centipawns, in either direction
+pub const MAX_CONTEMPT: i64 = 200;
+// How many centipawns worse a move can be per skill level below the maximum before it becomes
+// e times less likely to be chosen than the best move
+const SKILL_TEMPERATURE: f64 = 10.0;
 
 pub trait Engine {
-    fn new(board: Board) -> Self;
+    /// The game searched by the engine
+    type Game: Game;
+
+    fn new(board: Self::Game) -> Self;
 
     fn parse_fen(&mut self, fen_string: &str) -> Result<(), String>;
 
     fn should_stop(&self) -> bool;
 
-    fn perft(&mut self);
+    /// Handle which aborts the running search, it can be shared with other threads
+    fn stop_handle(&self) -> StopHandle;
+
+    /// Handle which switches the search from pondering to a normal search, it can be shared with
+    /// other threads
+    fn ponder_handle(&self) -> PonderHandle;
 
-    fn search(&mut self, depth: u8) -> Option<SearchResult>;
+    /// The perft count to `depth` below each legal move in the current position
+    fn perft(&mut self, depth: u8) -> Vec<(MoveOf<Self>, u64)>;
+
+    fn search(&mut self, depth: u8) -> Result<SearchResult<MoveOf<Self>>, SearchError>;
 
     //fn make_move(&mut self, play: &Play);
 
-    fn make_move_str(&mut self, play: &str) -> bool;
+    /// Play a move given in UCI notation, or in SAN if the game supports it
+    fn make_move_str(&mut self, play: &str) -> Result<(), String>;
+
+    /// Resize the transposition table, this clears any existing entries
+    fn set_hash_size(&mut self, megabytes: usize);
+
+    /// Write the transposition table to a file, so a later session can carry on from this one
+    fn save_tt(&self, path: &str) -> Result<(), String>;
+
+    /// Replace the transposition table with one written by save_tt, keeping its size
+    ///
+    /// Files from another version or with keys computed differently are rejected, on any error
+    /// the table is left empty.
+    fn load_tt(&mut self, path: &str) -> Result<(), String>;
+
+    /// Set the number of threads used by the search
+    fn set_threads(&mut self, threads: usize);
+
+    /// Set how strongly the engine plays, from 0 up to MAX_SKILL_LEVEL
+    fn set_skill_level(&mut self, level: u8);
 
-    fn iterative_deepening_search(&mut self, search_options: SearchParameters) -> Play {
-        let mut best_move: Option<Play> = None;
+    /// Evaluate with new parameters from now on, including the current position
+    ///
+    /// Positions set later, e.g. by parse_fen, also use them. The transposition table and
+    /// evaluation cache are cleared as their scores came from the old parameters.
+    fn set_eval_params(&mut self, params: EvalParams);
+
+    /// Set how many centipawns worse than equal a draw is for the side the engine plays
+    ///
+    /// A positive contempt avoids draws, a negative one seeks them.
+    fn set_contempt(&mut self, contempt: i64);
+
+    fn iterative_deepening_search(
+        &mut self,
+        mut search_options: SearchParameters<MoveOf<Self>>,
+    ) -> Result<MoveOf<Self>, SearchError> {
+        // Only returned if the first iteration is stopped, which can't happen with AlphaBeta
+        let mut best_move = Err(SearchError::Stopped);
         let max_depth = match search_options.depth {
             Some(depth) => depth,
             None => MAX_DEPTH,
         };
-        self.configure(search_options.start_time, search_options.search_duration);
+        self.configure(&search_options);
+        let mut nodes = 0;
 
         for depth in 1..=max_depth {
-            let search_result = self.search(depth);
-            if self.should_stop() {
-                return best_move.unwrap();
-            }
-            if let Some(m) = &search_result {
-                best_move = Some(m.best_move);
-                if search_options.print_info {
-                    if let Some(mate_in) = m.checkmate_in() {
-                        println!(
-                            "info depth {} seldepth {} nodes {} score mate {} pv {}",
-                            depth,
-                            m.selective_depth,
-                            m.nodes,
-                            mate_in,
-                            self.pv_line(),
-                        );
-                    } else {
-                        println!(
-                            "info depth {} seldepth {} nodes {} score cp {} pv {}",
-                            depth,
-                            m.selective_depth,
-                            m.nodes,
-                            m.score,
-                            self.pv_line(),
-                            // TODO add search time to this
-                            // TODO add nodes per second
-                        );
-                    }
+            let m = match self.search(depth) {
+                Ok(m) => m,
+                Err(SearchError::Stopped) => break,
+                Err(e) => {
+                    best_move = Err(e);
+                    break;
                 }
-            } else {
-                println!("info string no legal moves identified");
+            };
+            best_move = Ok(m.best_move);
+            nodes += m.nodes;
+            if let Some(on_iteration) = search_options.on_iteration.as_mut() {
+                on_iteration(&SearchInfo {
+                    depth,
+                    selective_depth: m.selective_depth,
+                    score: m.score,
+                    mate: m.checkmate_in(),
+                    nodes,
+                    time: search_options.start_time.elapsed(),
+                    pv: m.pv.clone(),
+                });
+            }
+            // A mate search is finished once a short enough mate is found
+            let mate_found = matches!(
+                (search_options.mate, m.checkmate_in()),
+                (Some(mate), Some(mate_in)) if mate_in > 0 && mate_in <= mate.into()
+            );
+            if mate_found || !self.can_start_iteration() {
+                break;
             }
         }
-        best_move.unwrap()
+        self.finish_search();
+        best_move.map(|m| self.select_move(m))
     }
 
-    fn configure(&mut self, start_time: time::Instant, search_duration: Option<time::Duration>);
+    fn configure(&mut self, search_options: &SearchParameters<MoveOf<Self>>);
+
+    /// True if there is time to start another iteration of iterative deepening
+    fn can_start_iteration(&self) -> bool;
+
+    /// Clean up after a search started with configure, e.g. stopping any helper threads
+    fn finish_search(&mut self);
+
+    /// The move to play after a search, below the maximum skill level this may not be the best
+    fn select_move(&mut self, best_move: MoveOf<Self>) -> MoveOf<Self>;
+
+    /// Print the position, e.g. for the "display" command
+    fn display_board(&self, options: DisplayOptions);
+
+    /// The position the next search starts from
+    fn position(&self) -> &Self::Game;
 
-    fn display_board(&self);
+    fn pv_line(&self) -> PvLine<MoveOf<Self>>;
 
-    fn pv_line(&self) -> PvLine;
+    /// How difficult the last searched position was, based on the completed iterations
+    fn complexity(&self) -> Option<Complexity>;
 
     fn active_color(&self) -> Color;
 }
 
-pub struct SearchParameters {
-    pub depth: Option<u8>,
-    pub search_duration: Option<time::Duration>,
-    pub start_time: time::Instant,
-    pub print_info: bool,
+/// The type of move played by an engine
+pub type MoveOf<E> = <<E as Engine>::Game as Game>::Move;
+
+/// Handle which stops a running search, e.g. from the thread reading UCI commands
+///
+/// Clones share the same flag. The flag isn't cleared when a search starts, so call reset before
+/// starting a search if the handle might have been stopped by a previous one.
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the search, it returns the best move from the last completed iteration
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
-impl SearchParameters {
+/// Handle which switches a search between pondering and searching normally
+///
+/// While pondering it's the opponent's turn on the real board, so the search ignores its time
+/// limits. Start pondering before the search is configured. When the opponent plays the predicted
+/// move call hit, the search then continues with its time limits counted from that point and keeps
+/// everything it has found so far.
+#[derive(Debug, Clone, Default)]
+pub struct PonderHandle(Arc<AtomicBool>);
+
+impl PonderHandle {
     pub fn new() -> Self {
-        Self {
-            depth: None,
-            search_duration: None,
-            start_time: time::Instant::now(),
-            print_info: false,
+        Self::default()
+    }
+
+    pub fn start(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// The opponent played the predicted move, stop pondering
+    pub fn hit(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Reasons a search can fail to produce a move
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    /// The side to move has been checkmated
+    Checkmated,
+    /// The side to move has no legal moves but isn't in check
+    Stalemate,
+    /// The game is already drawn by the fifty move rule or repetition
+    Drawn,
+    /// The search was stopped before an iteration completed
+    Stopped,
+    /// A move couldn't be undone, the board is no longer valid
+    UndoFailed(String),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Checkmated => write!(f, "checkmated, no legal moves"),
+            SearchError::Stalemate => write!(f, "stalemate, no legal moves"),
+            SearchError::Drawn => write!(f, "the game is already drawn"),
+            SearchError::Stopped => write!(f, "search stopped before finding a move"),
+            SearchError::UndoFailed(e) => write!(f, "failed to undo move: {}", e),
         }
     }
+}
 
-    pub fn new_with_depth(depth: u8) -> Self {
-        Self {
-            depth: Some(depth),
-            search_duration: None,
-            start_time: time::Instant::now(),
-            print_info: false,
+impl std::error::Error for SearchError {}
+
+/// Limits and options for a search, created with SearchParameters::builder
+///
+/// Any combination of limits can be set and the search stops at whichever is reached first. With
+/// no limits the search continues to the maximum depth.
+pub struct SearchParameters<M> {
+    depth: Option<u8>,
+    nodes: Option<u64>, // Stop the search after this many nodes
+    move_time: Option<time::Duration>,
+    clock: Option<Clock>,
+    soft_limit: Option<time::Duration>, // Don't start a new iteration after this
+    hard_limit: Option<time::Duration>, // Abort the search after this
+    mate: Option<u8>,                   // Stop once a mate in this many moves is found
+    infinite: bool,                     // Ignore the time limits
+    search_moves: Vec<String>,          // Only search these root moves, all if empty
+    start_time: Instant,
+    quiescence_depth: Option<u8>, // Maximum plies searched in quiescence
+    on_iteration: Option<IterationCallback<M>>,
+}
+
+/// Called with the progress of the search after each completed iteration
+pub type IterationCallback<M> = Box<dyn FnMut(&SearchInfo<M>)>;
+
+impl<M> Default for SearchParameters<M> {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl<M> SearchParameters<M> {
+    pub fn builder() -> SearchParametersBuilder<M> {
+        SearchParametersBuilder {
+            params: Self {
+                depth: None,
+                nodes: None,
+                move_time: None,
+                clock: None,
+                soft_limit: None,
+                hard_limit: None,
+                mate: None,
+                infinite: false,
+                search_moves: Vec::new(),
+                start_time: Instant::now(),
+                quiescence_depth: None,
+                on_iteration: None,
+            },
         }
     }
+
+    /// The time limits for a move by `color`: the soft limit allocated from the clock, which the
+    /// time manager scales, the tightest fixed soft limit from the move time and explicit limits,
+    /// and the tightest hard limit
+    fn time_limits(&self, color: Color) -> [Option<time::Duration>; 3] {
+        if self.infinite {
+            return [None, None, None];
+        }
+        let move_time = self.move_time.map(with_buffer);
+        let clock = self.clock.and_then(|c| c.limits(color));
+        let min = |limits: &[Option<time::Duration>]| limits.iter().flatten().min().copied();
+        [
+            clock.map(|c| c.0),
+            min(&[move_time, self.soft_limit]),
+            min(&[move_time, clock.map(|c| c.1), self.hard_limit]),
+        ]
+    }
+}
+
+/// Builds SearchParameters, every limit is optional
+pub struct SearchParametersBuilder<M> {
+    params: SearchParameters<M>,
+}
+
+impl<M> SearchParametersBuilder<M> {
+    /// Maximum depth of iterative deepening
+    pub fn depth(mut self, depth: u8) -> Self {
+        self.params.depth = Some(depth);
+        self
+    }
+
+    /// Maximum number of nodes searched
+    pub fn nodes(mut self, nodes: u64) -> Self {
+        self.params.nodes = Some(nodes);
+        self
+    }
+
+    /// Search for this long
+    pub fn move_time(mut self, move_time: time::Duration) -> Self {
+        self.params.move_time = Some(move_time);
+        self
+    }
+
+    /// Allocate time from the clock of the side to move
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.params.clock = Some(clock);
+        self
+    }
+
+    /// Explicit limits, no iteration starts after `soft` and the search is aborted at `hard`
+    pub fn time_limits(mut self, soft: time::Duration, hard: time::Duration) -> Self {
+        self.params.soft_limit = Some(soft);
+        self.params.hard_limit = Some(hard);
+        self
+    }
+
+    /// Stop once a mate in `moves` moves or fewer is found
+    pub fn mate(mut self, moves: u8) -> Self {
+        self.params.mate = Some(moves);
+        self
+    }
+
+    /// Ignore the time limits, search until stopped or another limit is reached
+    pub fn infinite(mut self) -> Self {
+        self.params.infinite = true;
+        self
+    }
+
+    /// Only search these moves from the root, written as the engine displays its moves
+    ///
+    /// Moves which aren't legal are ignored, if none are legal every move is searched.
+    pub fn search_moves(mut self, moves: Vec<String>) -> Self {
+        self.params.search_moves = moves;
+        self
+    }
+
+    /// When the search started, time limits count from here (defaults to when builder was called)
+    pub fn start_time(mut self, start_time: Instant) -> Self {
+        self.params.start_time = start_time;
+        self
+    }
+
+    /// Call `on_iteration` with the progress of the search after each completed iteration
+    pub fn on_iteration(mut self, on_iteration: impl FnMut(&SearchInfo<M>) + 'static) -> Self {
+        self.params.on_iteration = Some(Box::new(on_iteration));
+        self
+    }
+
+    /// Maximum plies searched in quiescence
+    pub fn quiescence_depth(mut self, depth: u8) -> Self {
+        self.params.quiescence_depth = Some(depth);
+        self
+    }
+
+    pub fn build(self) -> SearchParameters<M> {
+        self.params
+    }
 }
 
-pub struct AlphaBeta {
-    pub board: Board,
+pub struct AlphaBeta<G: Game = Board> {
+    pub board: G,
     nodes: u64,
+    qnodes: u64, // Nodes searched in quiescence, included in nodes
     score: i64,
-    moves: HashTable,
+    moves: Arc<HashTable<G>>,
+    eval_params: Arc<EvalParams>, // Given to each position the engine is set to
     selective_depth: u8,
     // search parameters
     search_depth: u8,
+    threads: usize,
+    quiescence_depth: Option<u8>,
+    skill_level: u8,
+    node_limit: Option<u64>,
+    contempt: i64,
     // search state
-    start_time: time::Instant,
-    search_duration: Option<time::Duration>,
+    root_color: Color, // side to move at the root, the side contempt applies to
+    time_manager: TimeManager,
+    stop: StopHandle,
+    ponder: PonderHandle,
+    pondering: bool, // the time limits are ignored until the ponder handle is hit
     should_stop: bool,
+    search_nodes: u64, // nodes searched by every iteration since configure
+    iterations: Vec<(G::Move, i64)>, // best move and score for each completed iteration
+    root_moves: RootMoves<G::Move>,
+    evaluator: Arc<dyn Evaluator<G>>,
+    eval_cache: EvalCache,
+    /// Static evaluation at each ply of the current line, None in check
+    static_evals: Vec<Option<i64>>,
+    killers: Vec<[Option<G::Move>; 2]>, // quiet moves which caused a cutoff at each ply
+    move_buffers: Vec<MoveBuffers<G::Move>>, // the move picker's lists at each ply
+    helpers: Vec<thread::JoinHandle<()>>,
+    helper_stop: StopHandle,
 }
 
-impl AlphaBeta {
-    fn eval(&self) -> i64 {
-        self.board.eval()
+impl<G: Game> AlphaBeta<G> {
+    /// An engine with a transposition table of `megabytes` rather than the default size, so a
+    /// smaller table doesn't need the default one allocating first
+    pub fn with_hash_size(board: G, megabytes: usize) -> Self {
+        let root_color = board.active_color();
+        Self {
+            board,
+            nodes: 0,
+            qnodes: 0,
+            score: 0,
+            moves: Arc::new(HashTable::with_capacity_bytes(megabytes * 1024 * 1024)),
+            eval_params: default_params(),
+            search_depth: 0,
+            selective_depth: 0,
+            threads: 1,
+            quiescence_depth: None,
+            skill_level: MAX_SKILL_LEVEL,
+            node_limit: None,
+            contempt: 0,
+            root_color,
+            time_manager: TimeManager::new(Instant::now(), None, None),
+            stop: StopHandle::new(),
+            ponder: PonderHandle::new(),
+            pondering: false,
+            should_stop: false,
+            search_nodes: 0,
+            iterations: Vec::new(),
+            root_moves: RootMoves::default(),
+            evaluator: Arc::new(ClassicalEval),
+            eval_cache: EvalCache::new(),
+            static_evals: Vec::new(),
+            killers: Vec::new(),
+            move_buffers: Vec::new(),
+            helpers: Vec::new(),
+            helper_stop: StopHandle::new(),
+        }
+    }
+
+    fn eval(&mut self) -> i64 {
+        let key = self.board.key();
+        if let Some(score) = self.eval_cache.get(key) {
+            return score;
+        }
+        let score = self.evaluator.eval(&self.board);
+        self.eval_cache.set(key, score);
+        score
+    }
+
+    fn killers(&self) -> [Option<G::Move>; 2] {
+        self.killers
+            .get(self.board.line_ply())
+            .copied()
+            .unwrap_or([None, None])
+    }
+
+    /// Remember a quiet move which caused a cutoff, it's tried early in sibling positions
+    fn store_killer(&mut self, play: G::Move) {
+        let ply = self.board.line_ply();
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None, None]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(play) {
+            killers[1] = killers[0];
+            killers[0] = Some(play);
+        }
+    }
+
+    /// The move picker's lists for the current ply, empty the first time the ply is reached
+    fn take_move_buffers(&mut self) -> MoveBuffers<G::Move> {
+        let ply = self.board.line_ply();
+        self.move_buffers
+            .get_mut(ply)
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Keep a picker's lists for the next node at the current ply
+    ///
+    /// Lists which aren't given back, e.g. when the search is stopped, are allocated again.
+    fn give_back_move_buffers(&mut self, picker: MovePicker<G::Move>) {
+        let ply = self.board.line_ply();
+        if self.move_buffers.len() <= ply {
+            self.move_buffers.resize_with(ply + 1, MoveBuffers::default);
+        }
+        self.move_buffers[ply] = picker.into_buffers();
+    }
+
+    /// Record the static evaluation at the current ply and check if it is better than two plies
+    /// earlier, when the side to move last had a move
+    ///
+    /// With nothing to compare against, e.g. in check or near the root, the position is treated as
+    /// improving so it isn't pruned more aggressively.
+    fn is_improving(&mut self, static_eval: Option<i64>) -> bool {
+        let ply = self.board.line_ply();
+        if self.static_evals.len() <= ply {
+            self.static_evals.resize(ply + 1, None);
+        }
+        self.static_evals[ply] = static_eval;
+        match (
+            static_eval,
+            ply.checked_sub(2).map(|p| self.static_evals[p]),
+        ) {
+            (Some(eval), Some(Some(previous))) => eval > previous,
+            _ => true,
+        }
     }
 
     pub fn clear_cache(&mut self) {
         self.moves.clear();
     }
 
+    /// Replace the static evaluation, ClassicalEval by default
+    ///
+    /// The transposition table is cleared as its scores came from the old evaluation.
+    pub fn set_evaluator<E: Evaluator<G> + 'static>(&mut self, evaluator: E) {
+        self.evaluator = Arc::new(evaluator);
+        self.eval_cache = EvalCache::new();
+        self.clear_cache();
+    }
+
+    /// Start helper threads which search the same position, sharing the transposition table
+    /// (Lazy SMP)
+    ///
+    /// Only the main thread's result is used, the helpers fill the table with results which
+    /// speed up the main search. Every other helper starts a ply deeper so the threads spread
+    /// across depths rather than all searching the same tree.
+    fn start_helpers(&mut self) {
+        self.helper_stop.reset();
+        for id in 1..self.threads {
+            let mut helper = AlphaBeta {
+                board: self.board.clone(),
+                nodes: 0,
+                qnodes: 0,
+                score: 0,
+                moves: Arc::clone(&self.moves),
+                eval_params: Arc::clone(&self.eval_params),
+                search_depth: 0,
+                selective_depth: 0,
+                threads: 1,
+                quiescence_depth: self.quiescence_depth,
+                skill_level: MAX_SKILL_LEVEL,
+                node_limit: None,
+                contempt: self.contempt,
+                root_color: self.root_color,
+                time_manager: TimeManager::new(Instant::now(), None, None),
+                stop: self.helper_stop.clone(),
+                ponder: PonderHandle::new(),
+                pondering: false,
+                should_stop: false,
+                search_nodes: 0,
+                iterations: Vec::new(),
+                root_moves: RootMoves::default(),
+                evaluator: Arc::clone(&self.evaluator),
+                eval_cache: EvalCache::new(),
+                static_evals: Vec::new(),
+                killers: Vec::new(),
+                move_buffers: Vec::new(),
+                helpers: Vec::new(),
+                helper_stop: StopHandle::new(),
+            };
+            self.helpers.push(thread::spawn(move || {
+                for depth in (1 + (id % 2) as u8)..=MAX_DEPTH {
+                    if helper.search(depth).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+    }
+
+    fn stop_helpers(&mut self) {
+        self.helper_stop.stop();
+        for helper in self.helpers.drain(..) {
+            helper.join().expect("search helper thread panicked");
+        }
+    }
+
+    /// The transposition table entry for the current position
+    ///
+    /// Entries whose move isn't possible in the position came from a hash collision and are
+    /// ignored.
+    fn probe(&self) -> Option<Pv<G::Move>> {
+        self.moves
+            .get(self.board.key(), self.board.line_ply())
+            .filter(|pv| self.board.is_pseudo_legal(&pv.play))
+    }
+
     fn check_if_should_stop(&mut self) {
-        if let Some(search_time) = self.search_duration {
-            self.should_stop = self.start_time.elapsed() >= search_time;
+        self.check_ponder_hit();
+        // Always complete the first iteration so there is a move to play
+        if self.search_depth > 1 {
+            let time_up = !self.pondering && self.time_manager.is_time_up();
+            self.should_stop = self.should_stop || time_up || self.stop.is_stopped();
         }
     }
 
-    fn quiescence(&mut self, mut alpha: i64, beta: i64) -> i64 {
-        self.selective_depth = self.selective_depth.max(self.board.line_ply as u8);
-        if self.board.line_ply >= MAX_DEPTH.into() {
-            return self.eval();
+    /// Start the clock once the opponent plays the move being pondered on
+    fn check_ponder_hit(&mut self) {
+        if self.pondering && !self.ponder.is_pondering() {
+            self.pondering = false;
+            self.time_manager.restart(Instant::now());
         }
+    }
 
-        if self.nodes % 3000 == 0 {
-            self.check_if_should_stop();
+    /// Score of a draw for the side to move
+    fn draw_score(&self) -> i64 {
+        if self.board.active_color() == self.root_color {
+            -self.contempt
+        } else {
+            self.contempt
         }
+    }
+
+    fn node_limit_reached(&self) -> bool {
+        self.node_limit
+            .is_some_and(|limit| self.search_nodes >= limit)
+    }
+
+    /// Count a searched node, stopping the search once the node limit is reached
+    ///
+    /// The limit is checked at every node so a single threaded search with a node limit always
+    /// plays the same move.
+    fn count_node(&mut self) {
         self.nodes += 1;
+        self.search_nodes += 1;
+        if self.search_depth > 1 && self.node_limit_reached() {
+            self.should_stop = true;
+        }
+    }
+
+    fn undo_move(&mut self) -> Result<(), SearchError> {
+        self.board
+            .undo_move()
+            .map(|_| ())
+            .map_err(|e| SearchError::UndoFailed(e.to_string()))
+    }
+
+    /// Search captures and promotions until the position is quiet
+    ///
+    /// `ply` counts the plies since quiescence started. At the first ply quiet moves which give
+    /// check are also searched, and a side in check searches all of its moves so mates at the
+    /// horizon are found.
+    fn quiescence(&mut self, mut alpha: i64, beta: i64, ply: u8) -> Result<i64, SearchError> {
+        self.selective_depth = self.selective_depth.max(self.board.line_ply() as u8);
+        if self.board.line_ply() >= MAX_DEPTH.into()
+            || self.quiescence_depth.is_some_and(|max| ply >= max)
+        {
+            return Ok(self.eval());
+        }
 
-        let score = self.eval();
-        if score >= beta {
-            return beta;
-        } else if score >= alpha {
-            alpha = score;
+        if self.nodes.is_multiple_of(3000) {
+            self.check_if_should_stop();
+        }
+        self.count_node();
+        self.qnodes += 1;
+
+        // A side in check can't stand pat as it might be checkmated
+        let in_check = self.board.in_check();
+        if !in_check {
+            let score = self.eval();
+            if score >= beta {
+                return Ok(beta);
+            } else if score >= alpha {
+                alpha = score;
+            }
         }
 
-        let mut best_move: Option<Play> = None;
-        let mut best_board: Option<u64> = None;
+        let mut best_move: Option<G::Move> = None;
         let old_alpha = alpha;
         let mut score: i64;
-        let pv_line = self.moves.get(self.board.key);
-        let mut moves = self.board.generate_captures();
-        moves.sort_by_cached_key(|m| {
-            let mut score = m.mmv_lva(&self.board);
-            if let Some(pv) = pv_line {
-                if pv.play == *m {
-                    score += 100000;
-                }
-            };
-            -(score as i64)
-        });
+        let mut found_legal_move = false;
+        let hash_move = self.probe().map(|pv| pv.play);
+        let buffers = self.take_move_buffers();
+        let mut picker = match in_check {
+            true => MovePicker::new(hash_move, [None, None], buffers),
+            false => MovePicker::quiescence(hash_move, ply == 0, buffers),
+        };
 
-        for m in &moves {
-            if self.board.make_move(m) {
-                score = -self.quiescence(-beta, -alpha);
+        while let Some(m) = picker.next(&self.board) {
+            if self.board.make_move(&m) {
+                found_legal_move = true;
+                score = -self.quiescence(-beta, -alpha, ply + 1)?;
                 if score > alpha {
                     if score >= beta {
-                        self.board.undo_move().unwrap();
-                        return beta;
+                        self.undo_move()?;
+                        self.give_back_move_buffers(picker);
+                        return Ok(beta);
                     }
                     alpha = score;
-                    best_move = Some(*m);
-                    best_board = Some(self.board.key);
+                    best_move = Some(m);
                 }
-                self.board.undo_move().unwrap();
+                self.undo_move()?;
                 if self.should_stop {
-                    // TODO return an error instead
-                    return 0;
+                    return Err(SearchError::Stopped);
                 }
             }
         }
+        self.give_back_move_buffers(picker);
+
+        if in_check && !found_legal_move {
+            return Ok(-CHECKMATE_SCORE + (self.board.line_ply() as i64));
+        }
 
         if alpha != old_alpha {
             self.moves.set(
-                self.board.key,
+                self.board.key(),
                 Pv {
                     play: best_move.unwrap(),
-                    next_key: best_board.unwrap(),
                     score: alpha,
                     depth: 0, // Never use a quiescence move instead of evaluating, only for move ordering
                     node: Node::Ordering,
-                    ply: self.board.ply,
                 },
+                self.board.line_ply(),
             );
         }
-        alpha
+        Ok(alpha)
     }
 
-    fn get_transposition(&self, key: u64, alpha: i64, beta: i64, depth: u8) -> (Option<&Pv>, bool) {
-        let pv = self.moves.get(key);
-        if let Some(pv) = pv {
+    fn get_transposition(&self, alpha: i64, beta: i64, depth: u8) -> (Option<Pv<G::Move>>, bool) {
+        if let Some(pv) = self.probe() {
             if pv.depth >= depth.into() {
                 match pv.node {
                     Node::Exact => return (Some(pv), true),
@@ -229,125 +787,319 @@ impl AlphaBeta {
         (None, false)
     }
 
-    fn alpha_beta(&mut self, mut alpha: i64, beta: i64, mut depth: u8) -> i64 {
-        if self.nodes % 3000 == 0 {
+    fn alpha_beta(&mut self, mut alpha: i64, beta: i64, mut depth: u8) -> Result<i64, SearchError> {
+        if self.nodes.is_multiple_of(3000) {
             self.check_if_should_stop();
         }
-        self.selective_depth = self.selective_depth.max(self.board.line_ply as u8);
-        self.nodes += 1;
+        self.selective_depth = self.selective_depth.max(self.board.line_ply() as u8);
+        self.count_node();
 
-        if self.board.fifty_move_rule >= 100 || self.board.is_repetition() {
-            return 0;
+        if self.board.is_draw() {
+            return Ok(self.draw_score());
         }
-        let in_check = self.board.is_king_attacked();
+        let in_check = self.board.in_check();
         if in_check {
             depth += 1;
         }
 
         if depth == 0 {
             if self.search_depth >= 4 {
-                return self.quiescence(alpha, beta);
+                return self.quiescence(alpha, beta, 0);
             }
-            return self.eval();
+            return Ok(self.eval());
         }
 
         let old_alpha = alpha;
         let mut score: i64;
         let mut found_legal_move = false;
-        let mut best_move: Option<&Play> = None;
-        let mut best_board: Option<u64> = None;
-        let (pv_line, cutoff) = self.get_transposition(self.board.key, alpha, beta, depth);
+        let mut best_move: Option<G::Move> = None;
+        let (pv_line, cutoff) = self.get_transposition(alpha, beta, depth);
         if cutoff {
-            return pv_line.unwrap().score;
+            return Ok(pv_line.unwrap().score);
         }
 
-        let mut moves = self.board.generate_moves();
-        moves.sort_by_cached_key(|m| {
-            let mut score = m.mmv_lva(&self.board);
-            if let Some(pv) = pv_line {
-                if pv.play == *m {
-                    score += 100_000;
+        // Only needed where the pruning happens, and two plies above so there is a comparison
+        let static_eval = match in_check || depth as usize >= IMPROVING_MAX_DEPTH {
+            true => None,
+            false => Some(self.eval()),
+        };
+        let improving = self.is_improving(static_eval);
+
+        // Razoring: if the position looks hopeless at low depth confirm with a quiescence search
+        // and prune if it is still hopeless, unless it is improving
+        if !in_check && self.board.line_ply() > 0 && (depth as usize) < RAZOR_MARGINS.len() {
+            let mut margin = RAZOR_MARGINS[depth as usize];
+            if improving {
+                margin += IMPROVING_RAZOR_MARGIN;
+            }
+            if self.eval() + margin <= alpha {
+                if depth == 1 {
+                    return self.quiescence(alpha, beta, 0);
                 }
-            };
-            -(score as i64)
-        });
+                let razor_alpha = alpha - margin;
+                let score = self.quiescence(razor_alpha, razor_alpha + 1, 0)?;
+                if score <= razor_alpha {
+                    return Ok(score);
+                }
+            }
+        }
+
+        // Internal iterative deepening: with no hash move to try first at a PV node run a shallower
+        // search to find one, so the full depth search starts with a good move
+        let root = self.board.line_ply() == 0;
+        let mut hash_move = self.probe();
+        // The window can be as wide as i64 allows, so the width mustn't overflow
+        let pv_node = beta.saturating_sub(alpha) > 1;
+        if !root && hash_move.is_none() && pv_node && depth >= IID_MIN_DEPTH {
+            self.alpha_beta(alpha, beta, depth - IID_REDUCTION)?;
+            hash_move = self.probe();
+        }
+
+        // The root moves are ordered by the results of the previous iteration
+        let buffers = self.take_move_buffers();
+        let mut picker = match root {
+            true => MovePicker::from_moves(self.root_moves.plays(), buffers),
+            false => MovePicker::new(hash_move.map(|pv| pv.play), self.killers(), buffers),
+        };
 
-        for m in &moves {
-            if self.board.make_move(m) {
+        // Below the maximum skill level every root move is searched with a full window, so each
+        // has an exact score to weight the choice of move by
+        let full_window = root && self.skill_level < MAX_SKILL_LEVEL;
+        // Late move pruning: near the leaves quiet moves ordered after the first few are unlikely
+        // to raise alpha so are skipped, unless they give check. Fewer are searched when the
+        // position is getting worse.
+        let prune_late_moves = !root && !in_check && (depth as usize) < LATE_MOVE_COUNTS.len();
+        let late_move_count = match LATE_MOVE_COUNTS.get(depth as usize) {
+            Some(&count) if improving => count,
+            Some(&count) => count / 2,
+            None => 0,
+        };
+        let mut legal_moves = 0;
+        while let Some(m) = picker.next(&self.board) {
+            // The child probes the table straight away, so start loading its entry now
+            self.moves.prefetch(self.board.key_after(&m));
+            let quiet = self.board.is_quiet(&m);
+            if self.board.make_move(&m) {
                 found_legal_move = true;
-                score = -self.alpha_beta(-beta, -alpha, depth - 1);
+                legal_moves += 1;
+                if prune_late_moves
+                    && legal_moves > late_move_count
+                    && quiet
+                    && !self.board.in_check()
+                {
+                    self.undo_move()?;
+                    continue;
+                }
+                let nodes = self.nodes;
+                let move_alpha = if full_window { i64::MIN + 1 } else { alpha };
+                score = -self.alpha_beta(-beta, -move_alpha, depth - 1)?;
+                if root && !self.should_stop {
+                    self.root_moves.record(&m, score, self.nodes - nodes);
+                }
                 if score > alpha {
                     best_move = Some(m);
-                    best_board = Some(self.board.key);
                     if score >= beta {
-                        self.board.undo_move().unwrap();
+                        self.undo_move()?;
+                        self.give_back_move_buffers(picker);
+                        if quiet {
+                            self.store_killer(m);
+                        }
                         self.moves.set(
-                            self.board.key,
+                            self.board.key(),
                             Pv {
-                                play: *best_move.unwrap(),
-                                next_key: best_board.unwrap(),
+                                play: m,
                                 depth: depth as usize,
                                 score: beta,
                                 node: Node::Beta,
-                                ply: self.board.ply,
                             },
+                            self.board.line_ply(),
                         );
-                        return beta;
+                        return Ok(beta);
                     }
                     alpha = score;
                 }
-                self.board.undo_move().unwrap();
+                self.undo_move()?;
                 if self.should_stop {
-                    // TODO return an error instead
-                    return 0;
+                    return Err(SearchError::Stopped);
                 }
             }
         }
+        self.give_back_move_buffers(picker);
+
+        if root {
+            self.root_moves.sort(best_move);
+        }
 
         if !found_legal_move {
             if in_check {
-                return -CHECKMATE_SCORE + (self.board.line_ply as i64);
+                return Ok(-CHECKMATE_SCORE + (self.board.line_ply() as i64));
             }
-            return 0;
+            return Ok(self.draw_score());
         }
 
         if alpha != old_alpha {
             self.moves.set(
-                self.board.key,
+                self.board.key(),
                 Pv {
-                    play: *best_move.unwrap(),
-                    next_key: best_board.unwrap(),
+                    play: best_move.unwrap(),
                     depth: depth as usize,
                     score: alpha,
                     node: Node::Exact,
-                    ply: self.board.ply,
                 },
+                self.board.line_ply(),
             );
-        } else if let Some(&bm) = best_move {
+        } else if let Some(bm) = best_move {
             self.moves.set(
-                self.board.key,
+                self.board.key(),
                 Pv {
                     play: bm,
-                    next_key: best_board.unwrap(),
                     depth: depth as usize,
                     score: alpha,
                     node: Node::Alpha,
-                    ply: self.board.ply,
                 },
+                self.board.line_ply(),
             );
         }
-        alpha
+        Ok(alpha)
+    }
+}
+
+/// Cache of static evaluations keyed by the position key
+///
+/// Each search thread has its own cache, on a collision the old entry is replaced.
+#[derive(Debug)]
+struct EvalCache {
+    table: Vec<Option<(u64, i64)>>,
+}
+
+impl EvalCache {
+    fn new() -> Self {
+        Self {
+            table: vec![None; EVAL_CACHE_SIZE],
+        }
+    }
+
+    fn get(&self, key: u64) -> Option<i64> {
+        match self.table[key as usize & (EVAL_CACHE_SIZE - 1)] {
+            Some((k, score)) if k == key => Some(score),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, key: u64, score: i64) {
+        self.table[key as usize & (EVAL_CACHE_SIZE - 1)] = Some((key, score));
+    }
+}
+
+/// A legal move in the root position and the result of searching it in the last iteration
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct RootMove<M> {
+    play: M,
+    score: i64, // An upper bound unless this was the best move
+    nodes: u64, // Size of the subtree searched for the move
+}
+
+/// The moves in the root position, kept across iterations of iterative deepening
+///
+/// Each iteration searches the previous best move first followed by the moves with the largest
+/// subtrees, which are most likely to become the best move.
+#[derive(Debug)]
+struct RootMoves<M> {
+    key: u64,
+    moves: Vec<RootMove<M>>,
+}
+
+impl<M> Default for RootMoves<M> {
+    fn default() -> Self {
+        Self {
+            key: 0,
+            moves: Vec::new(),
+        }
+    }
+}
+
+impl<M: Copy + Eq> RootMoves<M> {
+    fn new<G: Game<Move = M>>(board: &G) -> Self {
+        let mut moves: Vec<RootMove<M>> = board
+            .legal_plays()
+            .into_iter()
+            .map(|play| RootMove {
+                play,
+                score: 0,
+                nodes: 0,
+            })
+            .collect();
+        // Captures first until there are node counts to go on
+        moves.sort_by_cached_key(|m| -board.order_score(&m.play));
+        Self {
+            key: board.key(),
+            moves,
+        }
+    }
+
+    fn plays(&self) -> Vec<M> {
+        self.moves.iter().map(|m| m.play).collect()
+    }
+
+    /// Only keep the moves named in `search_moves`, unless none of them match
+    fn restrict(&mut self, search_moves: &[String])
+    where
+        M: fmt::Display,
+    {
+        let matches = |m: &RootMove<M>| {
+            let play = m.play.to_string();
+            search_moves.iter().any(|s| s.eq_ignore_ascii_case(&play))
+        };
+        if self.moves.iter().any(matches) {
+            self.moves.retain(matches);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    fn contains(&self, play: &M) -> bool {
+        self.moves.iter().any(|m| m.play == *play)
+    }
+
+    fn record(&mut self, play: &M, score: i64, nodes: u64) {
+        if let Some(m) = self.moves.iter_mut().find(|m| m.play == *play) {
+            m.score = score;
+            m.nodes = nodes;
+        }
+    }
+
+    fn sort(&mut self, best_move: Option<M>) {
+        self.moves
+            .sort_by_key(|m| (Some(m.play) != best_move, std::cmp::Reverse(m.nodes)));
+    }
+
+    /// Pick a move at random, moves scoring close to the best are the most likely to be picked
+    ///
+    /// The lower the skill level the more likely a worse move is, at MAX_SKILL_LEVEL only the
+    /// best move is picked.
+    fn choose<R: Rng>(&self, skill_level: u8, rng: &mut R) -> Option<M> {
+        let best = self.moves.iter().max_by_key(|m| m.score)?;
+        if skill_level >= MAX_SKILL_LEVEL {
+            return Some(best.play);
+        }
+        let temperature = (MAX_SKILL_LEVEL - skill_level) as f64 * SKILL_TEMPERATURE;
+        let weights = self
+            .moves
+            .iter()
+            .map(|m| (-((best.score - m.score) as f64) / temperature).exp());
+        let index = WeightedIndex::new(weights).ok()?;
+        Some(self.moves[index.sample(rng)].play)
     }
 }
 
 #[derive(Copy, Clone, Debug)]
-struct Pv {
-    next_key: u64,
-    play: Play,
+struct Pv<M> {
+    play: M,
     score: i64,
     depth: usize,
     node: Node,
-    ply: usize,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -359,105 +1111,770 @@ enum Node {
     Ordering,
 }
 
-#[derive(Debug)]
-struct HashTable {
-    table: Vec<Option<(Pv, u64)>>,
-    capacity: usize,
+// Number of depth-preferred entries sharing a single hash table index
+const BUCKET_SIZE: usize = 4;
+// Index of the entry in each bucket which takes whatever the depth-preferred entries refuse
+const ALWAYS_REPLACE: usize = BUCKET_SIZE;
+// Weight of each search generation an entry is out of date by when choosing one to replace
+const AGE_WEIGHT: i64 = 8;
+
+// A saved transposition table is the magic bytes, the format version, Game::key_check and the
+// number of entries, followed by the entries. Each entry is its key, the move from
+// Game::encode_move, the score, the depth and the node type, all little endian.
+const TT_FILE_MAGIC: &[u8; 8] = b"ARCHETT\0";
+// Bump when the format changes, tables saved in another version aren't loaded
+const TT_FILE_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug)]
+struct Entry<M> {
+    key: u64,
+    pv: Pv<M>,
+    generation: u8, // The search which stored this entry
 }
 
-impl HashTable {
-    fn with_capacity(capacity: usize) -> Self {
-        Self {
-            table: vec![None; capacity as usize],
-            capacity,
-        }
-    }
+// Set in the info word of every stored entry, so an empty slot is all zeros
+const OCCUPIED: u64 = 1 << 24;
+
+/// An entry packed into atomics so search threads can share the table without locking it
+///
+/// `check` is the key xor the two data words. An entry torn by two threads writing it at once
+/// doesn't match its key, so it is ignored like an empty slot.
+#[derive(Debug, Default)]
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64, // The move from Game::encode_move and the score
+    info: AtomicU64, // The depth, node type, generation and OCCUPIED
+}
 
-    fn clear(&mut self) {
-        self.table = vec![None; self.capacity as usize];
+impl Slot {
+    /// The key and data words, None if the slot is empty
+    fn words(&self) -> Option<(u64, u64, u64)> {
+        let data = self.data.load(Ordering::Relaxed);
+        let info = self.info.load(Ordering::Relaxed);
+        let key = self.check.load(Ordering::Relaxed) ^ data ^ info;
+        (info & OCCUPIED != 0).then_some((key, data, info))
     }
 
-    fn with_capacity_bytes(bytes: usize) -> Self {
-        let entry_size = mem::size_of::<u64>() + mem::size_of::<Pv>();
-        Self::with_capacity(bytes / entry_size)
+    fn load<G: Game>(&self) -> Option<Entry<G::Move>> {
+        let (key, data, info) = self.words()?;
+        Self::unpack::<G>(key, data, info)
     }
 
-    fn get(&self, key: u64) -> Option<&Pv> {
-        let index = (key % self.capacity as u64) as usize;
-        if let Some((pv, k)) = &self.table[index] {
-            if *k == key {
-                return Some(pv);
-            }
-        }
-        None
+    fn unpack<G: Game>(key: u64, data: u64, info: u64) -> Option<Entry<G::Move>> {
+        let node = match (info >> 8) & 0xFF {
+            0 => Node::Exact,
+            1 => Node::Alpha,
+            2 => Node::Beta,
+            _ => Node::Ordering,
+        };
+        Some(Entry {
+            key,
+            pv: Pv {
+                play: G::decode_move(data as u32)?,
+                score: (data >> 32) as i32 as i64,
+                depth: (info & 0xFF) as usize,
+                node,
+            },
+            generation: (info >> 16) as u8,
+        })
     }
 
-    fn clear_key(&mut self, key: u64) {
-        let index = (key % self.capacity as u64) as usize;
-        self.table[index] = None;
-    }
-
-    fn set(&mut self, key: u64, pv: Pv) {
-        let index = (key % self.capacity as u64) as usize;
-        if let Some((old_pv, _)) = self.table[index] {
-            // if new is exact and old isn't replace
-            if matches!(pv.node, Node::Exact) {
-                self.table[index] = Some((pv, key));
-                return;
-            } else if !matches!(old_pv.node, Node::Exact) {
-                self.table[index] = Some((pv, key));
-                return;
-            } else if (pv.ply as isize - old_pv.ply as isize) > (MAX_DEPTH as isize + 3) {
-                self.table[index] = Some((pv, key));
-                return;
-            }
+    /// Store an entry, entries whose move can't be encoded aren't stored
+    fn store<G: Game>(&self, entry: &Entry<G::Move>) {
+        let Some(play) = G::encode_move(&entry.pv.play) else {
             return;
-        }
-        self.table[index] = Some((pv, key));
+        };
+        // Bounds from a full window can be beyond any real score, they stay beyond it
+        let score = entry.pv.score.clamp(i32::MIN.into(), i32::MAX.into()) as i32;
+        let data = play as u64 | (score as u32 as u64) << 32;
+        let node = match entry.pv.node {
+            Node::Exact => 0,
+            Node::Alpha => 1,
+            Node::Beta => 2,
+            Node::Ordering => 3,
+        };
+        let info = entry.pv.depth.min(u8::MAX.into()) as u64
+            | node << 8
+            | (entry.generation as u64) << 16
+            | OCCUPIED;
+        self.data.store(data, Ordering::Relaxed);
+        self.info.store(info, Ordering::Relaxed);
+        self.check.store(entry.key ^ data ^ info, Ordering::Relaxed);
     }
-}
-
-pub struct PvLine {
-    line: Vec<Play>,
-}
 
-impl fmt::Display for PvLine {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let out: Vec<String> = self.line.iter().map(|p| format!("{}", p)).collect();
-        let new = out.join(" ");
-        write!(f, "{}", new)?;
-        Ok(())
+    fn clear(&self) {
+        self.info.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+        self.check.store(0, Ordering::Relaxed);
     }
 }
 
+// Aligned so a bucket never straddles more cache lines than it has to
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Bucket([Slot; BUCKET_SIZE + 1]);
+
+/// Transposition table made of buckets of entries
+///
+/// A key can be stored in any of the depth-preferred entries of its bucket. When they are full the
+/// entry least worth keeping is replaced, shallow entries, entries from earlier searches and
+/// entries without an exact score are replaced first. Results which aren't worth more than any of
+/// the depth-preferred entries go in the bucket's always-replace entry instead, so recent shallow
+/// results still help move ordering without pushing out deep results.
+///
+/// Entries are lockless (see Slot) so the table can be shared between search threads. Two threads
+/// storing into the same bucket at once can lose one of the results, which only costs a re-search.
+/// Only moves Game::encode_move can pack are stored.
 #[derive(Debug)]
-pub struct SearchResult {
-    nodes: u64,          // The number of results examined as part of the search
-    selective_depth: u8, // Selective search depth in plies
-    best_move: Play,     // The best move found as part of the search
-    score: i64,          // The estimated score for the best move if played
+struct HashTable<G> {
+    table: Vec<Bucket>,
+    generation: AtomicU8,
+    game: PhantomData<fn() -> G>,
 }
 
-impl SearchResult {
-    fn checkmate_in(&self) -> Option<i64> {
-        if (CHECKMATE_SCORE - self.score.abs()) < 300 {
-            let mut mate = (CHECKMATE_SCORE - self.score.abs() + 1) / 2;
-            if self.score < 0 {
-                mate = -mate;
-            };
-            return Some(mate);
+impl<G: Game> HashTable<G> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            table: (0..capacity).map(|_| Bucket::default()).collect(),
+            generation: AtomicU8::new(0),
+            game: PhantomData,
         }
-        None
     }
-}
 
-#[cfg(test)]
-mod test_search {
-    use super::AlphaBeta;
+    fn clear(&self) {
+        for bucket in &self.table {
+            bucket.0.iter().for_each(Slot::clear);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    fn with_capacity_bytes(bytes: usize) -> Self {
+        Self::with_capacity((bytes / mem::size_of::<Bucket>()).max(1))
+    }
+
+    /// Start a new search, entries from previous searches become candidates for replacement
+    fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The entries of a key's bucket, None for empty or torn slots
+    fn bucket(&self, key: u64) -> [Option<Entry<G::Move>>; BUCKET_SIZE + 1] {
+        let slots = &self.table[self.index(key)].0;
+        std::array::from_fn(|i| slots[i].load::<G>())
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.table.len() as u64) as usize
+    }
+
+    /// Start loading the bucket for a key into the cache, so a later get or insert doesn't have
+    /// to wait for memory
+    #[inline(always)]
+    fn prefetch(&self, key: u64) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            let bucket: *const Bucket = &self.table[self.index(key)];
+            // SAFETY: prefetching is only a hint to the cache, the address is never dereferenced
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(bucket.cast()) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = key;
+    }
+
+    /// Get the entry for a key probed at `ply` plies from the root, preferring the depth-preferred
+    /// entry if the key is in both
+    fn get(&self, key: u64, ply: usize) -> Option<Pv<G::Move>> {
+        // Only the matching entry's move is decoded
+        self.table[self.index(key)]
+            .0
+            .iter()
+            .filter_map(Slot::words)
+            .find(|&(k, _, _)| k == key)
+            .and_then(|(k, data, info)| Slot::unpack::<G>(k, data, info))
+            .map(|e| Pv {
+                score: score_from_table(e.pv.score, ply),
+                ..e.pv
+            })
+    }
+
+    fn clear_key(&self, key: u64) {
+        for slot in &self.table[self.index(key)].0 {
+            if matches!(slot.words(), Some((k, _, _)) if k == key) {
+                slot.clear();
+            }
+        }
+    }
+
+    /// How much an entry is worth keeping, the lowest value entry in a bucket is replaced
+    fn replacement_value(entry: &Entry<G::Move>, generation: u8) -> i64 {
+        let bound = match entry.pv.node {
+            Node::Exact => 2,
+            Node::Alpha | Node::Beta => 1,
+            Node::Ordering => 0,
+        };
+        let age = generation.wrapping_sub(entry.generation) as i64;
+        entry.pv.depth as i64 + bound - AGE_WEIGHT * age
+    }
+
+    /// Store the result for a key searched at `ply` plies from the root
+    fn set(&self, key: u64, pv: Pv<G::Move>, ply: usize) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let entry = Entry {
+            key,
+            pv: Pv {
+                score: score_to_table(pv.score, ply),
+                ..pv
+            },
+            generation,
+        };
+        let slots = &self.table[self.index(key)].0;
+        let bucket = self.bucket(key);
+
+        // An existing entry for the key is updated unless it came from a much deeper search in
+        // this generation (e.g. a quiescence result shouldn't overwrite a full search result)
+        let existing = bucket[..BUCKET_SIZE]
+            .iter()
+            .position(|e| matches!(e, Some(e) if e.key == key));
+        let slot = match existing {
+            Some(slot) => {
+                let old = bucket[slot].unwrap();
+                let replace = old.generation != generation
+                    || pv.depth + 2 >= old.pv.depth
+                    || (matches!(pv.node, Node::Exact) && !matches!(old.pv.node, Node::Exact));
+                if replace {
+                    slot
+                } else {
+                    ALWAYS_REPLACE
+                }
+            }
+            None => match bucket[..BUCKET_SIZE].iter().position(|e| e.is_none()) {
+                Some(slot) => slot,
+                None => {
+                    let slot = (0..BUCKET_SIZE)
+                        .min_by_key(|&i| Self::replacement_value(&bucket[i].unwrap(), generation))
+                        .unwrap();
+                    let value = Self::replacement_value(&bucket[slot].unwrap(), generation);
+                    if Self::replacement_value(&entry, generation) >= value {
+                        slot
+                    } else {
+                        ALWAYS_REPLACE
+                    }
+                }
+            },
+        };
+        // Don't leave an older copy of the key in the always-replace entry
+        if slot != ALWAYS_REPLACE && matches!(bucket[ALWAYS_REPLACE], Some(e) if e.key == key) {
+            slots[ALWAYS_REPLACE].clear();
+        }
+        slots[slot].store::<G>(&entry);
+    }
+
+    /// Write every entry in the format described at TT_FILE_MAGIC
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        // Entries are read once, so the count matches what is written even if a search is
+        // storing into the table
+        let entries: Vec<_> = self
+            .table
+            .iter()
+            // The always-replace entry goes first so loading it can't push out one of the
+            // depth-preferred entries
+            .flat_map(|bucket| bucket.0.iter().rev().filter_map(Slot::load::<G>))
+            .collect();
+        out.write_all(TT_FILE_MAGIC)?;
+        out.write_all(&TT_FILE_VERSION.to_le_bytes())?;
+        out.write_all(&G::key_check().to_le_bytes())?;
+        out.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for entry in entries {
+            // Only encodable moves are stored
+            let play = G::encode_move(&entry.pv.play).unwrap();
+            let node: u8 = match entry.pv.node {
+                Node::Exact => 0,
+                Node::Alpha => 1,
+                Node::Beta => 2,
+                Node::Ordering => 3,
+            };
+            out.write_all(&entry.key.to_le_bytes())?;
+            out.write_all(&play.to_le_bytes())?;
+            // Stored scores already fit in an i32, see Slot::store
+            out.write_all(&(entry.pv.score as i32).to_le_bytes())?;
+            out.write_all(&[entry.pv.depth as u8, node])?;
+        }
+        Ok(())
+    }
+
+    /// Replace the entries with ones written by write, the entries are inserted as if they came
+    /// from a search so they fit however big the table is
+    fn read(&self, input: &mut impl Read) -> io::Result<()> {
+        fn invalid(message: String) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        }
+        if read_bytes(input)? != *TT_FILE_MAGIC {
+            return Err(invalid("not a saved transposition table".into()));
+        }
+        let version = u32::from_le_bytes(read_bytes(input)?);
+        if version != TT_FILE_VERSION {
+            return Err(invalid(format!("unsupported table version {}", version)));
+        }
+        if u64::from_le_bytes(read_bytes(input)?) != G::key_check() {
+            return Err(invalid("the table was saved with different keys".into()));
+        }
+        self.clear();
+        let count = u64::from_le_bytes(read_bytes(input)?);
+        for _ in 0..count {
+            let key = u64::from_le_bytes(read_bytes(input)?);
+            let bits = u32::from_le_bytes(read_bytes(input)?);
+            let play =
+                G::decode_move(bits).ok_or_else(|| invalid(format!("invalid move {}", bits)))?;
+            let score = i32::from_le_bytes(read_bytes(input)?) as i64;
+            let [depth, node] = read_bytes(input)?;
+            let node = match node {
+                0 => Node::Exact,
+                1 => Node::Alpha,
+                2 => Node::Beta,
+                3 => Node::Ordering,
+                _ => return Err(invalid(format!("invalid node type {}", node))),
+            };
+            // Scores are stored relative to the entry's position, which is ply 0
+            let pv = Pv {
+                play,
+                score,
+                depth: depth.into(),
+                node,
+            };
+            self.set(key, pv, 0);
+        }
+        Ok(())
+    }
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Checkmate scores count plies from the root, but a position can be reached at different plies so
+// they are stored in the table counting plies from the position itself
+fn score_to_table(score: i64, ply: usize) -> i64 {
+    if score >= CHECKMATE_SCORE - MATE_PLY_LIMIT {
+        score.saturating_add(ply as i64)
+    } else if score <= -CHECKMATE_SCORE + MATE_PLY_LIMIT {
+        score.saturating_sub(ply as i64)
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i64, ply: usize) -> i64 {
+    if score >= CHECKMATE_SCORE - MATE_PLY_LIMIT {
+        score.saturating_sub(ply as i64)
+    } else if score <= -CHECKMATE_SCORE + MATE_PLY_LIMIT {
+        score.saturating_add(ply as i64)
+    } else {
+        score
+    }
+}
+
+pub struct PvLine<M> {
+    line: Vec<M>,
+}
+
+impl<M> PvLine<M> {
+    pub fn moves(&self) -> &[M] {
+        &self.line
+    }
+}
+
+impl<M: fmt::Display> fmt::Display for PvLine<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let out: Vec<String> = self.line.iter().map(|p| format!("{}", p)).collect();
+        let new = out.join(" ");
+        write!(f, "{}", new)?;
+        Ok(())
+    }
+}
+
+/// Progress of a search, reported after each completed iteration of iterative deepening
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchInfo<M> {
+    pub depth: u8,
+    pub selective_depth: u8,
+    pub score: i64,        // Centipawns from the point of view of the side to move
+    pub mate: Option<i64>, // Moves until checkmate, negative if the side to move is mated
+    pub nodes: u64,        // Nodes searched by the completed iterations
+    pub time: time::Duration,
+    pub pv: Vec<M>,
+}
+
+/// Measures of how sharp a position is, based on how the search changed between iterations
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Complexity {
+    pub stable_depth: u8,      // The depth from which the best move stopped changing
+    pub best_move_changes: u8, // Number of times the best move changed between iterations
+    pub score_deviation: f64,  // Standard deviation of the score across iterations
+}
+
+impl Complexity {
+    // Decided and checkmate scores are clamped so they don't swamp the deviation
+    const MAX_SCORE: i64 = 1000;
+
+    fn from_iterations<M: PartialEq>(iterations: &[(M, i64)]) -> Option<Self> {
+        let (last_move, _) = iterations.last()?;
+        let stable_depth = iterations
+            .iter()
+            .rposition(|(play, _)| play != last_move)
+            .map_or(1, |i| i + 2) as u8;
+        let best_move_changes = iterations.windows(2).filter(|w| w[0].0 != w[1].0).count() as u8;
+
+        let scores: Vec<f64> = iterations
+            .iter()
+            .map(|(_, score)| (*score).clamp(-Self::MAX_SCORE, Self::MAX_SCORE) as f64)
+            .collect();
+        let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+        let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / scores.len() as f64;
+
+        Some(Self {
+            stable_depth,
+            best_move_changes,
+            score_deviation: variance.sqrt(),
+        })
+    }
+}
+
+/// Whether a search score is exact or only a bound on the true score
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower, // The search failed high, the true score is at least this
+    Upper, // The search failed low, the true score is at most this
+}
+
+#[derive(Debug)]
+pub struct SearchResult<M> {
+    nodes: u64,          // The number of results examined as part of the search
+    qnodes: u64,         // The number of those nodes which were in quiescence
+    depth: u8,           // Nominal search depth in plies
+    selective_depth: u8, // Selective search depth in plies
+    best_move: M,        // The best move found as part of the search
+    score: i64,          // The estimated score for the best move if played
+    bound: Bound,
+    pv: Vec<M>, // Principal variation, starting with the best move
+    time: time::Duration,
+}
+
+impl<M> SearchResult<M> {
+    pub fn best_move(&self) -> &M {
+        &self.best_move
+    }
+
+    /// Centipawns from the point of view of the side to move
+    pub fn score(&self) -> i64 {
+        self.score
+    }
+
+    pub fn bound(&self) -> Bound {
+        self.bound
+    }
+
+    pub fn pv(&self) -> &[M] {
+        &self.pv
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn selective_depth(&self) -> u8 {
+        self.selective_depth
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// The number of nodes searched in quiescence, these are also counted in the total nodes
+    pub fn qnodes(&self) -> u64 {
+        self.qnodes
+    }
+
+    pub fn time(&self) -> time::Duration {
+        self.time
+    }
+
+    /// Nodes searched per second
+    pub fn nps(&self) -> u64 {
+        match self.time.as_secs_f64() {
+            secs if secs > 0.0 => (self.nodes as f64 / secs) as u64,
+            _ => 0,
+        }
+    }
+
+    fn checkmate_in(&self) -> Option<i64> {
+        if (CHECKMATE_SCORE - self.score.abs()) < MATE_PLY_LIMIT {
+            let mut mate = (CHECKMATE_SCORE - self.score.abs() + 1) / 2;
+            if self.score < 0 {
+                mate = -mate;
+            };
+            return Some(mate);
+        }
+        None
+    }
+}
+
+impl<G: Game> Engine for AlphaBeta<G> {
+    type Game = G;
+
+    fn new(board: G) -> Self {
+        Self::with_hash_size(board, DEFAULT_HASH_SIZE_MB)
+    }
+
+    fn perft(&mut self, depth: u8) -> Vec<(G::Move, u64)> {
+        self.board.perft_divide(depth)
+    }
+
+    fn configure(&mut self, search_options: &SearchParameters<G::Move>) {
+        let [allocated, fixed_limit, hard_limit] =
+            search_options.time_limits(self.board.active_color());
+        self.time_manager = TimeManager::new(search_options.start_time, allocated, hard_limit);
+        self.time_manager.set_fixed_limit(fixed_limit);
+        self.quiescence_depth = search_options.quiescence_depth;
+        self.node_limit = search_options.nodes;
+        self.search_nodes = 0;
+        self.pondering = self.ponder.is_pondering();
+        self.should_stop = false;
+        self.iterations.clear();
+        self.killers.clear();
+        self.root_moves = RootMoves::new(&self.board);
+        self.root_moves.restrict(&search_options.search_moves);
+        self.time_manager
+            .set_forced(self.root_moves.moves.len() == 1);
+        self.moves.new_search();
+        self.start_helpers();
+    }
+
+    fn finish_search(&mut self) {
+        self.stop_helpers();
+    }
+
+    fn select_move(&mut self, best_move: G::Move) -> G::Move {
+        if self.skill_level >= MAX_SKILL_LEVEL {
+            return best_move;
+        }
+        self.root_moves
+            .choose(self.skill_level, &mut thread_rng())
+            .unwrap_or(best_move)
+    }
+
+    fn can_start_iteration(&self) -> bool {
+        let time_up = !self.pondering && self.time_manager.is_soft_limit_reached();
+        !time_up && !self.node_limit_reached()
+    }
+
+    fn active_color(&self) -> Color {
+        self.board.active_color()
+    }
+
+    fn should_stop(&self) -> bool {
+        self.should_stop
+    }
+
+    fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    fn ponder_handle(&self) -> PonderHandle {
+        self.ponder.clone()
+    }
+
+    fn parse_fen(&mut self, fen_string: &str) -> Result<(), String> {
+        self.nodes = 0;
+        self.score = 0;
+        self.board = G::from_fen(fen_string).map_err(|e| e.to_string())?;
+        self.board.set_eval_params(Arc::clone(&self.eval_params));
+        Ok(())
+    }
+
+    fn search(&mut self, depth: u8) -> Result<SearchResult<G::Move>, SearchError> {
+        let start_time = Instant::now();
+        self.nodes = 0;
+        self.qnodes = 0;
+        self.check_ponder_hit();
+        self.search_depth = depth;
+        self.selective_depth = depth;
+        self.board.reset_line_ply();
+        self.root_color = self.board.active_color();
+        if self.root_moves.key != self.board.key() {
+            self.root_moves = RootMoves::new(&self.board);
+        }
+        if self.root_moves.is_empty() {
+            return Err(match self.board.in_check() {
+                true => SearchError::Checkmated,
+                false => SearchError::Stalemate,
+            });
+        }
+        if self.board.is_draw() {
+            return Err(SearchError::Drawn);
+        }
+        let root_board = self.board.clone();
+        let score = self.alpha_beta(i64::MIN + 1, i64::MAX - 1, depth);
+        if score.is_err() {
+            // Errors are returned without undoing the moves played below the root
+            self.board = root_board;
+        }
+        self.score = score?;
+        if self.should_stop {
+            return Err(SearchError::Stopped);
+        }
+        // The root always has a legal move here, fall back to the first in case the entry has
+        // been overwritten by a helper thread
+        let root_entry = self.probe().filter(|pv| self.root_moves.contains(&pv.play));
+        let best_move = root_entry
+            .map(|pv| pv.play)
+            .unwrap_or(self.root_moves.moves[0].play);
+        let bound = match root_entry.map(|pv| pv.node) {
+            Some(Node::Alpha) => Bound::Upper,
+            Some(Node::Beta) => Bound::Lower,
+            _ => Bound::Exact,
+        };
+        let mut pv = self.pv_line().line;
+        if pv.first() != Some(&best_move) {
+            pv = vec![best_move];
+        }
+        let best_move_changed =
+            matches!(self.iterations.last(), Some((play, _)) if *play != best_move);
+        self.time_manager
+            .record_iteration(self.score, best_move_changed);
+        self.iterations.push((best_move, self.score));
+        Ok(SearchResult {
+            nodes: self.nodes,
+            qnodes: self.qnodes,
+            depth,
+            score: self.score,
+            selective_depth: self.selective_depth,
+            best_move,
+            bound,
+            pv,
+            time: start_time.elapsed(),
+        })
+    }
+
+    //fn make_move(&mut self, play: &Play) {
+    //    self.board.make_move(play);
+    //}
+
+    fn make_move_str(&mut self, play: &str) -> Result<(), String> {
+        // Fall back to SAN, e.g. for moves pasted from a PGN
+        let p = self
+            .board
+            .parse_uci_move(play)
+            .or_else(|_| self.board.parse_san(play))
+            .map_err(|e| e.to_string())?;
+        self.board
+            .make_move_checked(&p)
+            .map_err(|e| e.to_string())?;
+        self.moves.clear_key(self.board.key()); // TODO this is a hack to try to fix bad
+                                                // cache hits, particularly for draws
+        Ok(())
+    }
+
+    fn set_hash_size(&mut self, megabytes: usize) {
+        self.moves = Arc::new(HashTable::with_capacity_bytes(megabytes * 1024 * 1024));
+    }
+
+    fn save_tt(&self, path: &str) -> Result<(), String> {
+        let error = |e: io::Error| format!("{}: {}", path, e);
+        let mut out = BufWriter::new(File::create(path).map_err(error)?);
+        self.moves.write(&mut out).map_err(error)?;
+        out.flush().map_err(error)
+    }
+
+    fn load_tt(&mut self, path: &str) -> Result<(), String> {
+        let error = |e: io::Error| format!("{}: {}", path, e);
+        let result = File::open(path).and_then(|file| self.moves.read(&mut BufReader::new(file)));
+        if result.is_err() {
+            self.moves.clear();
+        }
+        result.map_err(error)
+    }
+
+    fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    fn set_eval_params(&mut self, params: EvalParams) {
+        self.eval_params = Arc::new(params);
+        self.board.set_eval_params(Arc::clone(&self.eval_params));
+        self.clear_cache();
+        self.eval_cache = EvalCache::new();
+    }
+
+    fn set_skill_level(&mut self, level: u8) {
+        self.skill_level = level.min(MAX_SKILL_LEVEL);
+    }
+
+    fn set_contempt(&mut self, contempt: i64) {
+        self.contempt = contempt.clamp(-MAX_CONTEMPT, MAX_CONTEMPT);
+    }
+
+    fn display_board(&self, options: DisplayOptions) {
+        println!("{}", self.board.pretty(options));
+    }
+
+    fn position(&self) -> &G {
+        &self.board
+    }
+
+    fn complexity(&self) -> Option<Complexity> {
+        Complexity::from_iterations(&self.iterations)
+    }
+
+    fn pv_line(&self) -> PvLine<G::Move> {
+        // Walk the transposition table by playing each move on a copy of the board, this lets the
+        // line stop when it reaches a draw rather than continuing with meaningless moves
+        let mut pv_line = Vec::new();
+        let mut board = self.board.clone();
+        while let Some(pv) = self.moves.get(board.key(), pv_line.len()) {
+            if pv_line.len() >= MAX_PV_LENGTH
+                || !board.is_pseudo_legal(&pv.play)
+                || !board.make_move(&pv.play)
+            {
+                break;
+            }
+            pv_line.push(pv.play);
+            if board.is_draw() {
+                break;
+            }
+        }
+        PvLine { line: pv_line }
+    }
+}
+
+#[cfg(test)]
+mod test_search {
+    use super::AlphaBeta;
     use super::Board;
+    use super::Bound;
+    use super::Clock;
+    use super::Color;
+    use super::Complexity;
     use super::Engine;
+    use super::EvalParams;
     use super::Game;
+    use super::SearchError;
+    use super::SearchParameters;
+    use super::CHECKMATE_SCORE;
+    use super::MAX_DEPTH;
+    use super::MAX_SKILL_LEVEL;
+    use crate::misc::Piece;
+    use crate::play::Play;
     use pretty_assertions::assert_eq;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::collections::HashSet;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_regression_bad_cache() {
@@ -493,6 +1910,101 @@ mod test_search {
         assert_eq!(format!("{}", result.best_move), "g3g6");
     }
 
+    #[test]
+    fn test_checkmate_distance_with_table_hits() {
+        // Searching again and searching after the moves are played hits mate scores stored in
+        // the table at a different ply, the mate distance must stay correct
+        let game =
+            Board::from_fen("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 0").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        e.search(4).unwrap();
+        assert_eq!(e.search(4).unwrap().checkmate_in(), Some(2));
+
+        e.make_move_str("g3g6").unwrap();
+        e.make_move_str("f7g6").unwrap();
+        let result = e.search(4).unwrap();
+        assert_eq!(result.checkmate_in(), Some(1));
+    }
+
+    #[test]
+    fn test_save_and_load_tt() {
+        let path = std::env::temp_dir().join(format!("arche-test-{}.hash", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        e.iterative_deepening_search(SearchParameters::builder().depth(5).build())
+            .unwrap();
+        e.save_tt(path).unwrap();
+
+        // A new session, with a different table size, carries on with the same line
+        let mut resumed = <AlphaBeta as Engine>::new(Board::new());
+        resumed.set_hash_size(1);
+        resumed.load_tt(path).unwrap();
+        assert_eq!(resumed.pv_line().moves(), e.pv_line().moves());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(resumed.load_tt(path).is_err());
+        assert!(resumed.pv_line().moves().is_empty());
+    }
+
+    #[test]
+    fn test_set_eval_params_clears_table() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        e.iterative_deepening_search(SearchParameters::builder().depth(3).build())
+            .unwrap();
+        assert!(!e.pv_line().moves().is_empty());
+        e.set_eval_params(EvalParams::default());
+        assert!(e.pv_line().moves().is_empty());
+    }
+
+    #[test]
+    fn test_eval_params_belong_to_the_engine() {
+        let fen = "4k3/8/8/8/8/8/PPP5/4K3 w - - 0 1";
+        let mut params = EvalParams::default();
+        params.material[Piece::Pawn as usize] += 100;
+        let mut tuned = <AlphaBeta as Engine>::new(Board::new());
+        tuned.set_eval_params(params);
+        tuned.parse_fen(fen).unwrap();
+
+        // Other engines and boards keep the defaults
+        let mut other = <AlphaBeta as Engine>::new(Board::new());
+        other.parse_fen(fen).unwrap();
+        let default = Board::from_fen(fen).unwrap().eval();
+        assert_eq!(other.position().eval(), default);
+        assert_eq!(tuned.position().eval(), default + 300);
+    }
+
+    #[test]
+    fn test_make_move_str_accepts_san() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        e.make_move_str("e2e4").unwrap();
+        e.make_move_str("e5").unwrap();
+        e.make_move_str("Nf3").unwrap();
+        assert!(e.make_move_str("Nf3").is_err());
+        assert!(e.make_move_str("e2e4").is_err());
+        let expected =
+            Board::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2")
+                .unwrap();
+        for index in 0..64 {
+            assert_eq!(
+                e.board.get_piece_index(index),
+                expected.get_piece_index(index)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lazy_smp_finds_checkmate() {
+        let game =
+            Board::from_fen("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 0").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        e.set_threads(4);
+        let best_move = e
+            .iterative_deepening_search(SearchParameters::builder().depth(4).build())
+            .unwrap();
+        assert_eq!(format!("{}", best_move), "g3g6");
+        assert!(e.helpers.is_empty());
+    }
+
     #[test]
     fn test_checkmate_in_1_black() {
         let game =
@@ -502,6 +2014,17 @@ mod test_search {
         assert_eq!(result.checkmate_in(), Some(-1));
     }
 
+    #[test]
+    fn test_razoring_finds_winning_capture() {
+        // Black has just left the queen hanging, razoring must not prune the capture
+        let game =
+            Board::from_fen("rnb1kbnr/pppp1ppp/8/4p1q1/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3")
+                .unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        let result = e.search(3).unwrap();
+        assert_eq!(format!("{}", result.best_move), "f3g5");
+    }
+
     #[test]
     fn test_fifty_move_rule_play_for_draw() {
         // white is down material in this position so should play for fifty move draw
@@ -511,106 +2034,734 @@ mod test_search {
         assert_eq!(result.score, 0);
     }
 
+    #[test]
+    fn test_contempt_scores_draws_for_root_side() {
+        // A queen down, any king move draws by the fifty move rule while a pawn move plays on
+        let game = Board::from_fen("k7/8/8/8/3q4/8/P7/7K w - - 99 112").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        e.set_contempt(50);
+        // The draw is still better than playing on but is now worth less than equal
+        let result = e.search(3).unwrap();
+        assert_eq!(result.score, -50);
+
+        // From the opponent's point of view the draw is worth the contempt
+        e.board.active_color = Color::White;
+        e.root_color = Color::Black;
+        assert_eq!(e.draw_score(), 50);
+    }
+
+    #[test]
+    fn test_pv_line_stops_at_fifty_move_rule() {
+        // Any move other than a capture or pawn move draws by the fifty move rule
+        let game = Board::from_fen("5k2/1p3p1p/p3pK1P/P1P1P3/4bP2/2B5/8/8 w - - 99 112").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        e.search(3).unwrap();
+        assert_eq!(e.pv_line().line.len(), 1);
+    }
+
+    #[test]
+    fn test_pv_line_is_playable() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        e.search(5).unwrap();
+        let mut board = e.board.clone();
+        let line = e.pv_line().line;
+        assert!(!line.is_empty());
+        for play in line {
+            assert!(board.make_move(&play), "{} should be legal", play);
+        }
+    }
+
+    #[test]
+    fn test_improving_compares_two_plies_earlier() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        assert!(e.is_improving(Some(50)));
+        for _ in 0..2 {
+            let play = e.board.legal_plays()[0];
+            assert!(e.board.make_move(&play));
+            assert!(e.is_improving(None));
+        }
+        assert!(!e.is_improving(Some(40)));
+        assert!(e.is_improving(Some(60)));
+    }
+
+    #[test]
+    fn test_search_result_details() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        let result = e.search(5).unwrap();
+        assert_eq!(result.depth(), 5);
+        assert_eq!(result.bound(), Bound::Exact);
+        assert_eq!(result.pv(), e.pv_line().moves());
+        assert_eq!(result.pv()[0], *result.best_move());
+        assert!(result.time() > Duration::ZERO);
+        assert!(result.nps() > 0);
+    }
+
+    #[test]
+    fn test_root_moves_ordered_by_last_iteration() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        let result = e.search(4).unwrap();
+        let root_moves = &e.root_moves.moves;
+        assert_eq!(root_moves.len(), 20);
+        assert_eq!(root_moves[0].play, result.best_move);
+        assert!(root_moves.iter().all(|m| m.nodes > 0));
+        assert!(root_moves.iter().map(|m| m.nodes).sum::<u64>() < result.nodes);
+        assert!(root_moves[1..].windows(2).all(|w| w[0].nodes >= w[1].nodes));
+    }
+
+    #[test]
+    fn test_skill_level_prefers_good_moves() {
+        // Rxd5 wins the queen
+        let game = Board::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        e.set_skill_level(0);
+        let result = e.search(3).unwrap();
+        assert_eq!(format!("{}", result.best_move), "d2d5");
+        // Each root move has its own score rather than a bound from the best move
+        let scores: HashSet<i64> = e.root_moves.moves.iter().map(|m| m.score).collect();
+        assert!(scores.len() > 2);
+
+        let mut rng = SmallRng::seed_from_u64(1);
+        let picks: Vec<Play> = (0..100)
+            .map(|_| e.root_moves.choose(0, &mut rng).unwrap())
+            .collect();
+        let best_picks = picks.iter().filter(|&&p| p == result.best_move).count();
+        assert!(best_picks > 50 && best_picks < 100);
+        assert!((0..100)
+            .all(|_| { e.root_moves.choose(MAX_SKILL_LEVEL, &mut rng) == Some(result.best_move) }));
+    }
+
+    #[test]
+    fn test_eval_cache_matches_board_eval() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        e.search(4).unwrap();
+        let cached = e.eval_cache.table.iter().flatten().count();
+        assert!(cached > 0);
+        for m in Board::new().generate_moves() {
+            let mut board = Board::new();
+            board.make_move(&m);
+            e.board = board.clone();
+            assert_eq!(e.eval(), board.eval());
+            assert_eq!(e.eval_cache.get(board.key), Some(board.eval()));
+        }
+    }
+
+    #[test]
+    fn test_store_killer_keeps_two_most_recent() {
+        let board = Board::new();
+        let mut e = <AlphaBeta as Engine>::new(board.clone());
+        let moves = board.generate_moves();
+        e.store_killer(moves[0]);
+        e.store_killer(moves[0]);
+        assert_eq!(e.killers(), [Some(moves[0]), None]);
+        e.store_killer(moves[1]);
+        e.store_killer(moves[2]);
+        assert_eq!(e.killers(), [Some(moves[2]), Some(moves[1])]);
+    }
+
+    #[test]
+    fn test_custom_evaluator() {
+        // A queen up, but the evaluator scores every position as a pawn for the side to move
+        let game = Board::from_fen("4k3/8/8/8/8/8/PPP5/3QK3 w - - 0 1").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        e.search(1).unwrap();
+        assert!(e.score > 500);
+
+        e.set_evaluator(|b: &Board| match b.active_color() {
+            Color::White => 100,
+            Color::Black => -100,
+        });
+        assert_eq!(e.eval_cache.table.iter().flatten().count(), 0);
+        e.search(1).unwrap();
+        assert_eq!(e.score, 100);
+    }
+
+    #[test]
+    fn test_quiescence_finds_quiet_checkmate() {
+        // Ra8 is checkmate but isn't a capture
+        let game = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        let score = e.quiescence(-CHECKMATE_SCORE, CHECKMATE_SCORE, 0).unwrap();
+        assert_eq!(score, CHECKMATE_SCORE - 1);
+        // Checks are only searched in the first ply
+        let score = e.quiescence(-CHECKMATE_SCORE, CHECKMATE_SCORE, 1).unwrap();
+        assert!(score < 1000);
+    }
+
+    #[test]
+    fn test_quiescence_searches_promotions() {
+        // White is a rook down but e8=Q doesn't capture and the rook can't stop it
+        let game = Board::from_fen("7k/4P3/8/8/8/8/r7/4K3 w - - 0 1").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        let score = e.quiescence(-CHECKMATE_SCORE, CHECKMATE_SCORE, 1).unwrap();
+        assert!(score > 0, "{}", score);
+    }
+
+    #[test]
+    fn test_quiescence_depth_limit() {
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        let mut e = <AlphaBeta as Engine>::new(Board::from_fen(fen).unwrap());
+        e.configure(&SearchParameters::default());
+        let unlimited = e.search(4).unwrap();
+        e.finish_search();
+        assert!(unlimited.qnodes > 0);
+        assert!(unlimited.qnodes < unlimited.nodes);
+
+        let mut e = <AlphaBeta as Engine>::new(Board::from_fen(fen).unwrap());
+        e.configure(&SearchParameters::builder().quiescence_depth(1).build());
+        let limited = e.search(4).unwrap();
+        e.finish_search();
+        assert!(limited.qnodes < unlimited.qnodes);
+        assert!(limited.selective_depth < unlimited.selective_depth);
+    }
+
+    #[test]
+    fn test_complexity_from_iterations() {
+        let a = Play::new(12, 28, None, None, false, false);
+        let b = Play::new(6, 21, None, None, false, false);
+        assert_eq!(Complexity::from_iterations::<Play>(&[]), None);
+
+        let stable = Complexity::from_iterations(&[(a, 20), (a, 20), (a, 20)]).unwrap();
+        assert_eq!(stable.stable_depth, 1);
+        assert_eq!(stable.best_move_changes, 0);
+        assert_eq!(stable.score_deviation, 0.0);
+
+        let sharp = Complexity::from_iterations(&[(a, 20), (b, -80), (a, 40), (b, -60)]).unwrap();
+        assert_eq!(sharp.stable_depth, 4);
+        assert_eq!(sharp.best_move_changes, 3);
+        assert!(sharp.score_deviation > 50.0);
+    }
+
+    #[test]
+    fn test_stop_flag_aborts_search() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        e.stop_handle().stop();
+        let best_move = e
+            .iterative_deepening_search(SearchParameters::builder().depth(8).build())
+            .unwrap();
+        // The first iteration always completes so a move is still returned
+        assert!(Board::new().legal_moves().any(|m| m.play() == best_move));
+        assert!(e.should_stop());
+    }
+
+    #[test]
+    fn test_stop_handle_from_another_thread() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        let handle = e.stop_handle();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            handle.stop();
+        });
+        // Without the handle this search would run to the maximum depth
+        let start = Instant::now();
+        e.iterative_deepening_search(SearchParameters::default())
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(e.stop_handle().is_stopped());
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn test_soft_limit_stops_between_iterations() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        let sp = SearchParameters::builder()
+            .start_time(Instant::now() - Duration::from_millis(10))
+            .time_limits(Duration::from_millis(1), Duration::from_secs(60))
+            .build();
+        e.iterative_deepening_search(sp).unwrap();
+        // The first iteration always completes and then the soft limit has passed
+        assert_eq!(e.iterations.len(), 1);
+        assert!(!e.should_stop());
+    }
+
+    #[test]
+    fn test_time_limits_use_tightest_limit() {
+        let clock = Clock {
+            white_time: Some(Duration::from_secs(60)),
+            black_time: Some(Duration::from_secs(60)),
+            ..Clock::default()
+        };
+        let sp = SearchParameters::<Play>::builder()
+            .move_time(Duration::from_secs(1))
+            .clock(clock)
+            .build();
+        assert_eq!(
+            sp.time_limits(Color::White),
+            [
+                Some(Duration::from_millis(1450)),
+                Some(Duration::from_millis(950)),
+                Some(Duration::from_millis(950))
+            ]
+        );
+        let sp = SearchParameters::<Play>::builder()
+            .move_time(Duration::from_secs(10))
+            .clock(clock)
+            .build();
+        assert_eq!(
+            sp.time_limits(Color::White),
+            [
+                Some(Duration::from_millis(1450)),
+                Some(Duration::from_millis(9950)),
+                Some(Duration::from_millis(5950))
+            ]
+        );
+        let sp = SearchParameters::<Play>::builder()
+            .clock(clock)
+            .infinite()
+            .build();
+        assert_eq!(sp.time_limits(Color::White), [None, None, None]);
+    }
+
+    #[test]
+    fn test_search_moves_restrict_root() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        let sp = SearchParameters::builder()
+            .depth(3)
+            .search_moves(vec!["a2a3".to_string(), "h2h3".to_string()])
+            .build();
+        let best_move = e.iterative_deepening_search(sp).unwrap();
+        assert!(["a2a3", "h2h3"].contains(&best_move.to_string().as_str()));
+        assert_eq!(e.root_moves.moves.len(), 2);
+
+        // Without any legal moves in the list every move is searched
+        let sp = SearchParameters::builder()
+            .depth(1)
+            .search_moves(vec!["e2e5".to_string()])
+            .build();
+        e.iterative_deepening_search(sp).unwrap();
+        assert_eq!(e.root_moves.moves.len(), 20);
+    }
+
+    #[test]
+    fn test_on_iteration_reports_progress() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        let (sender, receiver) = mpsc::channel();
+        let sp = SearchParameters::builder()
+            .depth(4)
+            .on_iteration(move |info| sender.send(info.clone()).unwrap())
+            .build();
+        let best_move = e.iterative_deepening_search(sp).unwrap();
+        let infos: Vec<_> = receiver.iter().collect();
+        assert_eq!(
+            infos.iter().map(|i| i.depth).collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        assert!(infos.windows(2).all(|w| w[0].nodes < w[1].nodes));
+        let last = infos.last().unwrap();
+        assert_eq!(last.pv[0], best_move);
+        assert_eq!(last.mate, None);
+    }
+
+    #[test]
+    fn test_ponder_ignores_time_until_hit() {
+        let mut e = <AlphaBeta as Engine>::new(Board::new());
+        let ponder = e.ponder_handle();
+        ponder.start();
+        let handle = ponder.clone();
+        let hitter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            handle.hit();
+        });
+        let start = Instant::now();
+        let sp = SearchParameters::builder()
+            .move_time(Duration::from_millis(10))
+            .build();
+        e.iterative_deepening_search(sp).unwrap();
+        hitter.join().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(!e.pondering);
+    }
+
+    #[test]
+    fn test_mate_limit_stops_search() {
+        let game =
+            Board::from_fen("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 0").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        let best_move = e
+            .iterative_deepening_search(SearchParameters::builder().mate(2).build())
+            .unwrap();
+        assert_eq!(best_move.to_string(), "g3g6");
+        assert!(e.iterations.len() < MAX_DEPTH.into());
+    }
+
+    #[test]
+    fn test_node_limit_is_deterministic() {
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+        let search = || {
+            let mut e = <AlphaBeta as Engine>::new(Board::from_fen(fen).unwrap());
+            let sp = SearchParameters::builder().nodes(20_000).build();
+            let best_move = e.iterative_deepening_search(sp).unwrap();
+            (best_move, e.search_nodes, e.iterations.len())
+        };
+        let (best_move, nodes, iterations) = search();
+        assert!((20_000..21_000).contains(&nodes));
+        assert!(iterations < MAX_DEPTH.into());
+        assert_eq!(search(), (best_move, nodes, iterations));
+    }
+
     #[test]
     fn test_fifty_move_rule_no_legal_moves() {
         // The fifty move rules has been triggered - there should not be any legal moves
         let game = Board::from_fen("5k2/1p3p1p/p3pK1P/P1P1P3/4bP2/2B5/8/8 w - - 100 112").unwrap();
         let mut e = <AlphaBeta as Engine>::new(game);
         let result = e.search(3);
-        assert!(result.is_none());
+        assert_eq!(result.unwrap_err(), SearchError::Drawn);
+    }
+
+    #[test]
+    fn test_stalemate_returns_error() {
+        let game = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        let result = e.iterative_deepening_search(SearchParameters::builder().depth(3).build());
+        assert_eq!(result, Err(SearchError::Stalemate));
+    }
+
+    #[test]
+    fn test_checkmated_returns_error() {
+        let game = Board::from_fen("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        let mut e = <AlphaBeta as Engine>::new(game);
+        let result = e.iterative_deepening_search(SearchParameters::builder().depth(3).build());
+        assert_eq!(result, Err(SearchError::Checkmated));
     }
 }
 
-impl Engine for AlphaBeta {
-    fn new(board: Board) -> Self {
-        Self {
-            board,
-            nodes: 0,
-            score: 0,
-            moves: HashTable::with_capacity_bytes(500 * 1024 * 1024),
-            search_depth: 0,
-            selective_depth: 0,
-            start_time: time::Instant::now(),
-            search_duration: None,
-            should_stop: false,
+#[cfg(test)]
+mod test_hash_table {
+    use super::{
+        HashTable, Node, Pv, ALWAYS_REPLACE, BUCKET_SIZE, CHECKMATE_SCORE, TT_FILE_VERSION,
+    };
+    use crate::board::Board;
+    use crate::play::Play;
+    use crate::Game;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+
+    fn pv(depth: usize, node: Node) -> Pv<Play> {
+        Pv {
+            play: Play::new(12, 28, None, None, false, false),
+            score: depth as i64,
+            depth,
+            node,
         }
     }
 
-    fn perft(&mut self) {
-        // TODO add a param
-        self.board.perft(1);
+    #[test]
+    fn test_bucket_keeps_colliding_keys() {
+        let table = HashTable::<Board>::with_capacity(1);
+        for key in 0..BUCKET_SIZE as u64 {
+            table.set(key, pv(key as usize + 1, Node::Beta), 0);
+        }
+        for key in 0..BUCKET_SIZE as u64 {
+            assert_eq!(table.get(key, 0).unwrap().depth, key as usize + 1);
+        }
     }
 
-    fn configure(&mut self, start_time: time::Instant, search_duration: Option<time::Duration>) {
-        self.start_time = start_time;
-        self.search_duration = search_duration;
-        self.should_stop = false;
+    #[test]
+    fn test_full_bucket_replaces_shallowest() {
+        let table = HashTable::<Board>::with_capacity(1);
+        for key in 0..BUCKET_SIZE as u64 {
+            table.set(key, pv(5 - key as usize, Node::Exact), 0);
+        }
+        table.set(100, pv(3, Node::Alpha), 0);
+        assert!(table.get(BUCKET_SIZE as u64 - 1, 0).is_none());
+        assert_eq!(table.get(100, 0).unwrap().depth, 3);
+        assert_eq!(table.get(0, 0).unwrap().depth, 5);
     }
 
-    fn active_color(&self) -> Color {
-        self.board.active_color
+    #[test]
+    fn test_full_bucket_replaces_old_generation() {
+        let table = HashTable::<Board>::with_capacity(1);
+        table.set(0, pv(5, Node::Exact), 0);
+        table.new_search();
+        for key in 1..BUCKET_SIZE as u64 {
+            table.set(key, pv(1, Node::Alpha), 0);
+        }
+        table.set(100, pv(1, Node::Alpha), 0);
+        assert!(table.get(0, 0).is_none());
+        assert!(table.get(100, 0).is_some());
     }
 
-    fn should_stop(&self) -> bool {
-        self.should_stop
+    #[test]
+    fn test_shallow_result_keeps_deep_entry() {
+        let table = HashTable::<Board>::with_capacity(1);
+        table.set(7, pv(8, Node::Exact), 0);
+        table.set(7, pv(0, Node::Ordering), 0);
+        assert_eq!(table.get(7, 0).unwrap().depth, 8);
+
+        // But it is replaced once the entry is from a previous search
+        table.new_search();
+        table.set(7, pv(0, Node::Ordering), 0);
+        assert_eq!(table.get(7, 0).unwrap().depth, 0);
     }
 
-    fn parse_fen(&mut self, fen_string: &str) -> Result<(), String> {
-        self.nodes = 0;
-        self.score = 0;
-        self.board = Board::from_fen(fen_string)?;
-        Ok(())
+    #[test]
+    fn test_refused_result_goes_to_always_replace() {
+        let table = HashTable::<Board>::with_capacity(1);
+        for key in 0..BUCKET_SIZE as u64 {
+            table.set(key, pv(8, Node::Exact), 0);
+        }
+        // Too shallow to replace any of the deep entries, but still kept
+        table.set(100, pv(1, Node::Alpha), 0);
+        assert_eq!(table.get(100, 0).unwrap().depth, 1);
+        assert!((0..BUCKET_SIZE as u64).all(|key| table.get(key, 0).is_some()));
+
+        // Until the next refused result
+        table.set(101, pv(1, Node::Alpha), 0);
+        assert!(table.get(100, 0).is_none());
+        assert_eq!(table.get(101, 0).unwrap().depth, 1);
+
+        // A refused update of an existing key is also kept without losing the deep entry
+        table.set(0, pv(0, Node::Ordering), 0);
+        assert_eq!(table.get(0, 0).unwrap().depth, 8);
+        assert_eq!(table.bucket(0)[ALWAYS_REPLACE].unwrap().pv.depth, 0);
     }
 
-    fn search(&mut self, depth: u8) -> Option<SearchResult> {
-        self.nodes = 0;
-        self.search_depth = depth;
-        self.selective_depth = depth;
-        self.board.line_ply = 0;
-        self.score = self.alpha_beta(i64::MIN + 1, i64::MAX - 1, depth);
-        if let Some(best_move) = self.moves.get(self.board.key) {
-            return Some(SearchResult {
-                nodes: self.nodes,
-                score: self.score,
-                selective_depth: self.selective_depth,
-                best_move: best_move.play,
-            });
-        }
-        None
+    #[test]
+    fn test_mate_score_relative_to_node() {
+        let table = HashTable::<Board>::with_capacity(1);
+        let mut mate = pv(3, Node::Exact);
+        mate.score = CHECKMATE_SCORE - 5; // Mate 5 plies from the root, found at ply 2
+        table.set(1, mate, 2);
+        assert_eq!(table.get(1, 2).unwrap().score, CHECKMATE_SCORE - 5);
+        assert_eq!(table.get(1, 4).unwrap().score, CHECKMATE_SCORE - 7);
+
+        mate.score = -CHECKMATE_SCORE + 5;
+        table.set(2, mate, 2);
+        assert_eq!(table.get(2, 0).unwrap().score, -CHECKMATE_SCORE + 3);
+
+        // Other scores are unchanged
+        table.set(3, pv(3, Node::Exact), 2);
+        assert_eq!(table.get(3, 6).unwrap().score, 3);
     }
 
-    //fn make_move(&mut self, play: &Play) {
-    //    self.board.make_move(play);
-    //}
+    #[test]
+    fn test_torn_entry_is_ignored() {
+        let table = HashTable::<Board>::with_capacity(1);
+        table.set(1, pv(3, Node::Exact), 0);
+        // Another thread's write to the entry which has only stored the new move so far
+        let slot = &table.table[0].0[0];
+        let data = slot.data.load(Ordering::Relaxed);
+        slot.data.store(data ^ 1, Ordering::Relaxed);
+        assert!(table.get(1, 0).is_none());
+
+        // The key can still be stored
+        table.set(1, pv(3, Node::Exact), 0);
+        assert_eq!(table.get(1, 0).unwrap().depth, 3);
+    }
 
-    fn make_move_str(&mut self, play: &str) -> bool {
-        for p in self.board.generate_moves() {
-            let play_str = format!("{}", p).to_lowercase();
-            if play == play_str {
-                let result = self.board.make_move(&p);
-                self.moves.clear_key(self.board.key); // TODO this is a hack to try to fix bad
-                                                      // cache hits, particularly for draws
-                return result; // TODO change this to return Result
-            };
+    #[test]
+    fn test_clear_key() {
+        let table = HashTable::<Board>::with_capacity(1);
+        table.set(1, pv(1, Node::Exact), 0);
+        table.set(2, pv(2, Node::Exact), 0);
+        table.clear_key(1);
+        assert!(table.get(1, 0).is_none());
+        assert_eq!(table.get(2, 0).unwrap().depth, 2);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let table = HashTable::<Board>::with_capacity(16);
+        for key in 0..40 {
+            table.set(key, pv(key as usize % 10, Node::Beta), 0);
+        }
+        let mut mate = pv(3, Node::Exact);
+        mate.score = CHECKMATE_SCORE - 5;
+        table.set(100, mate, 2);
+        let mut bytes = Vec::new();
+        table.write(&mut bytes).unwrap();
+
+        // Read into a table of another size, replacing what was there
+        let other = HashTable::<Board>::with_capacity(64);
+        other.set(1000, pv(1, Node::Exact), 0);
+        other.read(&mut bytes.as_slice()).unwrap();
+        assert!(other.get(1000, 0).is_none());
+        for key in 0..40 {
+            let entry = other.get(key, 0).unwrap();
+            assert_eq!(entry.depth, key as usize % 10);
+            assert!(matches!(entry.node, Node::Beta));
+            assert_eq!(entry.play, pv(0, Node::Beta).play);
         }
-        false
+        assert_eq!(other.get(100, 4).unwrap().score, CHECKMATE_SCORE - 7);
+    }
+
+    #[test]
+    fn test_read_rejects_invalid_files() {
+        let table = HashTable::<Board>::with_capacity(1);
+        table.set(1, pv(1, Node::Exact), 0);
+        let mut bytes = Vec::new();
+        table.write(&mut bytes).unwrap();
+        let read = |bytes: &[u8]| {
+            let table = HashTable::<Board>::with_capacity(1);
+            table.read(&mut &bytes[..]).map_err(|e| e.to_string())
+        };
+        assert_eq!(read(&bytes), Ok(()));
+
+        let mut other = bytes.clone();
+        other[0] = b'X';
+        assert_eq!(read(&other), Err("not a saved transposition table".into()));
+        let mut other = bytes.clone();
+        other[8..12].copy_from_slice(&(TT_FILE_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            read(&other),
+            Err(format!("unsupported table version {}", TT_FILE_VERSION + 1))
+        );
+        let mut other = bytes.clone();
+        other[12..20].copy_from_slice(&(Board::key_check() ^ 1).to_le_bytes());
+        assert_eq!(
+            read(&other),
+            Err("the table was saved with different keys".into())
+        );
+        // The node type is the last byte
+        let mut other = bytes.clone();
+        *other.last_mut().unwrap() = 9;
+        assert_eq!(read(&other), Err("invalid node type 9".into()));
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
     }
+}
 
-    fn display_board(&self) {
-        println!("{}", self.board);
+#[cfg(test)]
+mod test_generic_game {
+    use super::{AlphaBeta, Engine, SearchParameters};
+    use crate::{Color, FenError, FenField, Game, NoMoveToUndo};
+    use pretty_assertions::assert_eq;
+    use std::fmt;
+
+    /// Players take turns removing 1-3 stones, the player who takes the last stone wins
+    #[derive(Copy, Clone, Debug)]
+    struct Nim {
+        stones: u8,
+        history: [u8; 32],
+        ply: usize,
+        line_ply: usize,
     }
 
-    fn pv_line(&self) -> PvLine {
-        let mut pv_line = Vec::new();
-        let mut pv = self.moves.get(self.board.key).unwrap();
-        pv_line.push(pv.play);
-        while let Some(next) = self.moves.get(pv.next_key) {
-            pv_line.push(next.play);
-            pv = next;
-            if pv_line.len() >= 16 {
-                break; // TODO resolve hash colisions to prevent errors here
+    impl fmt::Display for Nim {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{} stones", self.stones)
+        }
+    }
+
+    impl Game for Nim {
+        type Move = u8;
+
+        fn from_fen(fen: &str) -> Result<Self, FenError> {
+            Ok(Self {
+                stones: fen
+                    .parse()
+                    .map_err(|_| FenError::InvalidField(FenField::Placement, fen.to_string()))?,
+                history: [0; 32],
+                ply: 0,
+                line_ply: 0,
+            })
+        }
+
+        fn key(&self) -> u64 {
+            self.stones as u64 * 2 + (self.ply % 2) as u64
+        }
+
+        fn encode_move(play: &u8) -> Option<u32> {
+            Some(*play as u32)
+        }
+
+        fn decode_move(bits: u32) -> Option<u8> {
+            bits.try_into().ok()
+        }
+
+        fn active_color(&self) -> Color {
+            if self.ply.is_multiple_of(2) {
+                Color::White
+            } else {
+                Color::Black
             }
         }
-        PvLine { line: pv_line }
+
+        fn line_ply(&self) -> usize {
+            self.line_ply
+        }
+
+        fn reset_line_ply(&mut self) {
+            self.line_ply = 0;
+        }
+
+        fn is_draw(&self) -> bool {
+            false
+        }
+
+        // With no stones left the side to move has lost, like being checkmated
+        fn in_check(&self) -> bool {
+            self.stones == 0
+        }
+
+        fn generate_moves(&self) -> Vec<u8> {
+            (1..=self.stones.min(3)).collect()
+        }
+
+        fn generate_captures(&self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        fn generate_promotions(&self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        fn generate_quiet_checks(&self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        fn legal_plays(&self) -> Vec<u8> {
+            self.generate_moves()
+        }
+
+        fn is_pseudo_legal(&self, play: &u8) -> bool {
+            (1..=3).contains(play) && *play <= self.stones
+        }
+
+        fn is_quiet(&self, _play: &u8) -> bool {
+            true
+        }
+
+        fn make_move(&mut self, play: &u8) -> bool {
+            if !self.is_pseudo_legal(play) {
+                return false;
+            }
+            self.stones -= play;
+            self.history[self.ply] = *play;
+            self.ply += 1;
+            self.line_ply += 1;
+            true
+        }
+
+        fn undo_move(&mut self) -> Result<u8, NoMoveToUndo> {
+            if self.ply == 0 {
+                return Err(NoMoveToUndo);
+            }
+            self.ply -= 1;
+            self.line_ply -= 1;
+            self.stones += self.history[self.ply];
+            Ok(self.history[self.ply])
+        }
+
+        fn eval(&self) -> i64 {
+            0
+        }
+
+        fn order_score(&self, play: &u8) -> i64 {
+            *play as i64
+        }
+
+        fn perft(&mut self, _depth: u8) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn test_search_other_game() {
+        // Leaving a multiple of four stones wins
+        for (stones, best_move) in [(5, 1), (6, 2), (7, 3)] {
+            let game = Nim::from_fen(&stones.to_string()).unwrap();
+            let mut e = <AlphaBeta<Nim> as Engine>::new(game);
+            let result = e.iterative_deepening_search(SearchParameters::builder().depth(8).build());
+            assert_eq!(result.unwrap(), best_move);
+        }
     }
 }
@@ -0,0 +1,273 @@
+use crate::board::Board;
+use crate::misc::FenError;
+use crate::play::Play;
+use std::fmt;
+
+/// A position in Extended Position Description, as used by test suites and tuning datasets
+///
+/// An EPD is the first four fields of a FEN followed by operations, each an opcode with its
+/// operands and a semicolon, e.g. `<fen> bm Nf3 Nc3; id "test 1";`. The move clocks are taken
+/// from the hmvc and fmvn operations if present. Every operation is kept, the common ones are
+/// also parsed into fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Epd {
+    pub board: Board,
+    pub fen: String, // the board as a FEN, with the move clocks
+    pub id: Option<String>,
+    pub best_moves: Vec<Play>,   // bm
+    pub avoid_moves: Vec<Play>,  // am
+    pub evaluation: Option<i64>, // ce, in centipawns from the point of view of the side to move
+    pub pv: Vec<Play>,           // each move is played after the ones before it
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+/// Reasons an EPD can't be parsed, see Epd::parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EpdError {
+    /// The position fields, with the move clocks, aren't a valid FEN
+    InvalidFen(FenError),
+    /// A quoted operand without the closing quote
+    UnterminatedString(String),
+    /// The operand of the opcode isn't valid, e.g. a move which isn't legal or a ce which isn't
+    /// a number
+    InvalidOperand(String, String),
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpdError::InvalidFen(e) => write!(f, "{}", e),
+            EpdError::UnterminatedString(s) => write!(f, "unterminated string: {}", s),
+            EpdError::InvalidOperand(opcode, operand) => {
+                write!(f, "invalid {} operand: {}", opcode, operand)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EpdError {}
+
+impl From<EpdError> for String {
+    fn from(e: EpdError) -> Self {
+        e.to_string()
+    }
+}
+
+impl From<FenError> for EpdError {
+    fn from(e: FenError) -> Self {
+        EpdError::InvalidFen(e)
+    }
+}
+
+impl Epd {
+    pub fn parse(epd: &str) -> Result<Epd, EpdError> {
+        let mut rest = epd.trim();
+        let mut fields = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let (field, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            fields.push(field);
+            rest = tail.trim_start();
+        }
+        let operations = parse_operations(rest)?;
+
+        let operand = |opcode: &str| {
+            operations
+                .iter()
+                .find(|(o, _)| o == opcode)
+                .and_then(|(_, operands)| operands.first())
+                .map(String::as_str)
+        };
+        let fen = format!(
+            "{} {} {}",
+            fields.join(" "),
+            operand("hmvc").unwrap_or("0"),
+            operand("fmvn").unwrap_or("1"),
+        );
+        let board = Board::from_fen(&fen)?;
+
+        let mut epd = Epd {
+            board,
+            fen,
+            id: operand("id").map(str::to_string),
+            best_moves: Vec::new(),
+            avoid_moves: Vec::new(),
+            evaluation: None,
+            pv: Vec::new(),
+            operations: Vec::new(),
+        };
+        for (opcode, operands) in &operations {
+            let invalid = |operand: &str| EpdError::InvalidOperand(opcode.clone(), operand.into());
+            match opcode.as_str() {
+                "bm" | "am" => {
+                    let moves = operands
+                        .iter()
+                        .map(|san| epd.board.parse_san(san).map_err(|_| invalid(san)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    match opcode.as_str() {
+                        "bm" => epd.best_moves = moves,
+                        _ => epd.avoid_moves = moves,
+                    }
+                }
+                "ce" => {
+                    let ce = operands.first().map_or("", String::as_str);
+                    epd.evaluation = Some(ce.parse().map_err(|_| invalid(ce))?);
+                }
+                "pv" => {
+                    let mut board = epd.board.clone();
+                    for san in operands {
+                        let play = board.parse_san(san).map_err(|_| invalid(san))?;
+                        board.make_move(&play);
+                        epd.pv.push(play);
+                    }
+                }
+                _ => (),
+            }
+        }
+        epd.operations = operations;
+        Ok(epd)
+    }
+
+    /// If the move is one of the best moves and none of the moves to avoid, false if the EPD has
+    /// neither bm nor am
+    pub fn is_solved_by(&self, play: &Play) -> bool {
+        if self.best_moves.is_empty() && self.avoid_moves.is_empty() {
+            return false;
+        }
+        (self.best_moves.is_empty() || self.best_moves.contains(play))
+            && !self.avoid_moves.contains(play)
+    }
+
+    /// The operands of the first operation with the opcode, e.g. `c9` for a game result
+    pub fn operation(&self, opcode: &str) -> Option<&[String]> {
+        self.operations
+            .iter()
+            .find(|(o, _)| o == opcode)
+            .map(|(_, operands)| operands.as_slice())
+    }
+}
+
+// Split `opcode operand ...;` operations, operands in double quotes may contain spaces and
+// semicolons. The semicolon after the last operation may be left out.
+fn parse_operations(text: &str) -> Result<Vec<(String, Vec<String>)>, EpdError> {
+    let mut operations = Vec::new();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => {
+                chars.next();
+                if !tokens.is_empty() {
+                    let opcode = tokens.remove(0);
+                    operations.push((opcode, std::mem::take(&mut tokens)));
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => string.push(c),
+                        None => return Err(EpdError::UnterminatedString(string)),
+                    }
+                }
+                tokens.push(string);
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    if !tokens.is_empty() {
+        let opcode = tokens.remove(0);
+        operations.push((opcode, tokens));
+    }
+    Ok(operations)
+}
+
+#[cfg(test)]
+mod test_epd {
+    use super::{Epd, EpdError};
+    use crate::board::Board;
+    use crate::misc::FenError;
+    use crate::play::Play;
+    use pretty_assertions::assert_eq;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
+
+    #[test]
+    fn test_operations() {
+        let line = format!(
+            "{} bm e4 d4; am f3; id \"start; quoted\"; ce -35; pv e4 e5 Nf3; c9 \"1-0\";",
+            START
+        );
+        let epd = Epd::parse(&line).unwrap();
+        assert_eq!(epd.board, Board::new());
+        let uci = |moves: &[Play]| -> Vec<String> { moves.iter().map(Play::to_string).collect() };
+        assert_eq!(uci(&epd.best_moves), ["e2e4", "d2d4"]);
+        assert_eq!(uci(&epd.avoid_moves), ["f2f3"]);
+        assert_eq!(uci(&epd.pv), ["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(epd.id.as_deref(), Some("start; quoted"));
+        assert_eq!(epd.evaluation, Some(-35));
+        assert_eq!(epd.operation("c9"), Some(&["1-0".to_string()][..]));
+        assert_eq!(epd.operation("bm").map(<[String]>::len), Some(2));
+        assert_eq!(epd.operation("dm"), None);
+    }
+
+    #[test]
+    fn test_move_clocks() {
+        let epd = Epd::parse(&format!("{} hmvc 12; fmvn 30", START)).unwrap();
+        assert_eq!(epd.board.halfmove_clock(), 12);
+        assert_eq!(epd.board.move_number(), 30);
+        assert_eq!(epd.fen, format!("{} 12 30", START));
+        // No operations at all
+        assert_eq!(Epd::parse(START).unwrap().board, Board::new());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Epd::parse(&format!("{} bm e5;", START)),
+            Err(EpdError::InvalidOperand("bm".into(), "e5".into()))
+        );
+        assert_eq!(
+            Epd::parse(&format!("{} ce high;", START)),
+            Err(EpdError::InvalidOperand("ce".into(), "high".into()))
+        );
+        assert_eq!(
+            Epd::parse(&format!("{} id \"unterminated;", START)),
+            Err(EpdError::UnterminatedString("unterminated;".into()))
+        );
+        assert!(matches!(
+            Epd::parse("rnbqkbnr/pppppppp/8 w KQkq -"),
+            Err(EpdError::InvalidFen(FenError::InvalidField(..)))
+        ));
+    }
+
+    #[test]
+    fn test_is_solved_by() {
+        let find = |epd: &Epd, san: &str| epd.board.parse_san(san).unwrap();
+        let epd = Epd::parse(&format!("{} bm e4 d4; am f3;", START)).unwrap();
+        assert!(epd.is_solved_by(&find(&epd, "e4")));
+        assert!(!epd.is_solved_by(&find(&epd, "Nf3")));
+        assert!(!epd.is_solved_by(&find(&epd, "f3")));
+
+        let epd = Epd::parse(&format!("{} am f3;", START)).unwrap();
+        assert!(epd.is_solved_by(&find(&epd, "Nf3")));
+        assert!(!epd.is_solved_by(&find(&epd, "f3")));
+
+        let epd = Epd::parse(&format!("{} id \"no moves\";", START)).unwrap();
+        assert!(!epd.is_solved_by(&find(&epd, "e4")));
+    }
+}
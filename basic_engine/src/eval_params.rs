@@ -0,0 +1,350 @@
+use crate::misc::{Color, Piece};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const TABLE_NAMES: [&str; 6] = ["pawns", "knights", "bishops", "rooks", "queens", "kings"];
+
+lazy_static! {
+    // Shared by every board created without other parameters
+    static ref DEFAULT: Arc<EvalParams> = Arc::new(EvalParams::default());
+}
+
+pub(crate) fn init_params() {
+    lazy_static::initialize(&DEFAULT);
+}
+
+/// The weights used by the static evaluation
+///
+/// Boards are created with the defaults, Game::set_eval_params and Engine::set_eval_params switch
+/// them to others. Piece-square tables are laid out as the board is seen by white, with rank 8 first. Black uses them mirrored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EvalParams {
+    pub material: [u32; 6], // Indexed by piece
+    pub tables: [[isize; 64]; 6],
+    pub king_endgame: [isize; 64], // Used for kings instead once the queens are off
+    // Largest material advantage, without pawns, which is still scaled down as hard to win with
+    pub drawish_advantage: u32,
+    pub passed_pawn: [isize; 8], // Indexed by rank from the pawn's side, halved when blockaded
+    pub passed_pawn_rook_behind: isize,
+    // Per square the enemy king is further than our king from the square in front, in endgames
+    pub passed_pawn_king_distance: isize,
+    // King safety penalties on the king's file and those next to it, indexed by how many ranks in
+    // front of the king the nearest pawn is and scaled down as pieces come off
+    pub king_shelter: [isize; 8], // Our pawns, when they've advanced
+    pub king_open_file: isize,    // When we have no pawn in front of the king
+    pub pawn_storm: [isize; 8],   // Enemy pawns
+}
+
+impl EvalParams {
+    pub fn material(&self, piece: Piece) -> u32 {
+        self.material[piece as usize]
+    }
+
+    /// Piece-square value of a piece on the square `index`, from the point of view of `color`
+    pub fn get_value(&self, index: usize, piece: Piece, color: Color) -> isize {
+        self.tables[piece as usize][table_index(index, color)]
+    }
+
+    /// Like get_value, but kings are encouraged to centralize rather than stay castled
+    pub fn get_endgame_value(&self, index: usize, piece: Piece, color: Color) -> isize {
+        match piece {
+            Piece::King => self.king_endgame[table_index(index, color)],
+            _ => self.get_value(index, piece, color),
+        }
+    }
+
+    /// Read parameters written in the format of Display, e.g. by the tuner
+    pub fn load(path: &str) -> Result<Self, String> {
+        fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path, e))?
+            .parse()
+    }
+
+    fn set(&mut self, name: &str, values: &[i64]) -> Result<(), String> {
+        let expect = |len: usize| match values.len() == len {
+            true => Ok(()),
+            false => Err(format!(
+                "expected {} values for {}, found {}",
+                len,
+                name,
+                values.len()
+            )),
+        };
+        match name {
+            "material" => {
+                // The king's value can be left out
+                if values.len() != 5 {
+                    expect(6)?;
+                }
+                for (m, &v) in self.material.iter_mut().zip(values) {
+                    *m = u32::try_from(v).map_err(|_| format!("invalid material: {}", v))?;
+                }
+            }
+            "drawish_advantage" => {
+                expect(1)?;
+                self.drawish_advantage =
+                    u32::try_from(values[0]).map_err(|_| format!("invalid {}", name))?;
+            }
+            "passed_pawn" => {
+                expect(8)?;
+                for (p, &v) in self.passed_pawn.iter_mut().zip(values) {
+                    *p = v as isize;
+                }
+            }
+            "king_shelter" | "pawn_storm" => {
+                expect(8)?;
+                let table = match name {
+                    "king_shelter" => &mut self.king_shelter,
+                    _ => &mut self.pawn_storm,
+                };
+                for (p, &v) in table.iter_mut().zip(values) {
+                    *p = v as isize;
+                }
+            }
+            "king_open_file" => {
+                expect(1)?;
+                self.king_open_file = values[0] as isize;
+            }
+            "passed_pawn_rook_behind" => {
+                expect(1)?;
+                self.passed_pawn_rook_behind = values[0] as isize;
+            }
+            "passed_pawn_king_distance" => {
+                expect(1)?;
+                self.passed_pawn_king_distance = values[0] as isize;
+            }
+            "kings_endgame" => {
+                expect(64)?;
+                for (t, &v) in self.king_endgame.iter_mut().zip(values) {
+                    *t = v as isize;
+                }
+            }
+            _ => {
+                let table = TABLE_NAMES
+                    .iter()
+                    .position(|&t| t == name)
+                    .ok_or_else(|| format!("unknown parameter: {}", name))?;
+                expect(64)?;
+                for (t, &v) in self.tables[table].iter_mut().zip(values) {
+                    *t = v as isize;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The tables are laid out with rank 8 first, so white's squares (a1 is 0) are mirrored
+fn table_index(index: usize, color: Color) -> usize {
+    match color {
+        Color::White => index ^ 56,
+        Color::Black => index,
+    }
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        // From https://www.chessprogramming.org/Simplified_Evaluation_Function
+        #[rustfmt::skip]
+        let pawns = [
+            0,  0,  0,  0,  0,  0,  0,  0,
+            50, 50, 50, 50, 50, 50, 50, 50,
+            10, 10, 20, 30, 30, 20, 10, 10,
+             5,  5, 10, 25, 25, 10,  5,  5,
+             1,  1,  1, 20, 20,  1,  1,  1,
+             5, -5,-10,  0,  0,-10, -5,  5,
+             5, 10, 10,-20,-20, 10, 10,  5,
+             0,  0,  0,  0,  0,  0,  0,  0
+        ];
+        #[rustfmt::skip]
+        let knights = [
+            -50,-40,-30,-30,-30,-30,-40,-50,
+            -40,-20,  0,  0,  0,  0,-20,-40,
+            -30,  0, 10, 15, 15, 10,  0,-30,
+            -30,  5, 15, 20, 20, 15,  5,-30,
+            -30,  0, 15, 20, 20, 15,  0,-30,
+            -30,  5, 10, 15, 15, 10,  5,-30,
+            -40,-20,  0,  5,  5,  0,-20,-40,
+            -50,-40,-30,-30,-30,-30,-40,-50,
+        ];
+        #[rustfmt::skip]
+        let bishops = [
+            -20,-10,-10,-10,-10,-10,-10,-20,
+            -10,  0,  0,  0,  0,  0,  0,-10,
+            -10,  0,  5, 10, 10,  5,  0,-10,
+            -10,  5,  5, 10, 10,  5,  5,-10,
+            -10,  0, 10, 10, 10, 10,  0,-10,
+            -10, 10, 10, 10, 10, 10, 10,-10,
+            -10,  5,  0,  0,  0,  0,  5,-10,
+            -20,-10,-10,-10,-10,-10,-10,-20,
+        ];
+        #[rustfmt::skip]
+        let rooks = [
+             0,  0,  0,  0,  0,  0,  0,  0,
+             5, 10, 10, 10, 10, 10, 10,  5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+            -5,  0,  0,  0,  0,  0,  0, -5,
+             0,  0,  0,  5,  5,  0,  0,  0
+        ];
+        #[rustfmt::skip]
+        let queens = [
+            -20,-10,-10, -5, -5,-10,-10,-20,
+            -10,  0,  0,  0,  0,  0,  0,-10,
+            -10,  0,  5,  5,  5,  5,  0,-10,
+             -5,  0,  5,  5,  5,  5,  0, -5,
+              0,  0,  5,  5,  5,  5,  0, -5,
+            -10,  5,  5,  5,  5,  5,  0,-10,
+            -10,  0,  5,  0,  0,  0,  0,-10,
+            -20,-10,-10, -5, -5,-10,-10,-20
+        ];
+        #[rustfmt::skip]
+        let kings = [
+            -30,-40,-40,-50,-50,-40,-40,-30,
+            -30,-40,-40,-50,-50,-40,-40,-30,
+            -30,-40,-40,-50,-50,-40,-40,-30,
+            -30,-40,-40,-50,-50,-40,-40,-30,
+            -20,-30,-30,-40,-40,-30,-30,-20,
+            -10,-20,-20,-20,-20,-20,-20,-10,
+             20, 20,  0,  0,  0,  0, 20, 20,
+             20, 30, 10,  0,  0, 10, 30, 20
+        ];
+        #[rustfmt::skip]
+        let kings_endgame = [
+            -50,-40,-30,-20,-20,-30,-40,-50,
+            -30,-20,-10,  0,  0,-10,-20,-30,
+            -30,-10, 20, 30, 30, 20,-10,-30,
+            -30,-10, 30, 40, 40, 30,-10,-30,
+            -30,-10, 30, 40, 40, 30,-10,-30,
+            -30,-10, 20, 30, 30, 20,-10,-30,
+            -30,-30,  0,  0,  0,  0,-30,-30,
+            -50,-30,-30,-30,-30,-30,-30,-50
+        ];
+        Self {
+            material: [100, 310, 320, 500, 900, 10000],
+            tables: [pawns, knights, bishops, rooks, queens, kings],
+            king_endgame: kings_endgame,
+            drawish_advantage: 350,
+            passed_pawn: [0, 5, 10, 20, 35, 60, 100, 0],
+            passed_pawn_rook_behind: 15,
+            passed_pawn_king_distance: 5,
+            king_shelter: [0, 0, 10, 20, 25, 25, 25, 25],
+            king_open_file: 30,
+            pawn_storm: [0, 0, 30, 15, 5, 0, 0, 0],
+        }
+    }
+}
+
+impl fmt::Display for EvalParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let material: Vec<String> = self.material.iter().map(|v| v.to_string()).collect();
+        writeln!(f, "material: {}", material.join(" "))?;
+        writeln!(f, "drawish_advantage: {}", self.drawish_advantage)?;
+        let passed: Vec<String> = self.passed_pawn.iter().map(|v| v.to_string()).collect();
+        writeln!(f, "passed_pawn: {}", passed.join(" "))?;
+        writeln!(
+            f,
+            "passed_pawn_rook_behind: {}",
+            self.passed_pawn_rook_behind
+        )?;
+        writeln!(
+            f,
+            "passed_pawn_king_distance: {}",
+            self.passed_pawn_king_distance
+        )?;
+        for (name, table) in [
+            ("king_shelter", &self.king_shelter),
+            ("pawn_storm", &self.pawn_storm),
+        ] {
+            let values: Vec<String> = table.iter().map(|v| v.to_string()).collect();
+            writeln!(f, "{}: {}", name, values.join(" "))?;
+        }
+        writeln!(f, "king_open_file: {}", self.king_open_file)?;
+        let tables = self.tables.iter().chain([&self.king_endgame]);
+        for (name, table) in TABLE_NAMES.iter().chain(&["kings_endgame"]).zip(tables) {
+            writeln!(f, "{}:", name)?;
+            for rank in table.chunks_exact(8) {
+                let rank: Vec<String> = rank.iter().map(|v| format!("{:4}", v)).collect();
+                writeln!(f, "{}", rank.join(""))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for EvalParams {
+    type Err = String;
+
+    /// Parse `name: values` entries, any entry left out keeps its default value
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = Self::default();
+        let mut name: Option<&str> = None;
+        let mut values: Vec<i64> = Vec::new();
+        // A trailing empty name flushes the last entry
+        for token in s.split_whitespace().chain([":"]) {
+            if let Some(next) = token.strip_suffix(':') {
+                if let Some(name) = name {
+                    params.set(name, &values)?;
+                }
+                name = Some(next);
+                values.clear();
+            } else {
+                let value = token
+                    .parse()
+                    .map_err(|_| format!("invalid value: {}", token))?;
+                values.push(value);
+            }
+        }
+        Ok(params)
+    }
+}
+
+/// The default parameters boards are created with
+pub(crate) fn default_params() -> Arc<EvalParams> {
+    Arc::clone(&DEFAULT)
+}
+
+#[cfg(test)]
+mod test_eval_params {
+    use super::EvalParams;
+    use crate::misc::{Color, Piece};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_round_trip() {
+        let mut params = EvalParams::default();
+        params.material[1] = 330;
+        params.tables[0][8] = 60;
+        params.king_endgame[0] = -60;
+        params.passed_pawn[6] = 120;
+        params.passed_pawn_king_distance = 7;
+        params.pawn_storm[2] = 40;
+        params.king_open_file = 20;
+        let parsed: EvalParams = params.to_string().parse().unwrap();
+        assert_eq!(parsed, params);
+    }
+
+    #[test]
+    fn test_partial_file_keeps_defaults() {
+        let parsed: EvalParams = "material: 100 300 330 500 950".parse().unwrap();
+        let defaults = EvalParams::default();
+        assert_eq!(parsed.material(Piece::Knight), 300);
+        assert_eq!(parsed.material(Piece::King), defaults.material(Piece::King));
+        assert_eq!(parsed.tables, defaults.tables);
+
+        assert!("pawns: 1 2 3".parse::<EvalParams>().is_err());
+        assert!("unknown: 1".parse::<EvalParams>().is_err());
+    }
+
+    #[test]
+    fn test_tables_mirrored_for_black() {
+        let params = EvalParams::default();
+        // A pawn one step from promoting, e2 is 12 and e7 is 52
+        assert_eq!(params.get_value(52, Piece::Pawn, Color::White), 50);
+        assert_eq!(params.get_value(12, Piece::Pawn, Color::Black), 50);
+    }
+}
@@ -0,0 +1,26 @@
+use crate::Game;
+
+/// A static evaluation used by the search, in centipawns from the point of view of the side to
+/// move
+///
+/// Evaluators are shared between the search threads. Closures taking a position can be used as
+/// evaluators, which is handy for experiments.
+pub trait Evaluator<G: Game>: Send + Sync {
+    fn eval(&self, board: &G) -> i64;
+}
+
+/// The hand written evaluation of the game itself, see Game::eval
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClassicalEval;
+
+impl<G: Game> Evaluator<G> for ClassicalEval {
+    fn eval(&self, board: &G) -> i64 {
+        board.eval()
+    }
+}
+
+impl<G: Game, F: Fn(&G) -> i64 + Send + Sync> Evaluator<G> for F {
+    fn eval(&self, board: &G) -> i64 {
+        self(board)
+    }
+}
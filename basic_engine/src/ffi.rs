@@ -0,0 +1,251 @@
+//! C bindings for embedding the engine, see include/arche.h
+//!
+//! Strings returned to C are allocated by Rust and must be released with arche_string_free.
+//!
+//! A panic mustn't unwind into C, so each function catches it and returns its error value
+//! instead. The engine may be left part way through an update, it should only be freed then.
+
+use crate::board::Board;
+use crate::engine::{AlphaBeta, Engine, SearchParameters};
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// An engine owned by C code, only ever handled through a pointer
+pub struct ArcheEngine {
+    engine: AlphaBeta<Board>,
+}
+
+/// Create an engine at the starting position with a transposition table of `hash_size_mb`
+/// megabytes, release it with arche_engine_free
+///
+/// Returns null if the engine couldn't be created.
+#[no_mangle]
+pub extern "C" fn arche_engine_new(hash_size_mb: usize) -> *mut ArcheEngine {
+    catch_panic(ptr::null_mut(), || {
+        crate::init();
+        let engine = AlphaBeta::with_hash_size(Board::new(), hash_size_mb);
+        Box::into_raw(Box::new(ArcheEngine { engine }))
+    })
+}
+
+/// Release an engine created by arche_engine_new
+///
+/// # Safety
+///
+/// `engine` must be null or a pointer from arche_engine_new which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn arche_engine_free(engine: *mut ArcheEngine) {
+    if !engine.is_null() {
+        catch_panic((), || drop(Box::from_raw(engine)));
+    }
+}
+
+/// Replace the transposition table with an empty one of `megabytes`
+///
+/// Returns 0 on success and -1 if the table couldn't be allocated.
+///
+/// # Safety
+///
+/// `engine` must be a live pointer from arche_engine_new.
+#[no_mangle]
+pub unsafe extern "C" fn arche_engine_set_hash_size(
+    engine: *mut ArcheEngine,
+    megabytes: usize,
+) -> c_int {
+    let engine = &mut (*engine).engine;
+    catch_panic(-1, || {
+        engine.set_hash_size(megabytes);
+        0
+    })
+}
+
+/// Set the position from a FEN, or the starting position if `fen` is null, followed by the
+/// space separated moves in UCI notation if `moves` isn't null
+///
+/// Returns 0 on success and -1 if the FEN or one of the moves isn't valid, the position is then
+/// left as it was before the invalid move.
+///
+/// # Safety
+///
+/// `engine` must be a live pointer from arche_engine_new, `fen` and `moves` must each be null or
+/// a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn arche_engine_set_position(
+    engine: *mut ArcheEngine,
+    fen: *const c_char,
+    moves: *const c_char,
+) -> c_int {
+    let engine = &mut (*engine).engine;
+    catch_panic(-1, || {
+        let fen = match str_arg(fen) {
+            Some(Ok(fen)) => fen,
+            Some(Err(())) => return -1,
+            None => START_FEN,
+        };
+        if engine.parse_fen(fen).is_err() {
+            return -1;
+        }
+        let moves = match str_arg(moves) {
+            Some(Ok(moves)) => moves,
+            Some(Err(())) => return -1,
+            None => "",
+        };
+        for play in moves.split_whitespace() {
+            if engine.make_move_str(play).is_err() {
+                return -1;
+            }
+        }
+        0
+    })
+}
+
+/// Search the position for `move_time_ms` milliseconds and return the best move in UCI
+/// notation, or null if there is no legal move or the search failed
+///
+/// # Safety
+///
+/// `engine` must be a live pointer from arche_engine_new.
+#[no_mangle]
+pub unsafe extern "C" fn arche_engine_search(
+    engine: *mut ArcheEngine,
+    move_time_ms: u64,
+) -> *mut c_char {
+    let engine = &mut (*engine).engine;
+    catch_panic(ptr::null_mut(), || {
+        let params = SearchParameters::builder()
+            .move_time(Duration::from_millis(move_time_ms))
+            .build();
+        match engine.iterative_deepening_search(params) {
+            Ok(best_move) => string_result(best_move.to_string()),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// The principal variation of the last search as space separated moves in UCI notation, empty
+/// if there hasn't been a search, or null if it couldn't be read
+///
+/// # Safety
+///
+/// `engine` must be a live pointer from arche_engine_new.
+#[no_mangle]
+pub unsafe extern "C" fn arche_engine_pv(engine: *const ArcheEngine) -> *mut c_char {
+    let engine = &(*engine).engine;
+    catch_panic(ptr::null_mut(), || {
+        string_result(engine.pv_line().to_string())
+    })
+}
+
+/// Release a string returned by one of the arche_engine functions
+///
+/// # Safety
+///
+/// `s` must be null or a string returned by this library which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn arche_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        catch_panic((), || drop(CString::from_raw(s)));
+    }
+}
+
+// The result of `f`, or `on_panic` if it panicked
+fn catch_panic<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    // The engine isn't used again after a panic other than to be freed
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+// None for a null pointer, Err if the string isn't UTF-8
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<Result<&'a str, ()>> {
+    match s.is_null() {
+        true => None,
+        false => Some(CStr::from_ptr(s).to_str().map_err(|_| ())),
+    }
+}
+
+// Moves and PVs never contain a nul byte
+fn string_result(s: String) -> *mut c_char {
+    CString::new(s).unwrap().into_raw()
+}
+
+#[cfg(test)]
+mod test_ffi {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    unsafe fn take_string(s: *mut c_char) -> Option<String> {
+        if s.is_null() {
+            return None;
+        }
+        let string = CStr::from_ptr(s).to_str().unwrap().to_string();
+        arche_string_free(s);
+        Some(string)
+    }
+
+    #[test]
+    fn test_search() {
+        unsafe {
+            let engine = arche_engine_new(16);
+            // Scholar's mate in one
+            let moves = CString::new("e2e4 e7e5 f1c4 b8c6 d1h5 g8f6").unwrap();
+            assert_eq!(
+                arche_engine_set_position(engine, ptr::null(), moves.as_ptr()),
+                0
+            );
+            assert_eq!(
+                take_string(arche_engine_search(engine, 100)).unwrap(),
+                "h5f7"
+            );
+            let pv = take_string(arche_engine_pv(engine)).unwrap();
+            assert!(pv.starts_with("h5f7"), "{}", pv);
+
+            // Checkmated, there is no move to return
+            let fen = CString::new("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+            assert_eq!(
+                arche_engine_set_position(engine, fen.as_ptr(), ptr::null()),
+                0
+            );
+            assert_eq!(take_string(arche_engine_search(engine, 10)), None);
+            arche_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_set_hash_size() {
+        unsafe {
+            let engine = arche_engine_new(1);
+            assert_eq!(arche_engine_set_hash_size(engine, 2), 0);
+            assert!(take_string(arche_engine_search(engine, 10)).is_some());
+            arche_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(catch_panic(-1, || 0), 0);
+        assert_eq!(catch_panic(-1, || panic!("unwound")), -1);
+    }
+
+    #[test]
+    fn test_invalid_position() {
+        unsafe {
+            let engine = arche_engine_new(16);
+            let fen = CString::new("not a fen").unwrap();
+            assert_eq!(
+                arche_engine_set_position(engine, fen.as_ptr(), ptr::null()),
+                -1
+            );
+            let moves = CString::new("e2e4 e2e4").unwrap();
+            assert_eq!(
+                arche_engine_set_position(engine, ptr::null(), moves.as_ptr()),
+                -1
+            );
+            arche_engine_free(engine);
+            arche_engine_free(ptr::null_mut());
+            arche_string_free(ptr::null_mut());
+        }
+    }
+}
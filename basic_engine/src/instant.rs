@@ -0,0 +1,38 @@
+//! The clock searches are timed with
+//!
+//! std::time::Instant panics on wasm32-unknown-unknown as there is no system clock, so there the
+//! time is read from JavaScript instead.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub use wasm::Instant;
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use std::ops::Sub;
+    use std::time::Duration;
+
+    /// Milliseconds since the epoch as given by Date.now()
+    #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+    pub struct Instant(f64);
+
+    impl Instant {
+        pub fn now() -> Self {
+            Instant(js_sys::Date::now())
+        }
+
+        /// Zero if the system clock has been set back since
+        pub fn elapsed(&self) -> Duration {
+            Duration::from_secs_f64((Self::now().0 - self.0).max(0.0) / 1000.0)
+        }
+    }
+
+    impl Sub<Duration> for Instant {
+        type Output = Instant;
+
+        fn sub(self, duration: Duration) -> Instant {
+            Instant(self.0 - duration.as_secs_f64() * 1000.0)
+        }
+    }
+}
@@ -1,22 +1,232 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod attacks;
 mod bitboard;
 mod board;
+mod book;
 mod engine;
+mod epd;
+mod eval_params;
+mod evaluator;
+pub mod ffi;
+mod instant;
 mod magic;
 mod misc;
+mod move_picker;
 mod play;
-mod pvt;
+mod syzygy;
+mod time_manager;
+mod tuning;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
 mod zorbrist;
 
-pub use board::Board;
-pub use engine::{AlphaBeta, Engine, SearchParameters};
-pub use misc::Color;
+pub use board::{
+    Board, DisplayOptions, GameState, LegalMove, LegalMoves, PositionError, MAX_PHASE,
+};
+pub use book::{polyglot_key, Book};
+pub use engine::{
+    AlphaBeta, Bound, Complexity, Engine, IterationCallback, MoveOf, PonderHandle, SearchError,
+    SearchInfo, SearchParameters, SearchParametersBuilder, SearchResult, StopHandle,
+    DEFAULT_HASH_SIZE_MB, MAX_CONTEMPT, MAX_SKILL_LEVEL,
+};
+pub use epd::{Epd, EpdError};
+pub use eval_params::EvalParams;
+pub use evaluator::{ClassicalEval, Evaluator};
+pub use instant::Instant;
+pub use magic::find_magics;
+pub use misc::{CastlePermissions, Color, FenError, FenField, Piece, PromotePiece};
+pub use play::{MoveError, MoveList, NoMoveToUndo, Play, MAX_MOVES};
 use std::fmt;
+use std::sync::Arc;
+pub use syzygy::{init_tablebases, tablebase_pieces, Dtz, Wdl};
+pub use time_manager::Clock;
+pub use tuning::{load_dataset, Tuner, TuningPosition, Weights};
 
-pub trait Game: fmt::Display {
-    fn from_fen(fen: &str) -> Result<Self, String>
+/// Build the lookup tables used for move generation, hashing and evaluation
+///
+/// The tables are otherwise built the first time they are used, which the first search would pay
+/// for. Calling this more than once is harmless.
+pub fn init() {
+    board::init_tables();
+    magic::init_tables();
+    eval_params::init_params();
+}
+
+/// A two player game which an Engine can search
+///
+/// Clones must be cheap enough to take at the root of a search, they're used to restore the
+/// position after an aborted search and to give each search thread its own position.
+pub trait Game: fmt::Display + Clone + Send + 'static {
+    /// A move in the game
+    type Move: Copy + Eq + fmt::Debug + fmt::Display + Send + Sync + 'static;
+
+    fn from_fen(fen: &str) -> Result<Self, FenError>
     where
         Self: std::marker::Sized;
+
+    /// Hash of the position, equal positions must have equal keys
+    fn key(&self) -> u64;
+
+    /// Hash of the position after a move, without making it
+    ///
+    /// This is only used to prefetch transposition table entries. The default makes the move on
+    /// a copy of the position, so games should override it with something cheaper.
+    fn key_after(&self, play: &Self::Move) -> u64 {
+        let mut game = self.clone();
+        game.make_move(play);
+        game.key()
+    }
+
+    /// Identifies how keys are computed, a saved transposition table is only loaded by a game
+    /// with the same key check
+    fn key_check() -> u64 {
+        0
+    }
+
+    /// Key of the position in Polyglot opening books, None if the game has no such books
+    fn polyglot_key(&self) -> Option<u64> {
+        None
+    }
+
+    /// The move packed into 32 bits for the transposition table, None if the game's moves can't
+    /// be packed (the table then stores nothing)
+    fn encode_move(_play: &Self::Move) -> Option<u32> {
+        None
+    }
+
+    /// A move packed by encode_move, None if the bits aren't one
+    fn decode_move(_bits: u32) -> Option<Self::Move> {
+        None
+    }
+
+    fn active_color(&self) -> Color;
+
+    /// Number of moves played since reset_line_ply, i.e. the distance from the search root
+    fn line_ply(&self) -> usize;
+
+    fn reset_line_ply(&mut self);
+
+    /// True if the position is drawn by rule, e.g. by repetition, the fifty move rule or
+    /// insufficient material
+    fn is_draw(&self) -> bool;
+
+    /// True if the side to move is in check
+    fn in_check(&self) -> bool;
+
+    /// Moves which may leave the side to move in check, make_move rejects those
+    fn generate_moves(&self) -> Vec<Self::Move>;
+
+    /// Moves which capture a piece, a subset of generate_moves
+    fn generate_captures(&self) -> Vec<Self::Move>;
+
+    /// The moves from generate_moves which aren't in generate_captures
+    fn generate_quiets(&self) -> Vec<Self::Move> {
+        let captures = self.generate_captures();
+        self.generate_moves()
+            .into_iter()
+            .filter(|m| !captures.contains(m))
+            .collect()
+    }
+
+    /// Promotions which don't capture, in the order they should be searched
+    fn generate_promotions(&self) -> Vec<Self::Move>;
+
+    /// Moves which give check without capturing
+    fn generate_quiet_checks(&self) -> Vec<Self::Move>;
+
+    /// Like generate_captures, but fills a reusable list, replacing any moves already in it
+    ///
+    /// The search keeps a list for each ply, so games which can generate into it avoid
+    /// allocating at every node. The same goes for the other _into generators.
+    fn generate_captures_into(&self, moves: &mut Vec<Self::Move>) {
+        *moves = self.generate_captures();
+    }
+
+    fn generate_quiets_into(&self, moves: &mut Vec<Self::Move>) {
+        *moves = self.generate_quiets();
+    }
+
+    fn generate_promotions_into(&self, moves: &mut Vec<Self::Move>) {
+        *moves = self.generate_promotions();
+    }
+
+    fn generate_quiet_checks_into(&self, moves: &mut Vec<Self::Move>) {
+        *moves = self.generate_quiet_checks();
+    }
+
+    /// Moves which don't leave the side to move in check
+    fn legal_plays(&self) -> Vec<Self::Move>;
+
+    /// True if the move could have been generated in this position, used to reject moves from
+    /// hash collisions
+    fn is_pseudo_legal(&self, play: &Self::Move) -> bool;
+
+    /// True if the move doesn't capture or promote
+    fn is_quiet(&self, play: &Self::Move) -> bool;
+
+    /// True if a capture is likely to lose material, these are searched after the quiet moves
+    fn is_losing_capture(&self, _play: &Self::Move) -> bool {
+        false
+    }
+
+    /// Find the move written in UCI notation, e.g. "e7e8q"
+    fn parse_uci_move(&self, uci: &str) -> Result<Self::Move, MoveError> {
+        self.generate_moves()
+            .into_iter()
+            .find(|m| m.to_string().eq_ignore_ascii_case(uci))
+            .ok_or_else(|| MoveError::Illegal(uci.to_string()))
+    }
+
+    /// Find the legal move written in standard algebraic notation, if the game supports it
+    fn parse_san(&self, san: &str) -> Result<Self::Move, MoveError> {
+        Err(MoveError::InvalidFormat(san.to_string()))
+    }
+
+    /// Play a move, returning false without changing the position if it's illegal
+    fn make_move(&mut self, play: &Self::Move) -> bool;
+
+    /// Play a move after checking it's legal, e.g. one given by the user
+    fn make_move_checked(&mut self, play: &Self::Move) -> Result<(), MoveError> {
+        if !self.legal_plays().contains(play) {
+            return Err(MoveError::Illegal(play.to_string()));
+        }
+        self.make_move(play);
+        Ok(())
+    }
+
+    /// Take back the last move played, returning it
+    fn undo_move(&mut self) -> Result<Self::Move, NoMoveToUndo>;
+
+    /// The position drawn for a terminal, games without their own drawing use Display
+    fn pretty(&self, _options: DisplayOptions) -> String {
+        self.to_string()
+    }
+
+    /// Static evaluation of the position from the point of view of the side to move
+    fn eval(&self) -> i64;
+
+    /// Evaluate with other parameters from now on, for games which use them
+    fn set_eval_params(&mut self, _params: Arc<EvalParams>) {}
+
+    /// Score used to order moves before searching them, higher scoring moves are tried first
+    fn order_score(&self, play: &Self::Move) -> i64;
+
+    /// Count the leaf nodes of the move tree to `depth`
+    fn perft(&mut self, depth: u8) -> u64;
+
+    /// The perft count to `depth` below each legal move
+    fn perft_divide(&mut self, depth: u8) -> Vec<(Self::Move, u64)> {
+        if depth == 0 {
+            return Vec::new();
+        }
+        let mut divide = Vec::new();
+        for m in self.legal_plays() {
+            self.make_move(&m);
+            divide.push((m, self.perft(depth - 1)));
+            self.undo_move().expect("the move was just made");
+        }
+        divide
+    }
 }
@@ -3,6 +3,35 @@ use crate::board::BASE_CONVERSIONS;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 
+lazy_static! {
+    static ref MAGIC: Magic = Magic::new();
+}
+
+// Build the move tables now rather than when they are first used, see crate::init
+pub(crate) fn init_tables() {
+    lazy_static::initialize(&MAGIC);
+}
+
+/// The squares a rook on the square attacks, up to and including the first occupied square in
+/// each direction
+#[inline(always)]
+pub fn rook_attacks(square: u8, occupied: u64) -> u64 {
+    MAGIC.rook_attacks(square, occupied)
+}
+
+/// The squares a bishop on the square attacks, up to and including the first occupied square in
+/// each direction
+#[inline(always)]
+pub fn bishop_attacks(square: u8, occupied: u64) -> u64 {
+    MAGIC.bishop_attacks(square, occupied)
+}
+
+/// The squares a queen on the square attacks, the rook and bishop attacks combined
+#[inline(always)]
+pub fn queen_attacks(square: u8, occupied: u64) -> u64 {
+    MAGIC.rook_attacks(square, occupied) | MAGIC.bishop_attacks(square, occupied)
+}
+
 // Mask for locations of possible blockers
 // for a given slider movement type and board square
 struct BlockerMasks {
@@ -22,107 +51,198 @@ struct MoveBoards {
     diagonal: Vec<Vec<u64>>,
 }
 
+// Where the moves of a slider on one square are in the shared attack table
+// The magic and shift aren't read when the moves are looked up with pext
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(all(feature = "pext", target_feature = "bmi2"), allow(dead_code))]
+struct MagicEntry {
+    mask: u64, // the squares which can block the slider
+    magic: u64,
+    shift: u8,
+    offset: usize,
+}
+
 pub struct Magic {
-    blocker_masks: BlockerMasks,
-    straight: [u64; 64],
-    straight_moves: Vec<Vec<u64>>,
-    straight_bits: [u8; 64],
-    diagonal: [u64; 64],
-    diagonal_moves: Vec<Vec<u64>>,
-    diagonal_bits: [u8; 64],
+    straight: [MagicEntry; 64],
+    diagonal: [MagicEntry; 64],
+    // The moves of every square, straight then diagonal, in one allocation
+    attacks: Vec<u64>,
+}
+
+// Magic numbers for the rooks and queens on each square
+#[rustfmt::skip]
+const STRAIGHT_MAGICS: [u64; 64] = [
+    0x41800031400080a8, 0x20c00420009000c4, 0xc0800c8060003000, 0x0580040800801000,
+    0x210002108801000c, 0x0500080300040002, 0x070000e401000200, 0x0100010004802046,
+    0x0100800882244000, 0x008140005000a000, 0x8940808020001000, 0x2a38800800803000,
+    0x4000800c0080a802, 0x0002001410220008, 0x2411004100020004, 0x4404800100004080,
+    0x4000228002400088, 0x0182404010042001, 0x0040848020001000, 0x000021001003002a,
+    0xc400808004000800, 0x1001808034008200, 0x0000240001904802, 0x0308020010804405,
+    0x0202400080032380, 0x0c1020064000d000, 0x0020100280200481, 0x0000500080080280,
+    0x0000180080040080, 0x0002000200088c10, 0x0203008100044200, 0xa404040200008641,
+    0x4100400080800034, 0x2010112000c00040, 0x1022801008802000, 0x20c2002812002041,
+    0x0004000c80801802, 0x5800020080800400, 0x1004800100800200, 0x0000008402000041,
+    0x04a8214000808010, 0x0100500660044000, 0x0020008090008020, 0x9801008810010020,
+    0x4214004080080800, 0x0242001810020084, 0x0001000200010004, 0x27010048840a0001,
+    0x2407008001204100, 0x0000400880200080, 0x4202500020008080, 0x0010100018018280,
+    0x0000d10088000500, 0x0082000810043200, 0x40190a3005481400, 0x1041000204805100,
+    0x3001048000906049, 0x0000318040002101, 0x0100420900106003, 0x0a450084a8100021,
+    0x2602002810046002, 0x0011000400088201, 0x010801080082100c, 0x0000210400502082,
+];
+
+// Magic numbers for the bishops and queens on each square
+#[rustfmt::skip]
+const DIAGONAL_MAGICS: [u64; 64] = [
+    0x011808c088004102, 0x0160010246004002, 0x4008860cc2002902, 0x04240c0080240000,
+    0x0004242020028100, 0x000201100a102884, 0x0424088431080000, 0x0601220610290800,
+    0x0004841002480101, 0x0820081001020428, 0x0000080200e20100, 0x01401c1402800202,
+    0x4240041c20000000, 0x8800211008041108, 0x0402204416601004, 0x0000018401080300,
+    0x0604004204440402, 0x9026012008211112, 0x00a8003004401220, 0x81081010860040c0,
+    0x0802120401200000, 0x2012020040500410, 0x008088204c500804, 0x0009004080682200,
+    0x0008148020210a06, 0x2110110008060584, 0x404230040a008200, 0x0304004004050042,
+    0x00009c0000802004, 0x8004010080900080, 0x800408900100902a, 0x4010420009050105,
+    0x00c8049211400604, 0x1214042020844504, 0x0004004802104080, 0x0002004141040100,
+    0x0020020018008080, 0x0102024902020082, 0x0110048b02508400, 0x0e040c104420e100,
+    0x20c2029024c64000, 0x040a0202260a6000, 0x20003a0486007000, 0x0d00382018000900,
+    0x8810404812000042, 0x106002015a0000c0, 0x1928032800902221, 0x4002008101000204,
+    0x8007010823400400, 0x30410401048a8401, 0x00001201008882c0, 0x0100148108480200,
+    0x0104002410440220, 0x0214102101050040, 0x4040080805076044, 0xc01007020400d001,
+    0x0402002094100890, 0x0480008401088200, 0x8000020020a41002, 0x6008006828840404,
+    0x1102000020204c46, 0x0009840414080200, 0x0221042006420600, 0x20100614b4040047,
+];
+
+/// Search for the straight and diagonal magic numbers of each square
+///
+/// The search takes a while so the results are embedded as STRAIGHT_MAGICS and DIAGONAL_MAGICS,
+/// this is only needed to regenerate them with `arche generate-magics`.
+pub fn find_magics() -> ([u64; 64], [u64; 64]) {
+    let bm = BlockerMasks::new();
+    let bb = BlockerBoards::new(&bm);
+    let mb = MoveBoards::new(&bb);
+    let mut rng: SmallRng = <SmallRng as SeedableRng>::seed_from_u64(102938423890384);
+    let mut straight = [0; 64];
+    let mut diagonal = [0; 64];
+    for index in 0..64 {
+        let bits = bb.straight_bits[index];
+        straight[index] =
+            Magic::find_magic(&mut rng, &bb.straight[index], &mb.straight[index], bits);
+        let bits = bb.diagonal_bits[index];
+        diagonal[index] =
+            Magic::find_magic(&mut rng, &bb.diagonal[index], &mb.diagonal[index], bits);
+    }
+    (straight, diagonal)
 }
 
 impl Magic {
+    /// Build the move tables from the precomputed magic numbers
     pub fn new() -> Self {
+        Self::with_magics(&STRAIGHT_MAGICS, &DIAGONAL_MAGICS)
+    }
+
+    fn with_magics(straight_magics: &[u64; 64], diagonal_magics: &[u64; 64]) -> Self {
         let bm = BlockerMasks::new();
         let bb = BlockerBoards::new(&bm);
         let mb = MoveBoards::new(&bb);
-        let mut straight_magic_idxs = Vec::new();
-        let mut straight_moves_magic = Vec::new();
-
-        let mut diagonal_magic_idxs = Vec::new();
-        let mut diagonal_moves_magic = Vec::new();
-        let mut rng: SmallRng = <SmallRng as SeedableRng>::seed_from_u64(102938423890384);
-
-        for index in 0..64 {
-            let blockers = &bb.straight[index];
-            let move_boards = &mb.straight[index];
-            let bits = bb.straight_bits[index];
-            let (s_magic, s_result) = Magic::find_magic(&mut rng, blockers, move_boards, bits);
-
-            straight_magic_idxs.push(s_magic);
-            straight_moves_magic.push(s_result);
-
-            let blockers = &bb.diagonal[index];
-            let move_boards = &mb.diagonal[index];
-            let bits = bb.diagonal_bits[index];
-            let (d_magic, d_result) = Magic::find_magic(&mut rng, blockers, move_boards, bits);
+        // The move tables are indexed by pext of the blockers instead of the magic multiplication
+        let pext = cfg!(all(feature = "pext", target_feature = "bmi2"));
+
+        let size = bb.straight.iter().chain(&bb.diagonal).map(Vec::len).sum();
+        let mut attacks = Vec::with_capacity(size);
+        // The blocker boards are generated by depositing the bits of their index into the blocker
+        // mask, so pext of a blocker board with the mask is its index and the move boards are
+        // already in order
+        let mut add_table = |mask: u64, magic: u64, bits: u8, blockers: &[u64], moves: &[u64]| {
+            let entry = MagicEntry {
+                mask,
+                magic,
+                shift: 64 - bits,
+                offset: attacks.len(),
+            };
+            match pext {
+                true => attacks.extend_from_slice(moves),
+                false => {
+                    let table = Magic::fill_moves(magic, blockers, moves, bits);
+                    attacks.extend(table.expect("invalid magic number"));
+                }
+            }
+            entry
+        };
 
-            diagonal_magic_idxs.push(d_magic);
-            diagonal_moves_magic.push(d_result);
+        let mut straight = [MagicEntry::default(); 64];
+        for (index, entry) in straight.iter_mut().enumerate() {
+            *entry = add_table(
+                bm.straight[index],
+                straight_magics[index],
+                bb.straight_bits[index],
+                &bb.straight[index],
+                &mb.straight[index],
+            );
+        }
+        let mut diagonal = [MagicEntry::default(); 64];
+        for (index, entry) in diagonal.iter_mut().enumerate() {
+            *entry = add_table(
+                bm.diagonal[index],
+                diagonal_magics[index],
+                bb.diagonal_bits[index],
+                &bb.diagonal[index],
+                &mb.diagonal[index],
+            );
         }
 
         Self {
-            blocker_masks: bm,
-            straight: straight_magic_idxs.try_into().unwrap(),
-            straight_moves: straight_moves_magic,
-            straight_bits: bb
-                .straight_bits
-                .iter()
-                .map(|i| 64 - i)
-                .collect::<Vec<u8>>()
-                .try_into()
-                .unwrap(),
-            diagonal: diagonal_magic_idxs.try_into().unwrap(),
-            diagonal_moves: diagonal_moves_magic,
-            diagonal_bits: bb
-                .diagonal_bits
-                .iter()
-                .map(|i| 64 - i)
-                .collect::<Vec<u8>>()
-                .try_into()
-                .unwrap(),
+            straight,
+            diagonal,
+            attacks,
         }
     }
 
-    fn find_magic(
-        rng: &mut SmallRng,
-        blockers: &[u64],
-        move_boards: &Vec<u64>,
-        bits: u8,
-    ) -> (u64, Vec<u64>) {
+    // The moves indexed by the magic number, None if two sets of blockers with different moves
+    // share an index
+    fn fill_moves(magic: u64, blockers: &[u64], move_boards: &[u64], bits: u8) -> Option<Vec<u64>> {
         let mut result = vec![0; 2usize.pow(u32::from(bits))];
         let shift = 64 - bits;
-        'outer: loop {
-            let magic_candidate: u64 = rng.gen::<u64>() & rng.gen::<u64>() & rng.gen::<u64>();
-            for item in &mut result {
-                *item = 0;
+        for (blocker, &move_b) in blockers.iter().zip(move_boards) {
+            let magic_index = blocker.wrapping_mul(magic) >> shift;
+            if result[magic_index as usize] == 0 {
+                result[magic_index as usize] = move_b;
+            } else if result[magic_index as usize] != move_b {
+                return None;
             }
+        }
+        Some(result)
+    }
 
-            for (blocker, &move_b) in blockers.iter().zip(move_boards) {
-                let magic_index = blocker.wrapping_mul(magic_candidate) >> shift;
-                if result[magic_index as usize] == 0 {
-                    result[magic_index as usize] = move_b;
-                } else if result[magic_index as usize] != move_b {
-                    continue 'outer;
-                }
+    fn find_magic(rng: &mut SmallRng, blockers: &[u64], move_boards: &[u64], bits: u8) -> u64 {
+        loop {
+            let magic_candidate: u64 = rng.gen::<u64>() & rng.gen::<u64>() & rng.gen::<u64>();
+            if Magic::fill_moves(magic_candidate, blockers, move_boards, bits).is_some() {
+                return magic_candidate;
             }
-            return (magic_candidate, result);
         }
     }
 
-    pub fn get_straight_move(&self, square: u8, mask: u64) -> u64 {
-        let blockers = mask & self.blocker_masks.straight[square as usize];
-        let index = (blockers.wrapping_mul(self.straight[square as usize]))
-            >> self.straight_bits[square as usize];
-        self.straight_moves[square as usize][index as usize]
+    fn rook_attacks(&self, square: u8, occupied: u64) -> u64 {
+        self.lookup(&self.straight[square as usize], occupied)
     }
 
-    pub fn get_diagonal_move(&self, square: u8, mask: u64) -> u64 {
-        let blockers = mask & self.blocker_masks.diagonal[square as usize];
-        let index = (blockers.wrapping_mul(self.diagonal[square as usize]))
-            >> self.diagonal_bits[square as usize];
-        self.diagonal_moves[square as usize][index as usize]
+    fn bishop_attacks(&self, square: u8, occupied: u64) -> u64 {
+        self.lookup(&self.diagonal[square as usize], occupied)
+    }
+
+    // Only compiled in when the target has BMI2, so there's no branch on the CPU in the hot path
+    #[cfg(all(feature = "pext", target_feature = "bmi2"))]
+    #[inline(always)]
+    fn lookup(&self, entry: &MagicEntry, occupied: u64) -> u64 {
+        // SAFETY: the target is built with BMI2
+        let index = unsafe { std::arch::x86_64::_pext_u64(occupied, entry.mask) };
+        self.attacks[entry.offset + index as usize]
+    }
+
+    #[cfg(not(all(feature = "pext", target_feature = "bmi2")))]
+    #[inline(always)]
+    fn lookup(&self, entry: &MagicEntry, occupied: u64) -> u64 {
+        let index = (occupied & entry.mask).wrapping_mul(entry.magic) >> entry.shift;
+        self.attacks[entry.offset + index as usize]
     }
 }
 
@@ -283,16 +403,6 @@ impl BlockerMasks {
 }
 
 #[cfg(test)]
-mod magic_test {
-    use super::test;
-    //use pretty_assertions::assert_eq;
-
-    #[test]
-    fn test_perft_starting() {
-        test();
-    }
-}
-
 pub fn test() {
     let bm = BlockerMasks::new();
     let bb = BlockerBoards::new(&bm);
@@ -323,8 +433,8 @@ pub fn test() {
     mv.diagonal[55][3].debug_print();
 
     let mask = 10000982834900933;
-    let moves = magic.get_straight_move(27, mask);
-    let moves_d = magic.get_diagonal_move(27, mask);
+    let moves = magic.rook_attacks(27, mask);
+    let moves_d = magic.bishop_attacks(27, mask);
     println!("FINALLY");
     println!("MASK");
     mask.debug_print();
@@ -333,3 +443,52 @@ pub fn test() {
     println!("MOVES");
     moves.debug_print();
 }
+
+#[cfg(test)]
+mod magic_test {
+    use super::{bishop_attacks, queen_attacks, rook_attacks};
+    use super::{test, BlockerBoards, BlockerMasks, Magic, MoveBoards};
+    use crate::bitboard::BitBoard;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_perft_starting() {
+        test();
+    }
+
+    #[test]
+    fn test_embedded_magics() {
+        // Every set of blockers, plus pieces beyond them, looks up its own moves
+        let magic = Magic::new();
+        let bm = BlockerMasks::new();
+        let bb = BlockerBoards::new(&bm);
+        let mb = MoveBoards::new(&bb);
+        // 2^10 to 2^12 rook and 2^5 to 2^9 bishop blocker sets per square
+        assert_eq!(magic.attacks.len(), 102400 + 5248);
+        for square in 0u8..64 {
+            let i = square as usize;
+            let edges = !bm.straight[i] & !(1 << square);
+            for (&blockers, &moves) in bb.straight[i].iter().zip(&mb.straight[i]) {
+                assert_eq!(magic.rook_attacks(square, blockers | edges), moves);
+            }
+            let edges = !bm.diagonal[i] & !(1 << square);
+            for (&blockers, &moves) in bb.diagonal[i].iter().zip(&mb.diagonal[i]) {
+                assert_eq!(magic.bishop_attacks(square, blockers | edges), moves);
+            }
+        }
+    }
+
+    #[test]
+    fn test_slider_attacks() {
+        // A queen on d4 with pieces on d6, f6, b2 and g1
+        let occupied = (1 << 43) | (1 << 45) | (1 << 9) | (1 << 6);
+        let rook = rook_attacks(27, occupied);
+        let bishop = bishop_attacks(27, occupied);
+        assert_eq!(rook.count_ones(), 12);
+        assert!(rook.is_bit_set(43) && !rook.is_bit_set(51));
+        assert_eq!(bishop.count_ones(), 10);
+        assert!(bishop.is_bit_set(45) && !bishop.is_bit_set(54));
+        assert!(bishop.is_bit_set(9) && !bishop.is_bit_set(0));
+        assert_eq!(queen_attacks(27, occupied), rook | bishop);
+    }
+}
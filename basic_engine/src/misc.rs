@@ -1,107 +1,210 @@
+use crate::board::PositionError;
 use std::fmt;
 use std::ops::Not;
 
+/// The fields of a FEN, in order
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FenField {
+    Placement,
+    ActiveColor,
+    Castling,
+    EnPassant,
+    HalfMoveClock,
+    FullMoveNumber,
+}
+
+impl fmt::Display for FenField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenField::Placement => write!(f, "piece placement"),
+            FenField::ActiveColor => write!(f, "active color"),
+            FenField::Castling => write!(f, "castling rights"),
+            FenField::EnPassant => write!(f, "en passant square"),
+            FenField::HalfMoveClock => write!(f, "halfmove clock"),
+            FenField::FullMoveNumber => write!(f, "fullmove number"),
+        }
+    }
+}
+
+/// Reasons a FEN can't be parsed, see Board::from_fen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    /// The FEN ends before this field
+    MissingField(FenField),
+    /// The token given for the field isn't valid, for the piece placement this is the rank which
+    /// doesn't have eight files
+    InvalidField(FenField, String),
+    /// A character in the piece placement which isn't a piece or a count of empty squares
+    InvalidPiece(char),
+    /// A castling right without a king and rook on the back rank to castle with
+    NoCastlingPieces(char),
+    /// The position was set up but can't arise in a game, see Board::from_fen_strict
+    InvalidPosition(Vec<PositionError>),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {}", field),
+            FenError::InvalidField(field, token) => write!(f, "invalid {}: {}", field, token),
+            FenError::InvalidPiece(c) => write!(f, "invalid piece: {}", c),
+            FenError::NoCastlingPieces(c) => {
+                write!(f, "no king and rook to castle with for {}", c)
+            }
+            FenError::InvalidPosition(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "invalid position: {}", errors.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+impl From<FenError> for String {
+    fn from(e: FenError) -> Self {
+        e.to_string()
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Coordinate {
-    rank: u8,
+    rank: Rank,
     file: File,
 }
 
 impl Coordinate {
-    pub fn from_string(s: &str) -> Result<Option<Self>, String> {
+    /// Parse the en passant field of a FEN, "-" or a square such as "e3"
+    pub fn from_string(s: &str) -> Result<Option<Self>, FenError> {
         if s == "-" {
             return Ok(None);
         }
-        if s.len() != 2 {
-            return Err(format!("Expected two characters, got {}", s.len()));
-        }
-        let mut chars = s.chars();
+        let invalid = || FenError::InvalidField(FenField::EnPassant, s.to_string());
+        let chars: Vec<char> = s.chars().collect();
+        let (file, rank) = match chars[..] {
+            [file, rank] => (file, rank),
+            _ => return Err(invalid()),
+        };
         let c = Coordinate {
-            file: File::try_from(chars.next().unwrap())?,
-            rank: chars.next().unwrap().to_digit(10).unwrap() as u8,
+            file: File::try_from(file).map_err(|_| invalid())?,
+            rank: Rank::try_from(rank).map_err(|_| invalid())?,
         };
         Ok(Some(c))
     }
     pub fn as_index(self) -> u8 {
-        coordinate_to_index(self.rank, self.file) as u8
+        coordinate_to_index(self.rank, self.file)
     }
     pub fn from_index(index: u8) -> Self {
         let (rank, file) = index_to_coordinate(index);
-        Coordinate {
-            rank: rank as u8,
-            file,
-        }
+        Coordinate { rank, file }
+    }
+}
+
+impl fmt::Display for Coordinate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.file, self.rank)
     }
 }
 
-// Each color/side bit is true if that color is still allowed to castle on that side
+/// The castles each side is still allowed, one bit each for K, Q, k and q
+///
+/// The bits can index a table directly, e.g. of Zobrist keys for each combination of rights.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub struct CastlePermissions {
-    pub black_king_side: bool,
-    pub black_queen_side: bool,
-    pub white_king_side: bool,
-    pub white_queen_side: bool,
+pub struct CastlePermissions(u8);
+
+impl Default for CastlePermissions {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CastlePermissions {
+    pub const WHITE_KING_SIDE: u8 = 1;
+    pub const WHITE_QUEEN_SIDE: u8 = 2;
+    pub const BLACK_KING_SIDE: u8 = 4;
+    pub const BLACK_QUEEN_SIDE: u8 = 8;
+    pub const ALL: u8 = 15;
+
+    /// Every castle allowed, as in the starting position
     pub fn new() -> Self {
-        CastlePermissions {
-            black_king_side: true,
-            black_queen_side: true,
-            white_king_side: true,
-            white_queen_side: true,
+        CastlePermissions(Self::ALL)
+    }
+
+    /// The permissions with the given bits set, bits above ALL are ignored
+    pub fn from_bits(bits: u8) -> Self {
+        CastlePermissions(bits & Self::ALL)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// The bit for one side's castle
+    pub fn flag(color: Color, king_side: bool) -> u8 {
+        match (color, king_side) {
+            (Color::White, true) => Self::WHITE_KING_SIDE,
+            (Color::White, false) => Self::WHITE_QUEEN_SIDE,
+            (Color::Black, true) => Self::BLACK_KING_SIDE,
+            (Color::Black, false) => Self::BLACK_QUEEN_SIDE,
         }
     }
-    pub fn from_fen(s: &str) -> Result<CastlePermissions, String> {
-        let mut perms = CastlePermissions {
-            black_king_side: false,
-            black_queen_side: false,
-            white_king_side: false,
-            white_queen_side: false,
-        };
+
+    pub fn from_fen(s: &str) -> Result<CastlePermissions, FenError> {
+        let mut perms = CastlePermissions(0);
         if s == "-" {
             return Ok(perms);
         };
         for c in s.chars() {
-            match c {
-                'k' => perms.black_king_side = true,
-                'q' => perms.black_queen_side = true,
-                'K' => perms.white_king_side = true,
-                'Q' => perms.white_queen_side = true,
-                _ => {
-                    return Err(format!(
-                        "Unexpected character {} in castle permissions token",
-                        c
-                    ))
-                }
+            perms.0 |= match c {
+                'K' => Self::WHITE_KING_SIDE,
+                'Q' => Self::WHITE_QUEEN_SIDE,
+                'k' => Self::BLACK_KING_SIDE,
+                'q' => Self::BLACK_QUEEN_SIDE,
+                _ => return Err(FenError::InvalidField(FenField::Castling, s.to_string())),
             }
         }
         Ok(perms)
     }
+
+    #[inline(always)]
+    pub fn allowed(&self, color: Color, king_side: bool) -> bool {
+        self.0 & Self::flag(color, king_side) != 0
+    }
+
+    #[inline(always)]
+    pub fn remove(&mut self, color: Color, king_side: bool) {
+        self.0 &= !Self::flag(color, king_side);
+    }
+
+    /// Keep only the castles whose bits are set in `mask`
+    #[inline(always)]
+    pub fn retain(&mut self, mask: u8) {
+        self.0 &= mask;
+    }
+
+    /// White's permissions given to black and black's to white
+    pub fn swap_colors(self) -> Self {
+        CastlePermissions((self.0 >> 2) | ((self.0 & 3) << 2))
+    }
+
     pub fn as_fen(&self) -> String {
-        let mut s = String::new();
-        if self.white_king_side {
-            s.push('K');
-        };
-        if self.white_queen_side {
-            s.push('Q');
-        };
-        if self.black_king_side {
-            s.push('k');
-        };
-        if self.black_queen_side {
-            s.push('q');
-        };
-        if s.is_empty() {
-            s.push('-');
-        };
-        s
+        let s: String = ['K', 'Q', 'k', 'q']
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| self.0 & (1 << i) != 0)
+            .map(|(_, c)| c)
+            .collect();
+        match s.is_empty() {
+            true => String::from("-"),
+            false => s,
+        }
     }
 }
 
 #[cfg(test)]
 mod test_castle_permissions {
-    use super::CastlePermissions;
+    use super::{CastlePermissions, Color};
+    use pretty_assertions::assert_eq;
 
     #[test]
     fn round_trip_all() {
@@ -135,35 +238,82 @@ mod test_castle_permissions {
         let initial = "ksd";
         assert!(CastlePermissions::from_fen(initial).is_err());
     }
+
+    #[test]
+    fn flags() {
+        let mut perms = CastlePermissions::from_fen("KQq").unwrap();
+        assert_eq!(perms.bits(), 0b1011);
+        assert_eq!(perms.swap_colors().as_fen(), "Qkq");
+        perms.retain(!CastlePermissions::WHITE_QUEEN_SIDE);
+        assert_eq!(perms.as_fen(), "Kq");
+        perms.remove(Color::Black, false);
+        assert_eq!(
+            perms,
+            CastlePermissions::from_bits(CastlePermissions::WHITE_KING_SIDE)
+        );
+        assert!(perms.allowed(Color::White, true) && !perms.allowed(Color::White, false));
+    }
+}
+
+pub fn coordinate_to_index(rank: Rank, file: File) -> u8 {
+    (rank as u8 * 8) + file as u8
+}
+
+pub fn coordinate_to_large_index(rank: Rank, file: File) -> u8 {
+    (rank as u8 * 10) + file as u8 + 11
 }
 
-pub fn coordinate_to_index(rank: u8, file: File) -> u8 {
-    ((rank - 1) * 8) + (file) as u8
+pub fn index_to_coordinate(index: u8) -> (Rank, File) {
+    (rank_of(index), file_of(index))
 }
 
-pub fn coordinate_to_large_index(rank: u8, file: File) -> u8 {
-    ((rank - 1) * 10) + (file) as u8 + 11
+#[inline(always)]
+pub fn file_of(index: u8) -> File {
+    File::VARIANTS[(index % 8) as usize]
 }
 
-pub fn index_to_coordinate(index: u8) -> (u8, File) {
-    let rank = ((index) / 8) + 1;
-    let file = File::try_from(index % 8).unwrap();
-    (rank, file)
+#[inline(always)]
+pub fn rank_of(index: u8) -> Rank {
+    Rank::VARIANTS[(index / 8) as usize]
+}
+
+/// Number of king moves between two squares
+#[inline(always)]
+pub fn chebyshev_distance(a: u8, b: u8) -> u8 {
+    let rank_distance = (a / 8).abs_diff(b / 8);
+    let file_distance = (a % 8).abs_diff(b % 8);
+    rank_distance.max(file_distance)
 }
 
 #[cfg(test)]
 mod test_index_coordinate_conversion {
     use super::coordinate_to_index;
     use super::index_to_coordinate;
+    use super::{chebyshev_distance, file_of, rank_of, Coordinate, File, Rank};
+    use pretty_assertions::assert_eq;
     use proptest::prelude::*;
 
     proptest! {
         #[test]
-        fn round_trip(i in 1u8..=64) {
+        fn round_trip(i in 0u8..64) {
             let (rank, file) = index_to_coordinate(i);
             assert_eq!(i, coordinate_to_index(rank, file));
+            assert_eq!(Coordinate::from_index(i).to_string(), format!("{}{}", file, rank));
         }
     }
+
+    #[test]
+    fn square_helpers() {
+        // e4
+        assert_eq!(file_of(28), File::E);
+        assert_eq!(rank_of(28), Rank::Four);
+        assert_eq!(Coordinate::from_index(28).to_string(), "e4");
+        assert_eq!(chebyshev_distance(0, 63), 7);
+        assert_eq!(chebyshev_distance(28, 28), 0);
+        // e4 to g5 and h1 to a2
+        assert_eq!(chebyshev_distance(28, 38), 2);
+        assert_eq!(chebyshev_distance(7, 8), 7);
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -181,16 +331,30 @@ impl PromotePiece {
         PromotePiece::Rook,
         PromotePiece::Queen,
     ];
+
+    /// The piece of a promotion letter in either case, 'q' in UCI and 'Q' in SAN
+    pub fn from_char(c: char) -> Option<PromotePiece> {
+        match c.to_ascii_lowercase() {
+            'n' => Some(PromotePiece::Knight),
+            'b' => Some(PromotePiece::Bishop),
+            'r' => Some(PromotePiece::Rook),
+            'q' => Some(PromotePiece::Queen),
+            _ => None,
+        }
+    }
 }
 
 impl From<&PromotePiece> for char {
     fn from(c: &PromotePiece) -> Self {
-        match c {
-            PromotePiece::Knight => 'n',
-            PromotePiece::Bishop => 'b',
-            PromotePiece::Rook => 'r',
-            PromotePiece::Queen => 'q',
-        }
+        Piece::from(c).to_fen_char(Color::Black)
+    }
+}
+
+impl TryFrom<char> for PromotePiece {
+    type Error = String;
+
+    fn try_from(c: char) -> Result<Self, Self::Error> {
+        PromotePiece::from_char(c).ok_or_else(|| format!("{} is not a valid promotion piece", c))
     }
 }
 
@@ -205,14 +369,56 @@ pub enum Piece {
 }
 
 impl Piece {
-    pub fn material_value(self) -> u32 {
+    pub const VARIANTS: [Piece; 6] = [
+        Piece::Pawn,
+        Piece::Knight,
+        Piece::Bishop,
+        Piece::Rook,
+        Piece::Queen,
+        Piece::King,
+    ];
+
+    /// Contribution of the piece to the game phase, pawns and kings are present until the end
+    pub fn phase_weight(self) -> u32 {
         match self {
-            Piece::Pawn => 100,
-            Piece::Knight => 310,
-            Piece::Bishop => 320,
-            Piece::Rook => 500,
-            Piece::Queen => 900,
-            Piece::King => 10000,
+            Piece::Pawn | Piece::King => 0,
+            Piece::Knight | Piece::Bishop => 1,
+            Piece::Rook => 2,
+            Piece::Queen => 4,
+        }
+    }
+
+    /// The piece and colour of a FEN letter, upper case for white
+    pub fn from_fen_char(c: char) -> Option<(Piece, Color)> {
+        let piece = match c.to_ascii_lowercase() {
+            'p' => Piece::Pawn,
+            'n' => Piece::Knight,
+            'b' => Piece::Bishop,
+            'r' => Piece::Rook,
+            'q' => Piece::Queen,
+            'k' => Piece::King,
+            _ => return None,
+        };
+        let color = match c.is_ascii_uppercase() {
+            true => Color::White,
+            false => Color::Black,
+        };
+        Some((piece, color))
+    }
+
+    /// The FEN letter of the piece, upper case for white
+    pub fn to_fen_char(self, color: Color) -> char {
+        let c = match self {
+            Piece::Pawn => 'p',
+            Piece::Knight => 'n',
+            Piece::Bishop => 'b',
+            Piece::Rook => 'r',
+            Piece::Queen => 'q',
+            Piece::King => 'k',
+        };
+        match color {
+            Color::White => c.to_ascii_uppercase(),
+            Color::Black => c,
         }
     }
 }
@@ -357,3 +563,148 @@ impl TryFrom<char> for File {
         }
     }
 }
+
+/// A rank of the board, Rank::One is white's back rank
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rank {
+    One = 0,
+    Two = 1,
+    Three = 2,
+    Four = 3,
+    Five = 4,
+    Six = 5,
+    Seven = 6,
+    Eight = 7,
+}
+
+impl Rank {
+    pub const VARIANTS: [Rank; 8] = [
+        Rank::One,
+        Rank::Two,
+        Rank::Three,
+        Rank::Four,
+        Rank::Five,
+        Rank::Six,
+        Rank::Seven,
+        Rank::Eight,
+    ];
+
+    /// The rank `delta` ranks up the board, None if that's off the board
+    pub fn offset(self, delta: i8) -> Option<Rank> {
+        let rank = self as i8 + delta;
+        Rank::VARIANTS.get(usize::try_from(rank).ok()?).copied()
+    }
+
+    /// The rank as seen from `color`'s side of the board, e.g. Rank::Seven is the rank white
+    /// pawns promote from and Rank::Two for black
+    pub fn relative(self, color: Color) -> Rank {
+        match color {
+            Color::White => self,
+            Color::Black => Rank::VARIANTS[7 - self as usize],
+        }
+    }
+
+    /// The rank's number, 1 to 8
+    pub fn number(self) -> u8 {
+        self as u8 + 1
+    }
+}
+
+impl fmt::Display for Rank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+impl From<Rank> for u64 {
+    fn from(rank: Rank) -> Self {
+        rank as u64
+    }
+}
+
+impl TryFrom<u8> for Rank {
+    type Error = String;
+
+    fn try_from(i: u8) -> Result<Self, Self::Error> {
+        Rank::VARIANTS
+            .get(i as usize)
+            .copied()
+            .ok_or_else(|| format!("{} is not a valid Rank value. Rank only has 8 variants.", i))
+    }
+}
+
+impl TryFrom<char> for Rank {
+    type Error = String;
+
+    fn try_from(c: char) -> Result<Self, Self::Error> {
+        match c.to_digit(10) {
+            Some(n @ 1..=8) => Ok(Rank::VARIANTS[n as usize - 1]),
+            _ => Err(format!("{} is not a valid Rank token", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_rank {
+    use super::{Color, Rank};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn conversions() {
+        for (i, rank) in Rank::VARIANTS.into_iter().enumerate() {
+            assert_eq!(Rank::try_from(i as u8), Ok(rank));
+            let c = char::from_digit(i as u32 + 1, 10).unwrap();
+            assert_eq!(Rank::try_from(c), Ok(rank));
+            assert_eq!(rank.to_string(), c.to_string());
+        }
+        assert!(Rank::try_from(8u8).is_err());
+        assert!(Rank::try_from('0').is_err());
+        assert!(Rank::try_from('9').is_err());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(Rank::Two.offset(2), Some(Rank::Four));
+        assert_eq!(Rank::Seven.offset(-2), Some(Rank::Five));
+        assert_eq!(Rank::Eight.offset(1), None);
+        assert_eq!(Rank::One.offset(-1), None);
+        assert_eq!(Rank::Seven.relative(Color::White), Rank::Seven);
+        assert_eq!(Rank::Seven.relative(Color::Black), Rank::Two);
+        assert_eq!(Rank::One.number(), 1);
+    }
+}
+
+#[cfg(test)]
+mod test_piece_chars {
+    use super::{Color, Piece, PromotePiece};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn round_trip() {
+        for piece in Piece::VARIANTS {
+            for color in [Color::White, Color::Black] {
+                let c = piece.to_fen_char(color);
+                assert_eq!(Piece::from_fen_char(c), Some((piece, color)));
+            }
+        }
+        assert_eq!(Piece::Knight.to_fen_char(Color::White), 'N');
+        assert_eq!(Piece::Knight.to_fen_char(Color::Black), 'n');
+        assert_eq!(Piece::from_fen_char('x'), None);
+        assert_eq!(Piece::from_fen_char('1'), None);
+    }
+
+    #[test]
+    fn promotions() {
+        for promote in PromotePiece::VARIANTS {
+            let c = char::from(&promote);
+            assert_eq!(PromotePiece::from_char(c), Some(promote));
+            assert_eq!(
+                PromotePiece::from_char(c.to_ascii_uppercase()),
+                Some(promote)
+            );
+        }
+        assert_eq!(char::from(&PromotePiece::Queen), 'q');
+        assert_eq!(PromotePiece::from_char('k'), None);
+        assert_eq!(PromotePiece::from_char('p'), None);
+    }
+}
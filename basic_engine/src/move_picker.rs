@@ -0,0 +1,350 @@
+use crate::Game;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Stage {
+    Given,
+    HashMove,
+    GenerateCaptures,
+    GoodCaptures,
+    GeneratePromotions,
+    Promotions,
+    Killers,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    GenerateQuietChecks,
+    QuietChecks,
+    Done,
+}
+
+/// Hands out the moves of a position one at a time, most promising first
+///
+/// Moves are generated in stages: the hash move, captures which don't lose material, killer
+/// moves, the remaining quiet moves and finally the losing captures. In quiescence the promotions
+/// which don't capture replace the killer and quiet moves. Most nodes are cut off by
+/// one of the first few moves, so the later stages usually aren't generated at all. Within a
+/// stage the best scoring move is picked each time rather than sorting the whole stage.
+///
+/// Like generate_moves the moves may leave the king in check, make_move rejects those.
+pub(crate) struct MovePicker<M> {
+    stage: Stage,
+    hash_move: Option<M>,
+    killers: [Option<M>; 2],
+    killer_index: usize,
+    buffers: MoveBuffers<M>,
+    quiets: bool, // False in quiescence, only captures, promotions (and maybe checks) are searched
+    quiet_checks: bool, // Quiet moves giving check are searched after the captures
+}
+
+/// The lists a MovePicker keeps its moves in
+///
+/// The search keeps a set for each ply and hands it from one picker to the next, so the lists
+/// are only allocated when a ply is first reached.
+#[derive(Debug)]
+pub(crate) struct MoveBuffers<M> {
+    generated: Vec<M>,
+    moves: Vec<(M, i64)>,
+    bad_captures: Vec<(M, i64)>,
+}
+
+impl<M> Default for MoveBuffers<M> {
+    fn default() -> Self {
+        Self {
+            generated: Vec::new(),
+            moves: Vec::new(),
+            bad_captures: Vec::new(),
+        }
+    }
+}
+
+impl<M: Copy + Eq> MovePicker<M> {
+    /// Pick from every move in the position, keeping the moves in `buffers`
+    pub(crate) fn new(
+        hash_move: Option<M>,
+        killers: [Option<M>; 2],
+        mut buffers: MoveBuffers<M>,
+    ) -> Self {
+        buffers.moves.clear();
+        buffers.bad_captures.clear();
+        Self {
+            stage: Stage::HashMove,
+            hash_move,
+            killers,
+            killer_index: 0,
+            buffers,
+            quiets: true,
+            quiet_checks: false,
+        }
+    }
+
+    /// Pick from the captures and promotions, followed by the quiet moves which give check if
+    /// `quiet_checks`
+    pub(crate) fn quiescence(
+        hash_move: Option<M>,
+        quiet_checks: bool,
+        buffers: MoveBuffers<M>,
+    ) -> Self {
+        Self {
+            quiets: false,
+            quiet_checks,
+            ..Self::new(hash_move, [None, None], buffers)
+        }
+    }
+
+    /// Hand out the given moves in order, e.g. the root moves sorted by a previous iteration
+    pub(crate) fn from_moves(moves: Vec<M>, buffers: MoveBuffers<M>) -> Self {
+        let mut picker = Self::new(None, [None, None], buffers);
+        picker.stage = Stage::Given;
+        picker
+            .buffers
+            .moves
+            .extend(moves.into_iter().rev().map(|m| (m, 0)));
+        picker
+    }
+
+    /// The lists to give to the next picker at this ply
+    pub(crate) fn into_buffers(self) -> MoveBuffers<M> {
+        self.buffers
+    }
+
+    pub(crate) fn next<G: Game<Move = M>>(&mut self, board: &G) -> Option<M> {
+        let buffers = &mut self.buffers;
+        loop {
+            match self.stage {
+                Stage::Given => return buffers.moves.pop().map(|(m, _)| m),
+                Stage::HashMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    if let Some(m) = self.hash_move {
+                        if board.is_pseudo_legal(&m) && (self.quiets || !board.is_quiet(&m)) {
+                            return Some(m);
+                        }
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    self.stage = Stage::GoodCaptures;
+                    board.generate_captures_into(&mut buffers.generated);
+                    for &m in &buffers.generated {
+                        if Some(m) == self.hash_move {
+                            continue;
+                        }
+                        let scored = (m, board.order_score(&m));
+                        match board.is_losing_capture(&m) {
+                            true => buffers.bad_captures.push(scored),
+                            false => buffers.moves.push(scored),
+                        }
+                    }
+                }
+                Stage::GoodCaptures => match pick_best(&mut buffers.moves) {
+                    Some(m) => return Some(m),
+                    None if self.quiets => self.stage = Stage::Killers,
+                    None => self.stage = Stage::GeneratePromotions,
+                },
+                // Quiet promotions are generated with the quiet moves outside of quiescence
+                Stage::GeneratePromotions => {
+                    self.stage = Stage::Promotions;
+                    board.generate_promotions_into(&mut buffers.generated);
+                    let hash_move = self.hash_move;
+                    buffers.moves.extend(
+                        buffers
+                            .generated
+                            .iter()
+                            .rev()
+                            .filter(|&&m| Some(m) != hash_move)
+                            .map(|&m| (m, 0)),
+                    );
+                }
+                Stage::Promotions => match buffers.moves.pop() {
+                    Some((m, _)) => return Some(m),
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::Killers => {
+                    while let Some(&killer) = self.killers.get(self.killer_index) {
+                        let i = self.killer_index;
+                        self.killer_index += 1;
+                        match killer {
+                            Some(m)
+                                if Some(m) != self.hash_move
+                                    && (i == 0 || killer != self.killers[0])
+                                    && board.is_pseudo_legal(&m)
+                                    && board.is_quiet(&m) =>
+                            {
+                                return Some(m)
+                            }
+                            // Killers which weren't handed out aren't skipped later
+                            _ => self.killers[i] = None,
+                        }
+                    }
+                    self.stage = Stage::GenerateQuiets;
+                }
+                Stage::GenerateQuiets => {
+                    self.stage = Stage::Quiets;
+                    board.generate_quiets_into(&mut buffers.generated);
+                    let skip = [self.hash_move, self.killers[0], self.killers[1]];
+                    buffers.moves.extend(
+                        buffers
+                            .generated
+                            .iter()
+                            .filter(|m| !skip.contains(&Some(**m)))
+                            .map(|&m| (m, board.order_score(&m))),
+                    );
+                }
+                Stage::Quiets => match pick_best(&mut buffers.moves) {
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::BadCaptures => match pick_best(&mut buffers.bad_captures) {
+                    Some(m) => return Some(m),
+                    None if self.quiet_checks => self.stage = Stage::GenerateQuietChecks,
+                    None => self.stage = Stage::Done,
+                },
+                Stage::GenerateQuietChecks => {
+                    self.stage = Stage::QuietChecks;
+                    board.generate_quiet_checks_into(&mut buffers.generated);
+                    buffers
+                        .moves
+                        .extend(buffers.generated.iter().rev().map(|&m| (m, 0)));
+                }
+                // Only captures and promotions can have been handed out as the hash move in
+                // quiescence
+                Stage::QuietChecks => match buffers.moves.pop() {
+                    Some((m, _)) => return Some(m),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+// Remove and return the highest scoring move, the first of equal scores
+fn pick_best<M: Copy>(moves: &mut Vec<(M, i64)>) -> Option<M> {
+    let mut best = 0;
+    for (i, (_, score)) in moves.iter().enumerate().skip(1) {
+        if *score > moves[best].1 {
+            best = i;
+        }
+    }
+    match moves.is_empty() {
+        true => None,
+        false => Some(moves.swap_remove(best).0),
+    }
+}
+
+#[cfg(test)]
+mod test_move_picker {
+    use super::{MoveBuffers, MovePicker};
+    use crate::board::Board;
+    use crate::play::Play;
+    use pretty_assertions::assert_eq;
+
+    fn find(board: &Board, uci: &str) -> Play {
+        board
+            .generate_moves()
+            .into_iter()
+            .find(|m| m.to_string() == uci)
+            .unwrap()
+    }
+
+    fn picked(board: &Board, mut picker: MovePicker<Play>) -> Vec<String> {
+        let mut moves = Vec::new();
+        while let Some(m) = picker.next(board) {
+            moves.push(m.to_string());
+        }
+        moves
+    }
+
+    // Nxb5 wins a knight, Nxd5 and Qxd5 lose material to the pawn on e6
+    const FEN: &str = "4k3/8/4p3/1n1p4/8/2N5/3Q4/4K3 w - - 0 1";
+
+    #[test]
+    fn test_stages_in_order() {
+        let board = Board::from_fen(FEN).unwrap();
+        let hash_move = find(&board, "e1f1");
+        let killer = find(&board, "c3a4");
+        let moves = picked(
+            &board,
+            MovePicker::new(
+                Some(hash_move),
+                [Some(killer), None],
+                MoveBuffers::default(),
+            ),
+        );
+        assert_eq!(&moves[..3], &["e1f1", "c3b5", "c3a4"]);
+        assert_eq!(&moves[moves.len() - 2..], &["c3d5", "d2d5"]);
+
+        // Each move is picked once
+        let mut sorted = moves.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), moves.len());
+        assert_eq!(moves.len(), board.generate_moves().len());
+    }
+
+    #[test]
+    fn test_buffers_reused() {
+        let board = Board::from_fen(FEN).unwrap();
+        let fresh = picked(
+            &board,
+            MovePicker::quiescence(None, true, MoveBuffers::default()),
+        );
+        // A picker stopped part way leaves moves in the lists, the next one mustn't see them
+        let mut picker = MovePicker::new(None, [None, None], MoveBuffers::default());
+        picker.next(&board);
+        let buffers = picker.into_buffers();
+        assert_eq!(
+            picked(&board, MovePicker::quiescence(None, true, buffers)),
+            fresh
+        );
+    }
+
+    #[test]
+    fn test_invalid_hash_move_and_killers_skipped() {
+        let board = Board::from_fen(FEN).unwrap();
+        let other = Board::new();
+        // Moves from another position aren't pseudo-legal here, and a capture isn't a killer
+        let hash_move = find(&other, "e2e4");
+        let killers = [Some(find(&other, "g1f3")), Some(find(&board, "c3d5"))];
+        let moves = picked(
+            &board,
+            MovePicker::new(Some(hash_move), killers, MoveBuffers::default()),
+        );
+        assert_eq!(moves[0], "c3b5");
+        assert_eq!(moves.len(), board.generate_moves().len());
+    }
+
+    #[test]
+    fn test_quiescence_only_captures_and_checks() {
+        let board = Board::from_fen(FEN).unwrap();
+        let quiet_hash_move = find(&board, "e1f1");
+        let moves = picked(
+            &board,
+            MovePicker::quiescence(Some(quiet_hash_move), false, MoveBuffers::default()),
+        );
+        assert_eq!(moves, ["c3b5", "c3d5", "d2d5"]);
+
+        let moves = picked(
+            &board,
+            MovePicker::quiescence(None, true, MoveBuffers::default()),
+        );
+        assert_eq!(&moves[..3], &["c3b5", "c3d5", "d2d5"]);
+        assert_eq!(moves[3..].len(), board.generate_quiet_checks().len());
+    }
+
+    #[test]
+    fn test_quiescence_promotions() {
+        // The captures come first, then the promotions which don't capture, queen first
+        let board = Board::from_fen("1n2k3/P1P5/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let hash_move = find(&board, "c7c8r");
+        let moves = picked(
+            &board,
+            MovePicker::quiescence(Some(hash_move), false, MoveBuffers::default()),
+        );
+        assert_eq!(moves.len(), 16);
+        assert_eq!(moves[0], "c7c8r");
+        assert!(moves[1..9].iter().all(|m| m.contains("b8")));
+        assert_eq!(
+            &moves[9..],
+            &["a7a8q", "a7a8r", "a7a8b", "a7a8n", "c7c8q", "c7c8b", "c7c8n"]
+        );
+    }
+}
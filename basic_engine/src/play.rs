@@ -1,7 +1,11 @@
 use crate::board::Board;
-use crate::misc::index_to_coordinate;
-use crate::misc::{Piece, PromotePiece};
+use crate::misc::{coordinate_to_index, index_to_coordinate, Coordinate};
+use crate::misc::{Color, File, Piece, PromotePiece, Rank};
 use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+/// Capacity of a MoveList, no chess position has more than 218 legal moves
+pub const MAX_MOVES: usize = 256;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Play {
@@ -33,6 +37,42 @@ impl Play {
         }
     }
 
+    /// The move packed into the low 20 bits: the squares, the captured and promoted pieces
+    /// (zero for none) and the en passant and castling flags
+    pub fn to_bits(&self) -> u32 {
+        let piece = |index: Option<u32>| index.map_or(0, |i| i + 1);
+        self.from as u32
+            | (self.to as u32) << 6
+            | piece(self.capture.map(|p| p as u32)) << 12
+            | piece(self.promote.map(|p| p as u32)) << 15
+            | (self.en_passant as u32) << 18
+            | (self.castle as u32) << 19
+    }
+
+    /// The move packed by to_bits, None if the bits aren't a packed move
+    pub fn from_bits(bits: u32) -> Option<Play> {
+        if bits >> 20 != 0 {
+            return None;
+        }
+        let field = |shift: u32, width: u32| ((bits >> shift) & ((1 << width) - 1)) as usize;
+        let capture = match field(12, 3) {
+            0 => None,
+            i => Some(*Piece::VARIANTS.get(i - 1)?),
+        };
+        let promote = match field(15, 3) {
+            0 => None,
+            i => Some(*PromotePiece::VARIANTS.get(i - 1)?),
+        };
+        Some(Play::new(
+            field(0, 6) as u8,
+            field(6, 6) as u8,
+            capture,
+            promote,
+            field(18, 1) == 1,
+            field(19, 1) == 1,
+        ))
+    }
+
     pub fn mmv_lva(&self, board: &Board) -> i64 {
         let victim_score = match self.capture {
             None => return 0,
@@ -58,17 +98,524 @@ impl Play {
         }
         score
     }
+
+    /// Find the legal move written in standard algebraic notation (SAN), e.g. "Nbd7" or "exd8=Q+"
+    ///
+    /// Parsing is lenient: check and annotation suffixes, the capture "x" and unneeded
+    /// disambiguation are all optional, and castling may be written with zeros.
+    pub fn from_san(board: &Board, san: &str) -> Result<Self, MoveError> {
+        let invalid = || MoveError::InvalidFormat(san.to_string());
+        let text = san.trim().trim_end_matches(['+', '#', '!', '?']);
+        let text = text.strip_suffix("e.p.").unwrap_or(text).trim_end();
+        let legal = board.legal_moves().map(|m| m.play());
+
+        let castle_file = match text {
+            "O-O" | "0-0" => Some(File::G),
+            "O-O-O" | "0-0-0" => Some(File::C),
+            _ => None,
+        };
+        if let Some(file) = castle_file {
+            return legal
+                .into_iter()
+                .find(|p| p.castle && index_to_coordinate(p.to).1 == file)
+                .ok_or_else(|| MoveError::Illegal(san.to_string()));
+        }
+
+        let mut chars: Vec<char> = text.chars().filter(|&c| !matches!(c, 'x' | '-')).collect();
+        // A promotion follows the destination square, which ends in a digit
+        let promote = match chars.last() {
+            Some(c) if c.is_ascii_alphabetic() => {
+                let promote = PromotePiece::try_from(*c).map_err(|_| invalid())?;
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+                Some(promote)
+            }
+            _ => None,
+        };
+        // Piece letters are upper case, a lower case letter is the file of a pawn move
+        let piece = match chars.first().and_then(|&c| Piece::from_fen_char(c)) {
+            Some((piece, Color::White)) => piece,
+            _ => Piece::Pawn,
+        };
+        if piece != Piece::Pawn {
+            chars.remove(0);
+        }
+        if chars.len() < 2 || chars.len() > 4 {
+            return Err(invalid());
+        }
+        let square = chars.split_off(chars.len() - 2);
+        let to_file = File::try_from(square[0]).map_err(|_| invalid())?;
+        let to_rank = Rank::try_from(square[1]).map_err(|_| invalid())?;
+        // Whatever is left disambiguates the from square
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars {
+            match Rank::try_from(c) {
+                Ok(rank) => from_rank = Some(rank),
+                Err(_) => from_file = Some(File::try_from(c).map_err(|_| invalid())?),
+            }
+        }
+
+        let to = coordinate_to_index(to_rank, to_file);
+        let mut matches = legal.filter(|p| {
+            let (rank, file) = index_to_coordinate(p.from);
+            p.to == to
+                && !p.castle
+                && p.promote == promote
+                && board.get_piece_index(p.from) == Some(piece)
+                && from_file.is_none_or(|f| f == file)
+                && from_rank.is_none_or(|r| r == rank)
+        });
+        match (matches.next(), matches.next()) {
+            (Some(play), None) => Ok(play),
+            (Some(_), Some(_)) => Err(MoveError::Ambiguous(san.to_string())),
+            (None, _) => Err(MoveError::Illegal(san.to_string())),
+        }
+    }
+
+    /// Format the play in standard algebraic notation (SAN)
+    ///
+    /// The board must be the position before the play is made.
+    pub fn to_san(&self, board: &Board) -> String {
+        let mut san = String::new();
+        let piece = board
+            .get_piece_index(self.from)
+            .expect("The from square must always be occupied");
+        let (_, from_file) = index_to_coordinate(self.from);
+        let (to_rank, to_file) = index_to_coordinate(self.to);
+
+        if self.castle {
+            if to_file == File::G {
+                san.push_str("O-O");
+            } else {
+                san.push_str("O-O-O");
+            }
+        } else {
+            match piece {
+                Piece::Pawn => {
+                    if self.capture.is_some() {
+                        san.push_str(&from_file.to_string());
+                    }
+                }
+                _ => {
+                    san.push(san_piece_char(piece));
+                    san.push_str(&self.disambiguation(board, piece));
+                }
+            }
+            if self.capture.is_some() {
+                san.push('x');
+            }
+            san.push_str(&format!("{}{}", to_file, to_rank));
+            if let Some(promote) = &self.promote {
+                san.push('=');
+                san.push(san_piece_char(promote.into()));
+            }
+        }
+
+        if board.gives_check(self) {
+            let mut after = board.clone();
+            if after.make_move(self) {
+                san.push(if after.is_checkmate() { '#' } else { '+' });
+            }
+        }
+        san
+    }
+
+    // The from file and/or rank required to distinguish this play from other legal plays of the
+    // same piece type to the same square
+    fn disambiguation(&self, board: &Board, piece: Piece) -> String {
+        let others: Vec<Play> = board
+            .legal_moves()
+            .map(|m| m.play())
+            .filter(|p| {
+                p.to == self.to
+                    && p.from != self.from
+                    && board.get_piece_index(p.from) == Some(piece)
+            })
+            .collect();
+        if others.is_empty() {
+            return String::new();
+        }
+        let (from_rank, from_file) = index_to_coordinate(self.from);
+        if others
+            .iter()
+            .all(|p| index_to_coordinate(p.from).1 != from_file)
+        {
+            from_file.to_string()
+        } else if others
+            .iter()
+            .all(|p| index_to_coordinate(p.from).0 != from_rank)
+        {
+            from_rank.to_string()
+        } else {
+            format!("{}{}", from_file, from_rank)
+        }
+    }
+}
+
+fn san_piece_char(piece: Piece) -> char {
+    piece.to_fen_char(Color::White)
 }
 
 impl fmt::Display for Play {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let (from_rank, from_file) = index_to_coordinate(self.from);
-        let (to_rank, to_file) = index_to_coordinate(self.to);
-        write!(f, "{}{}", from_file, from_rank)?;
-        write!(f, "{}{}", to_file, to_rank)?;
+        let from = Coordinate::from_index(self.from);
+        let to = Coordinate::from_index(self.to);
+        write!(f, "{}{}", from, to)?;
         if let Some(promote) = &self.promote {
             write!(f, "{}", char::from(promote))?;
         }
         Ok(())
     }
 }
+
+/// Reasons a move in UCI or SAN notation can't be played, see Board::parse_uci_move and
+/// Board::parse_san
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveError {
+    /// The text isn't a from and to square with an optional promotion, e.g. "e7e8q", or isn't
+    /// SAN
+    InvalidFormat(String),
+    /// The side to move has no piece on the from square
+    NoPiece(String),
+    /// The piece can't move there, or the move would leave the king in check
+    Illegal(String),
+    /// More than one legal move matches the SAN, e.g. Nd2 with knights on b1 and f3
+    Ambiguous(String),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::InvalidFormat(m) => write!(f, "invalid move format: {}", m),
+            MoveError::NoPiece(m) => write!(f, "no piece to move: {}", m),
+            MoveError::Illegal(m) => write!(f, "illegal move: {}", m),
+            MoveError::Ambiguous(m) => write!(f, "ambiguous move: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// Returned by Board::undo_move when no moves have been played since the position was set up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoMoveToUndo;
+
+impl fmt::Display for NoMoveToUndo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "there is no move to undo")
+    }
+}
+
+impl std::error::Error for NoMoveToUndo {}
+
+/// A list of moves with a fixed capacity which doesn't allocate
+///
+/// Lists can be kept and refilled, e.g. one per ply of a search, see Board::generate_moves_into.
+#[derive(Copy, Clone)]
+pub struct MoveList {
+    moves: [Play; MAX_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        Self {
+            moves: [Play::new(0, 0, None, None, false, false); MAX_MOVES],
+            len: 0,
+        }
+    }
+
+    /// Add a move to the end of the list, panics if the list is full
+    #[inline(always)]
+    pub fn push(&mut self, play: Play) {
+        self.moves[self.len] = play;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Keep only the moves `f` returns true for, in their original order
+    pub fn retain<F: FnMut(&Play) -> bool>(&mut self, mut f: F) {
+        let mut kept = 0;
+        for i in 0..self.len {
+            if f(&self.moves[i]) {
+                self.moves[kept] = self.moves[i];
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+}
+
+/// Somewhere the move generators can put moves, so the same generator fills a Vec or a MoveList
+pub(crate) trait MoveBuffer {
+    fn push(&mut self, play: Play);
+    fn retain<F: FnMut(&Play) -> bool>(&mut self, f: F);
+}
+
+impl MoveBuffer for Vec<Play> {
+    #[inline(always)]
+    fn push(&mut self, play: Play) {
+        Vec::push(self, play);
+    }
+
+    fn retain<F: FnMut(&Play) -> bool>(&mut self, f: F) {
+        Vec::retain(self, f);
+    }
+}
+
+impl MoveBuffer for MoveList {
+    #[inline(always)]
+    fn push(&mut self, play: Play) {
+        MoveList::push(self, play);
+    }
+
+    fn retain<F: FnMut(&Play) -> bool>(&mut self, f: F) {
+        MoveList::retain(self, f);
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Play];
+
+    fn deref(&self) -> &[Play] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Play] {
+        &mut self.moves[..self.len]
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Play;
+    type IntoIter = std::slice::Iter<'a, Play>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Debug for MoveList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test_move_list {
+    use super::{MoveList, Play};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_push_retain_clear() {
+        let mut list = MoveList::new();
+        for to in 0..10 {
+            list.push(Play::new(8, to, None, None, false, false));
+        }
+        assert_eq!(list.len(), 10);
+        list.retain(|p| p.to % 2 == 0);
+        let to: Vec<u8> = list.iter().map(|p| p.to).collect();
+        assert_eq!(to, [0, 2, 4, 6, 8]);
+        list.clear();
+        assert!(list.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test_play_bits {
+    use super::Play;
+    use crate::board::Board;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_round_trip() {
+        // Captures, promotions, en passant and castling
+        for fen in [
+            "r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1",
+            "rn2k3/1P6/8/8/8/8/6p1/4K2R b K - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for play in board.generate_moves() {
+                assert_eq!(Play::from_bits(play.to_bits()), Some(play));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_bits() {
+        assert_eq!(Play::from_bits(1 << 20), None);
+        // Piece numbers past the king and queen
+        assert_eq!(Play::from_bits(7 << 12), None);
+        assert_eq!(Play::from_bits(5 << 15), None);
+    }
+}
+
+#[cfg(test)]
+mod test_san {
+    use super::MoveError;
+    use crate::board::Board;
+    use pretty_assertions::assert_eq;
+
+    fn san(fen: &str, uci: &str) -> String {
+        let board = Board::from_fen(fen).unwrap();
+        board
+            .legal_moves()
+            .find(|m| m.uci() == uci)
+            .unwrap_or_else(|| panic!("{} is not legal", uci))
+            .san()
+    }
+
+    #[test]
+    fn test_pawn_and_piece_moves() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san(fen, "e2e4"), "e4");
+        assert_eq!(san(fen, "g1f3"), "Nf3");
+    }
+
+    #[test]
+    fn test_captures() {
+        let fen = "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2";
+        assert_eq!(san(fen, "e4d5"), "exd5");
+        let fen = "rnbqkbnr/ppp2ppp/8/3pp3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 3";
+        assert_eq!(san(fen, "f3e5"), "Nxe5");
+    }
+
+    #[test]
+    fn test_en_passant() {
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        assert_eq!(san(fen, "e5f6"), "exf6");
+    }
+
+    #[test]
+    fn test_castling() {
+        let fen = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+    }
+
+    #[test]
+    fn test_disambiguation() {
+        // Knights on b1 and f3 can both reach d2
+        let fen = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san(fen, "b1d2"), "Nbd2");
+        // Rooks on a1 and a5 can both reach a3
+        let fen = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1a3"), "R1a3");
+        // Queens on a1, a3 and c1 can all reach b2
+        let fen = "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1";
+        assert_eq!(san(fen, "a1b2"), "Qa1b2");
+    }
+
+    fn parse(fen: &str, san: &str) -> Result<String, MoveError> {
+        let board = Board::from_fen(fen).unwrap();
+        board.parse_san(san).map(|p| p.to_string())
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for m in board.legal_moves() {
+                assert_eq!(
+                    board.parse_san(&m.san()),
+                    Ok(m.play()),
+                    "{} {}",
+                    fen,
+                    m.san()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let fen = "r3k2r/pppppppp/8/8/8/5N2/PPPPPPPP/RN2K2R w KQkq - 0 1";
+        assert_eq!(parse(fen, "0-0"), Ok("e1g1".to_string()));
+        assert_eq!(
+            parse(fen, "O-O-O"),
+            Err(MoveError::Illegal("O-O-O".to_string()))
+        );
+        assert_eq!(parse(fen, "Ng1"), Ok("f3g1".to_string()));
+        assert_eq!(parse(fen, "Nfg1"), Ok("f3g1".to_string()));
+        assert_eq!(parse(fen, "Nf3-g5!?"), Ok("f3g5".to_string()));
+        assert_eq!(parse(fen, "e4"), Ok("e2e4".to_string()));
+        let fen = "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(parse(fen, "axb8=N"), Ok("a7b8n".to_string()));
+        assert_eq!(parse(fen, "a8Q+"), Ok("a7a8q".to_string()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // Knights on b1 and f3 can both reach d2
+        let fen = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(
+            parse(fen, "Nd2"),
+            Err(MoveError::Ambiguous("Nd2".to_string()))
+        );
+        assert_eq!(parse(fen, "Nd4"), Ok("f3d4".to_string()));
+        assert_eq!(
+            parse(fen, "Nd5"),
+            Err(MoveError::Illegal("Nd5".to_string()))
+        );
+        assert_eq!(
+            parse(fen, "Nz9"),
+            Err(MoveError::InvalidFormat("Nz9".to_string()))
+        );
+        assert_eq!(
+            parse(fen, ""),
+            Err(MoveError::InvalidFormat("".to_string()))
+        );
+        // A pawn reaching the last rank must promote
+        let fen = "4k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(parse(fen, "a8"), Err(MoveError::Illegal("a8".to_string())));
+    }
+
+    #[test]
+    fn test_disambiguation_ignores_pinned_pieces() {
+        // The knight on e2 is pinned by the rook on e8 so only the knight on b1 can reach c3
+        let fen = "4r1k1/8/8/8/8/8/4N3/1N2K3 w - - 0 1";
+        assert_eq!(san(fen, "b1c3"), "Nc3");
+        // Without the pin both can
+        let fen = "6k1/8/8/8/8/8/4N3/1N2K3 w - - 0 1";
+        assert_eq!(san(fen, "b1c3"), "Nbc3");
+    }
+
+    #[test]
+    fn test_promotion() {
+        let fen = "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "a7a8q"), "a8=Q");
+        assert_eq!(san(fen, "a7b8n"), "axb8=N");
+        let fen = "4k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "a7a8r"), "a8=R+");
+    }
+
+    #[test]
+    fn test_check_and_checkmate() {
+        let fen = "6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1a8"), "Ra8#");
+        let fen = "6k1/5pp1/8/8/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(fen, "a1a8"), "Ra8+");
+        // Promoting with checkmate
+        let fen = "6k1/P4ppp/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(san(fen, "a7a8q"), "a8=Q#");
+    }
+}
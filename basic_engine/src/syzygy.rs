@@ -0,0 +1,309 @@
+//! Syzygy endgame tablebase probing through Fathom
+//!
+//! Probing needs the `syzygy` feature, which links against a Fathom library built separately
+//! (libfathom, from https://github.com/jdart1/Fathom). Without it no tables can be loaded, so every
+//! probe returns None. Fathom numbers squares from a1 to h8 like the board, so the bitboards are
+//! passed to it as they are.
+
+use crate::board::Board;
+use crate::misc::Color;
+use std::sync::RwLock;
+
+// Fathom's packed probe results: the WDL in the low 4 bits and the DTZ in the top 12
+const RESULT_FAILED: u32 = 0xFFFF_FFFF;
+const WDL_MASK: u32 = 0xF;
+const DTZ_SHIFT: u32 = 20;
+const DTZ_MASK: u32 = 0xFFF;
+
+lazy_static! {
+    // Fathom can't probe while tables are being loaded, loading takes the write lock
+    static ref TABLES: RwLock<()> = RwLock::new(());
+}
+
+/// Result of a tablebase position for the side to move, assuming the best play by both sides
+///
+/// Cursed wins and blessed losses would be won and lost without the fifty move rule, which draws
+/// them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_fathom(wdl: u32) -> Option<Wdl> {
+        match wdl {
+            0 => Some(Wdl::Loss),
+            1 => Some(Wdl::BlessedLoss),
+            2 => Some(Wdl::Draw),
+            3 => Some(Wdl::CursedWin),
+            4 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+}
+
+/// The result of a tablebase position with the number of plies to the next capture or pawn move
+/// (the distance to zeroing) on the way to it, for the side to move
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Dtz {
+    Loss(u32),
+    BlessedLoss(u32),
+    Draw,
+    CursedWin(u32),
+    Win(u32),
+}
+
+impl Dtz {
+    pub fn wdl(&self) -> Wdl {
+        match self {
+            Dtz::Loss(_) => Wdl::Loss,
+            Dtz::BlessedLoss(_) => Wdl::BlessedLoss,
+            Dtz::Draw => Wdl::Draw,
+            Dtz::CursedWin(_) => Wdl::CursedWin,
+            Dtz::Win(_) => Wdl::Win,
+        }
+    }
+
+    fn from_fathom(result: u32) -> Option<Dtz> {
+        if result == RESULT_FAILED {
+            return None;
+        }
+        let plies = (result >> DTZ_SHIFT) & DTZ_MASK;
+        Some(match Wdl::from_fathom(result & WDL_MASK)? {
+            Wdl::Loss => Dtz::Loss(plies),
+            Wdl::BlessedLoss => Dtz::BlessedLoss(plies),
+            Wdl::Draw => Dtz::Draw,
+            Wdl::CursedWin => Dtz::CursedWin(plies),
+            Wdl::Win => Dtz::Win(plies),
+        })
+    }
+}
+
+/// Load the Syzygy tables in `path`, several directories are separated by `:` (`;` on Windows)
+///
+/// Tables loaded earlier are replaced. An empty path unloads them.
+pub fn init_tablebases(path: &str) -> Result<(), String> {
+    let _tables = TABLES.write().unwrap_or_else(|e| e.into_inner());
+    fathom::init(path)
+}
+
+/// The most pieces, kings included, of the positions the loaded tables cover, 0 if none are
+pub fn tablebase_pieces() -> u32 {
+    let _tables = TABLES.read().unwrap_or_else(|e| e.into_inner());
+    fathom::largest()
+}
+
+// Whether the loaded tables cover the position, they don't have castling rights
+fn covered(board: &Board) -> bool {
+    let pieces = board.occupancy(Color::White) | board.occupancy(Color::Black);
+    board.castle_permissions().bits() == 0 && pieces.count_ones() <= fathom::largest()
+}
+
+// Fathom reports checkmate and stalemate as results of their own, they're found on the board
+// instead
+fn game_over(board: &Board) -> Option<Wdl> {
+    match board.generate_legal_moves().is_empty() {
+        true if board.in_check() => Some(Wdl::Loss),
+        true => Some(Wdl::Draw),
+        false => None,
+    }
+}
+
+pub(crate) fn probe_wdl(board: &Board) -> Option<Wdl> {
+    let _tables = TABLES.read().unwrap_or_else(|e| e.into_inner());
+    if !covered(board) {
+        return None;
+    }
+    if let Some(wdl) = game_over(board) {
+        return Some(wdl);
+    }
+    let result = fathom::probe_wdl(board);
+    if result == RESULT_FAILED {
+        return None;
+    }
+    Wdl::from_fathom(result)
+}
+
+pub(crate) fn probe_dtz(board: &Board) -> Option<Dtz> {
+    let _tables = TABLES.read().unwrap_or_else(|e| e.into_inner());
+    if !covered(board) {
+        return None;
+    }
+    match game_over(board) {
+        Some(Wdl::Loss) => return Some(Dtz::Loss(0)),
+        Some(_) => return Some(Dtz::Draw),
+        None => {}
+    }
+    Dtz::from_fathom(fathom::probe_root(board))
+}
+
+#[cfg(feature = "syzygy")]
+mod fathom {
+    use crate::board::Board;
+    use crate::misc::{Color, Piece};
+    use crate::Game;
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_uint};
+    use std::ptr;
+
+    // The functions behind Fathom's inline tb_probe_wdl and tb_probe_root wrappers
+    #[link(name = "fathom")]
+    extern "C" {
+        static TB_LARGEST: c_uint;
+        fn tb_init(path: *const c_char) -> bool;
+        fn tb_probe_wdl_impl(
+            white: u64,
+            black: u64,
+            kings: u64,
+            queens: u64,
+            rooks: u64,
+            bishops: u64,
+            knights: u64,
+            pawns: u64,
+            ep: c_uint,
+            turn: bool,
+        ) -> c_uint;
+        fn tb_probe_root_impl(
+            white: u64,
+            black: u64,
+            kings: u64,
+            queens: u64,
+            rooks: u64,
+            bishops: u64,
+            knights: u64,
+            pawns: u64,
+            rule50: c_uint,
+            ep: c_uint,
+            turn: bool,
+            results: *mut c_uint,
+        ) -> c_uint;
+    }
+
+    // Both sides' pieces of one type
+    fn pieces(board: &Board, piece: Piece) -> u64 {
+        board.piece_bitboard(piece, Color::White) | board.piece_bitboard(piece, Color::Black)
+    }
+
+    // Fathom takes 0 for no en passant square
+    fn en_passant(board: &Board) -> c_uint {
+        board.en_passant().map_or(0, c_uint::from)
+    }
+
+    pub(super) fn init(path: &str) -> Result<(), String> {
+        let c_path = CString::new(path).map_err(|_| format!("invalid path: {}", path))?;
+        // SAFETY: the caller holds the write lock so nothing is probing, the path outlives the call
+        match unsafe { tb_init(c_path.as_ptr()) } {
+            true => Ok(()),
+            false => Err(format!("failed to load tablebases from {}", path)),
+        }
+    }
+
+    pub(super) fn largest() -> u32 {
+        // SAFETY: only written by tb_init, which can't run while a lock on the tables is held
+        unsafe { TB_LARGEST }
+    }
+
+    pub(super) fn probe_wdl(board: &Board) -> u32 {
+        // SAFETY: the caller holds a read lock so the tables aren't being loaded
+        unsafe {
+            tb_probe_wdl_impl(
+                board.occupancy(Color::White),
+                board.occupancy(Color::Black),
+                pieces(board, Piece::King),
+                pieces(board, Piece::Queen),
+                pieces(board, Piece::Rook),
+                pieces(board, Piece::Bishop),
+                pieces(board, Piece::Knight),
+                pieces(board, Piece::Pawn),
+                en_passant(board),
+                board.active_color() == Color::White,
+            )
+        }
+    }
+
+    pub(super) fn probe_root(board: &Board) -> u32 {
+        // SAFETY: as for probe_wdl, Fathom accepts a null list for the results of each move
+        unsafe {
+            tb_probe_root_impl(
+                board.occupancy(Color::White),
+                board.occupancy(Color::Black),
+                pieces(board, Piece::King),
+                pieces(board, Piece::Queen),
+                pieces(board, Piece::Rook),
+                pieces(board, Piece::Bishop),
+                pieces(board, Piece::Knight),
+                pieces(board, Piece::Pawn),
+                board.halfmove_clock() as c_uint,
+                en_passant(board),
+                board.active_color() == Color::White,
+                ptr::null_mut(),
+            )
+        }
+    }
+}
+
+// Without Fathom there are never any tables to probe
+#[cfg(not(feature = "syzygy"))]
+mod fathom {
+    use crate::board::Board;
+
+    pub(super) fn init(_path: &str) -> Result<(), String> {
+        Err("built without the syzygy feature".to_string())
+    }
+
+    pub(super) fn largest() -> u32 {
+        0
+    }
+
+    pub(super) fn probe_wdl(_board: &Board) -> u32 {
+        super::RESULT_FAILED
+    }
+
+    pub(super) fn probe_root(_board: &Board) -> u32 {
+        super::RESULT_FAILED
+    }
+}
+
+#[cfg(test)]
+mod test_syzygy {
+    use super::{Dtz, Wdl, DTZ_SHIFT, RESULT_FAILED};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_decode_wdl() {
+        assert_eq!(Wdl::from_fathom(0), Some(Wdl::Loss));
+        assert_eq!(Wdl::from_fathom(2), Some(Wdl::Draw));
+        assert_eq!(Wdl::from_fathom(4), Some(Wdl::Win));
+        assert_eq!(Wdl::from_fathom(5), None);
+    }
+
+    #[test]
+    fn test_decode_dtz() {
+        // The best move's squares and promotion sit between the WDL and the DTZ
+        let result = |wdl: u32, dtz: u32| wdl | (12 << 4) | (28 << 10) | (dtz << DTZ_SHIFT);
+        assert_eq!(Dtz::from_fathom(result(4, 17)), Some(Dtz::Win(17)));
+        assert_eq!(Dtz::from_fathom(result(3, 120)), Some(Dtz::CursedWin(120)));
+        assert_eq!(Dtz::from_fathom(result(2, 0)), Some(Dtz::Draw));
+        assert_eq!(
+            Dtz::from_fathom(result(1, 101)),
+            Some(Dtz::BlessedLoss(101))
+        );
+        assert_eq!(Dtz::from_fathom(result(0, 3)), Some(Dtz::Loss(3)));
+        assert_eq!(Dtz::from_fathom(RESULT_FAILED), None);
+        assert_eq!(Dtz::Loss(3).wdl(), Wdl::Loss);
+    }
+
+    #[cfg(not(feature = "syzygy"))]
+    #[test]
+    fn test_no_tables_without_fathom() {
+        assert!(super::init_tablebases("tables").is_err());
+        assert_eq!(super::tablebase_pieces(), 0);
+        let board = crate::Board::from_fen("8/8/8/8/8/4k3/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(board.probe_tb_wdl(), None);
+        assert_eq!(board.probe_tb_dtz(), None);
+    }
+}
@@ -0,0 +1,355 @@
+use crate::instant::Instant;
+use crate::misc::Color;
+use std::time::Duration;
+
+// Scores beyond this are considered decided (includes all checkmate scores)
+const DECIDED_SCORE: i64 = 1000;
+// Change in score between iterations which marks the position as sharp
+const SWING_SCORE: i64 = 30;
+
+const FORCED_SCALE: f64 = 0.1;
+const DECIDED_SCALE: f64 = 0.5;
+const SWING_SCALE: f64 = 1.3;
+const FAIL_LOW_SCALE: f64 = 1.6;
+
+// Best move changes count for less each iteration so recent changes matter most
+const INSTABILITY_DECAY: f64 = 0.5;
+// Extra scale for each (decayed) best move change
+const INSTABILITY_SCALE: f64 = 0.5;
+// Number of iterations the best move must stay the same for before it is considered obvious
+const OBVIOUS_ITERATIONS: u32 = 6;
+const OBVIOUS_SCALE: f64 = 0.7;
+
+// Moves the remaining clock time is shared between when the number of moves to go isn't known
+const DEFAULT_MOVES_TO_GO: u32 = 40;
+// Hard time limit as a multiple of the soft limit when playing with a clock
+const HARD_LIMIT_FACTOR: u32 = 4;
+// Largest safety margin taken off a time limit, smaller limits lose a tenth
+const MAX_BUFFER: Duration = Duration::from_millis(50);
+
+/// Time left on each side's clock, as sent with the UCI go command
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Clock {
+    pub white_time: Option<Duration>,
+    pub black_time: Option<Duration>,
+    pub white_increment: Duration,
+    pub black_increment: Duration,
+    pub moves_to_go: Option<u32>, // Moves until the next time control
+}
+
+impl Clock {
+    /// Soft and hard time limits for a move by `color`, None if its clock isn't known
+    pub fn limits(&self, color: Color) -> Option<(Duration, Duration)> {
+        let (time, increment) = match color {
+            Color::White => (self.white_time?, self.white_increment),
+            Color::Black => (self.black_time?, self.black_increment),
+        };
+        let moves = self
+            .moves_to_go
+            .unwrap_or(DEFAULT_MOVES_TO_GO)
+            .clamp(1, DEFAULT_MOVES_TO_GO);
+        let soft = time / moves + increment;
+        // Allow a long search when the soft limit is extended but keep enough time on the clock
+        // for the following moves
+        let hard = (soft * HARD_LIMIT_FACTOR).min(time / 3);
+        Some((with_buffer(soft.min(hard)), with_buffer(hard)))
+    }
+}
+
+/// Take a safety margin off a time limit so the search doesn't overrun it
+pub fn with_buffer(limit: Duration) -> Duration {
+    limit - (limit / 10).min(MAX_BUFFER)
+}
+
+/// Time Manager tracks the time budget for a single search.
+///
+/// There are two limits, no new iteration is started after the soft limit and the search is
+/// aborted mid-iteration at the hard limit. The soft limit comes from the clock, after each
+/// completed iteration it is scaled up when the score is swinging (particularly when it drops)
+/// or the best move keeps changing, and scaled down when the position is forced, already
+/// decided or the best move is obvious. The hard limit is never scaled so the clock can't run
+/// out, nor is a fixed soft limit such as a move time, as the search was asked to take that long.
+#[derive(Debug, Copy, Clone)]
+pub struct TimeManager {
+    start_time: Instant,
+    allocated: Option<Duration>,
+    fixed_limit: Option<Duration>,
+    hard_limit: Option<Duration>,
+    scale: f64,
+    forced: bool,
+    previous_score: Option<i64>,
+    instability: f64,       // Decayed count of best move changes
+    stable_iterations: u32, // Iterations since the best move last changed
+}
+
+impl TimeManager {
+    pub fn new(
+        start_time: Instant,
+        soft_limit: Option<Duration>,
+        hard_limit: Option<Duration>,
+    ) -> Self {
+        Self {
+            start_time,
+            allocated: soft_limit,
+            fixed_limit: None,
+            hard_limit,
+            scale: 1.0,
+            forced: false,
+            previous_score: None,
+            instability: 0.0,
+            stable_iterations: 0,
+        }
+    }
+
+    /// Also stop starting iterations after `limit`, whatever the position, e.g. for a move time
+    pub fn set_fixed_limit(&mut self, limit: Option<Duration>) {
+        self.fixed_limit = limit;
+    }
+
+    /// Mark the position as forced (only one legal move) so very little time is spent on it
+    pub fn set_forced(&mut self, forced: bool) {
+        self.forced = forced;
+        if forced {
+            self.scale = FORCED_SCALE;
+        }
+    }
+
+    /// Update the time budget based on the score from a completed iteration and whether its
+    /// best move differs from the previous iteration
+    pub fn record_iteration(&mut self, score: i64, best_move_changed: bool) {
+        self.instability *= INSTABILITY_DECAY;
+        if best_move_changed {
+            self.instability += 1.0;
+            self.stable_iterations = 0;
+        } else {
+            self.stable_iterations += 1;
+        }
+
+        let score_scale = if score.abs() >= DECIDED_SCORE {
+            DECIDED_SCALE
+        } else {
+            match self.previous_score {
+                Some(previous) if previous - score >= SWING_SCORE => FAIL_LOW_SCALE,
+                Some(previous) if (score - previous).abs() >= SWING_SCORE => SWING_SCALE,
+                _ => 1.0,
+            }
+        };
+        let move_scale = if self.stable_iterations >= OBVIOUS_ITERATIONS {
+            OBVIOUS_SCALE
+        } else {
+            1.0 + INSTABILITY_SCALE * self.instability
+        };
+        self.scale = if self.forced {
+            FORCED_SCALE
+        } else {
+            score_scale * move_scale
+        };
+        self.previous_score = Some(score);
+    }
+
+    /// The current time after which no new iteration should start, None if there is no limit
+    pub fn soft_limit(&self) -> Option<Duration> {
+        let scaled = self.allocated.map(|a| a.mul_f64(self.scale));
+        let soft = [scaled, self.fixed_limit].into_iter().flatten().min();
+        match (soft, self.hard_limit) {
+            (Some(soft), Some(hard)) => Some(soft.min(hard)),
+            (soft, _) => soft,
+        }
+    }
+
+    /// Count the time limits from `start_time` instead, e.g. when pondering becomes a search
+    pub fn restart(&mut self, start_time: Instant) {
+        self.start_time = start_time;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
+    /// True if there isn't time to start another iteration
+    pub fn is_soft_limit_reached(&self) -> bool {
+        match self.soft_limit() {
+            Some(limit) => self.elapsed() >= limit,
+            None => false,
+        }
+    }
+
+    /// True if the search must stop immediately
+    pub fn is_time_up(&self) -> bool {
+        match self.hard_limit {
+            Some(limit) => self.elapsed() >= limit,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_time_manager {
+    use super::{Clock, TimeManager};
+    use crate::misc::Color;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, Instant};
+
+    fn manager() -> TimeManager {
+        TimeManager::new(
+            Instant::now(),
+            Some(Duration::from_millis(1000)),
+            Some(Duration::from_millis(5000)),
+        )
+    }
+
+    #[test]
+    fn test_stable_score_keeps_allocation() {
+        let mut tm = manager();
+        tm.record_iteration(20, false);
+        tm.record_iteration(25, false);
+        assert_eq!(tm.soft_limit(), Some(Duration::from_millis(1000)));
+    }
+
+    #[test]
+    fn test_score_drop_extends_allocation() {
+        let mut tm = manager();
+        tm.record_iteration(20, false);
+        tm.record_iteration(-40, false);
+        assert!(tm.soft_limit().unwrap() > Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_fail_low_extends_more_than_rise() {
+        let mut drop = manager();
+        drop.record_iteration(20, false);
+        drop.record_iteration(-40, false);
+        let mut rise = manager();
+        rise.record_iteration(20, false);
+        rise.record_iteration(80, false);
+        assert!(drop.soft_limit().unwrap() > rise.soft_limit().unwrap());
+    }
+
+    #[test]
+    fn test_decided_position_shortens_allocation() {
+        let mut tm = manager();
+        tm.record_iteration(1500, false);
+        assert!(tm.soft_limit().unwrap() < Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_forced_position_shortens_allocation() {
+        let mut tm = manager();
+        tm.set_forced(true);
+        tm.record_iteration(-200, false);
+        assert_eq!(tm.soft_limit(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_unstable_best_move_extends_allocation() {
+        let mut tm = manager();
+        tm.record_iteration(20, false);
+        tm.record_iteration(20, true);
+        let once = tm.soft_limit().unwrap();
+        assert!(once > Duration::from_millis(1000));
+        tm.record_iteration(20, true);
+        assert!(tm.soft_limit().unwrap() > once);
+    }
+
+    #[test]
+    fn test_instability_decays() {
+        let mut tm = manager();
+        tm.record_iteration(20, true);
+        let extended = tm.soft_limit().unwrap();
+        tm.record_iteration(20, false);
+        tm.record_iteration(20, false);
+        assert!(tm.soft_limit().unwrap() < extended);
+        assert!(tm.soft_limit().unwrap() > Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_obvious_best_move_shortens_allocation() {
+        let mut tm = manager();
+        for _ in 0..6 {
+            tm.record_iteration(20, false);
+        }
+        assert!(tm.soft_limit().unwrap() < Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_fixed_limit_isnt_scaled() {
+        let mut tm = TimeManager::new(Instant::now(), None, Some(Duration::from_millis(1000)));
+        tm.set_fixed_limit(Some(Duration::from_millis(1000)));
+        tm.set_forced(true);
+        tm.record_iteration(1500, false);
+        assert_eq!(tm.soft_limit(), Some(Duration::from_millis(1000)));
+
+        // With a clock as well only the clock's allocation is scaled
+        let mut tm = manager();
+        tm.set_fixed_limit(Some(Duration::from_millis(800)));
+        tm.record_iteration(1500, false);
+        assert_eq!(tm.soft_limit(), Some(Duration::from_millis(500)));
+        tm.set_forced(false);
+        tm.record_iteration(20, false);
+        tm.record_iteration(-40, false);
+        assert_eq!(tm.soft_limit(), Some(Duration::from_millis(800)));
+    }
+
+    #[test]
+    fn test_no_allocation_never_stops() {
+        let mut tm = TimeManager::new(Instant::now(), None, None);
+        tm.record_iteration(1500, false);
+        assert_eq!(tm.soft_limit(), None);
+        assert!(!tm.is_soft_limit_reached());
+        assert!(!tm.is_time_up());
+    }
+
+    #[test]
+    fn test_soft_limit_capped_by_hard_limit() {
+        let mut tm = TimeManager::new(
+            Instant::now(),
+            Some(Duration::from_millis(1000)),
+            Some(Duration::from_millis(1200)),
+        );
+        tm.record_iteration(20, false);
+        tm.record_iteration(-40, false);
+        assert_eq!(tm.soft_limit(), Some(Duration::from_millis(1200)));
+    }
+
+    #[test]
+    fn test_soft_limit_reached_before_hard_limit() {
+        let start = Instant::now() - Duration::from_millis(2000);
+        let tm = TimeManager::new(
+            start,
+            Some(Duration::from_millis(1000)),
+            Some(Duration::from_millis(5000)),
+        );
+        assert!(tm.is_soft_limit_reached());
+        assert!(!tm.is_time_up());
+    }
+
+    #[test]
+    fn test_clock_limits() {
+        let clock = Clock {
+            white_time: Some(Duration::from_secs(60)),
+            black_time: Some(Duration::from_secs(30)),
+            white_increment: Duration::from_secs(1),
+            ..Clock::default()
+        };
+        assert_eq!(
+            clock.limits(Color::White),
+            Some((Duration::from_millis(2450), Duration::from_millis(9950)))
+        );
+        assert_eq!(
+            clock.limits(Color::Black),
+            Some((Duration::from_millis(700), Duration::from_millis(2950)))
+        );
+
+        // Short of time the hard limit keeps most of the clock for later moves
+        let clock = Clock {
+            white_time: Some(Duration::from_secs(3)),
+            moves_to_go: Some(1),
+            ..Clock::default()
+        };
+        assert_eq!(
+            clock.limits(Color::White),
+            Some((Duration::from_millis(950), Duration::from_millis(950)))
+        );
+        assert_eq!(clock.limits(Color::Black), None);
+    }
+}
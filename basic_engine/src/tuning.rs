@@ -0,0 +1,327 @@
+use crate::board::Board;
+use crate::eval_params::EvalParams;
+use crate::misc::Color;
+use crate::Game;
+use std::fs;
+use std::sync::Arc;
+
+// Material weights for each piece but the king, whose material is fixed
+const MATERIAL_COUNT: usize = 5;
+const PST_START: usize = MATERIAL_COUNT;
+const KING_ENDGAME_START: usize = PST_START + 6 * 64;
+const WEIGHT_COUNT: usize = KING_ENDGAME_START + 64 + 1 + 8 + 1 + 1 + 8 + 1 + 8;
+
+// Range searched for the scaling constant of the sigmoid
+const MIN_K: f64 = 0.1;
+const MAX_K: f64 = 4.0;
+const K_ITERATIONS: usize = 100;
+
+/// The evaluation weights being tuned: every weight of EvalParams but the king's material
+///
+/// The material values come first, then the piece-square tables, then the other terms in the
+/// order they're declared in EvalParams. Tables are indexed by square from white's point of view
+/// (a1 is 0).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Weights {
+    values: Vec<i64>,
+}
+
+impl Weights {
+    pub fn from_params(params: &EvalParams) -> Self {
+        let mut values = Vec::with_capacity(WEIGHT_COUNT);
+        values.extend(
+            params.material[..MATERIAL_COUNT]
+                .iter()
+                .map(|&m| i64::from(m)),
+        );
+        // The tables are stored with rank 8 first
+        for table in params.tables.iter().chain([&params.king_endgame]) {
+            values.extend((0..64).map(|square| table[square ^ 56] as i64));
+        }
+        values.push(i64::from(params.drawish_advantage));
+        values.extend(params.passed_pawn.iter().map(|&v| v as i64));
+        values.push(params.passed_pawn_rook_behind as i64);
+        values.push(params.passed_pawn_king_distance as i64);
+        values.extend(params.king_shelter.iter().map(|&v| v as i64));
+        values.push(params.king_open_file as i64);
+        values.extend(params.pawn_storm.iter().map(|&v| v as i64));
+        Self { values }
+    }
+
+    /// The parameters from `base` with the tuned weights in place
+    pub fn to_params(&self, base: &EvalParams) -> EvalParams {
+        let mut params = base.clone();
+        let mut values = self.values.iter().copied();
+        let mut next = || values.next().unwrap();
+        for m in &mut params.material[..MATERIAL_COUNT] {
+            *m = next().max(0) as u32;
+        }
+        for table in params.tables.iter_mut().chain([&mut params.king_endgame]) {
+            for square in 0..64 {
+                table[square ^ 56] = next() as isize;
+            }
+        }
+        params.drawish_advantage = next().max(0) as u32;
+        params.passed_pawn.fill_with(|| next() as isize);
+        params.passed_pawn_rook_behind = next() as isize;
+        params.passed_pawn_king_distance = next() as isize;
+        params.king_shelter.fill_with(|| next() as isize);
+        params.king_open_file = next() as isize;
+        params.pawn_storm.fill_with(|| next() as isize);
+        params
+    }
+}
+
+/// A position from a game and the result of the game, from white's point of view (1 is a win)
+#[derive(Debug, Clone)]
+pub struct TuningPosition {
+    pub board: Board,
+    pub result: f64,
+}
+
+impl TuningPosition {
+    /// Parse a FEN followed by the game result, e.g. `<fen> [1-0]`, `<fen> [0.5]` or the EPD
+    /// style `<fen> c9 "1/2-1/2";`
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim().trim_end_matches(';');
+        let (fen, result) = match line.split_once(" c9 ") {
+            Some((fen, result)) => (fen, result),
+            None => line
+                .rsplit_once(' ')
+                .ok_or_else(|| format!("no result found: {}", line))?,
+        };
+        let result = match result.trim_matches(|c| matches!(c, '[' | ']' | '"' | ' ')) {
+            "1-0" | "1.0" | "1" => 1.0,
+            "0-1" | "0.0" | "0" => 0.0,
+            "1/2-1/2" | "0.5" => 0.5,
+            r => return Err(format!("unknown result: {}", r)),
+        };
+        // EPD positions don't have the move clocks
+        let board = match fen.split_whitespace().count() {
+            4 => Board::from_fen(&format!("{} 0 1", fen.trim())),
+            _ => Board::from_fen(fen.trim()),
+        }?;
+        Ok(Self { board, result })
+    }
+}
+
+/// Read the positions in a dataset file, one per line
+pub fn load_dataset(path: &str) -> Result<Vec<TuningPosition>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    contents
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(TuningPosition::parse)
+        .collect()
+}
+
+/// Expected score for white given an evaluation in centipawns
+fn sigmoid(eval: i64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval as f64 / 400.0))
+}
+
+/// Tunes the evaluation weights to predict the results of a set of positions
+///
+/// Each position is scored by the full evaluation with the weights being tried, so every term is
+/// tuned including the drawish scaling. The evaluation error is the mean squared difference
+/// between the results and the sigmoid of the evaluation.
+#[derive(Debug)]
+pub struct Tuner {
+    positions: Vec<TuningPosition>,
+    base: Arc<EvalParams>, // For the king's material, which isn't tuned
+    k: f64,
+}
+
+impl Tuner {
+    /// A tuner starting from `base`, whose king material is kept in every set of weights tried
+    pub fn new(positions: &[TuningPosition], base: EvalParams) -> Self {
+        Self {
+            positions: positions.to_vec(),
+            base: Arc::new(base),
+            k: 1.0,
+        }
+    }
+
+    pub fn k(&self) -> f64 {
+        self.k
+    }
+
+    /// Pick the sigmoid scaling constant which best fits the results with the given weights
+    pub fn fit_k(&mut self, weights: &Weights) -> f64 {
+        let evals = self.evals(weights);
+        // The error is convex in k so a ternary search finds the minimum
+        let (mut low, mut high) = (MIN_K, MAX_K);
+        for _ in 0..K_ITERATIONS {
+            let third = (high - low) / 3.0;
+            if self.error_with_k(&evals, low + third) < self.error_with_k(&evals, high - third) {
+                high -= third;
+            } else {
+                low += third;
+            }
+        }
+        self.k = (low + high) / 2.0;
+        self.k
+    }
+
+    pub fn error(&mut self, weights: &Weights) -> f64 {
+        let evals = self.evals(weights);
+        self.error_with_k(&evals, self.k)
+    }
+
+    /// Evaluation of each position from white's point of view
+    fn evals(&mut self, weights: &Weights) -> Vec<i64> {
+        let params = Arc::new(weights.to_params(&self.base));
+        self.positions
+            .iter_mut()
+            .map(|p| {
+                p.board.set_eval_params(Arc::clone(&params));
+                match p.board.active_color() {
+                    Color::White => p.board.eval(),
+                    Color::Black => -p.board.eval(),
+                }
+            })
+            .collect()
+    }
+
+    fn error_with_k(&self, evals: &[i64], k: f64) -> f64 {
+        let total: f64 = self
+            .positions
+            .iter()
+            .zip(evals)
+            .map(|(p, &eval)| (p.result - sigmoid(eval, k)).powi(2))
+            .sum();
+        total / self.positions.len().max(1) as f64
+    }
+
+    /// Adjust each weight by one at a time while it reduces the error, for at most `max_passes`
+    /// passes over the weights
+    ///
+    /// `on_pass` is called with the pass number, error and weights after each pass.
+    pub fn local_search<F: FnMut(usize, f64, &Weights)>(
+        &mut self,
+        weights: &mut Weights,
+        max_passes: usize,
+        mut on_pass: F,
+    ) -> f64 {
+        let mut best = self.error(weights);
+        for pass in 1..=max_passes {
+            let mut improved = false;
+            for i in 0..WEIGHT_COUNT {
+                for step in [1, -2] {
+                    weights.values[i] += step;
+                    let error = self.error(weights);
+                    if error < best {
+                        best = error;
+                        improved = true;
+                        break;
+                    }
+                    if step == -2 {
+                        weights.values[i] += 1;
+                    }
+                }
+            }
+            on_pass(pass, best, weights);
+            if !improved {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod test_tuning {
+    use super::{Tuner, TuningPosition, Weights};
+    use crate::eval_params::EvalParams;
+    use crate::misc::Color;
+    use crate::Game;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_results() {
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        for (line, result) in [
+            (format!("{} [1-0]", fen), 1.0),
+            (format!("{} [0.5]", fen), 0.5),
+            (format!("{} \"0-1\"", fen), 0.0),
+            (
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - c9 \"1/2-1/2\";"
+                    .to_string(),
+                0.5,
+            ),
+        ] {
+            let position = TuningPosition::parse(&line).unwrap();
+            assert_eq!(position.result, result);
+            assert_eq!(position.board.key, crate::Board::from_fen(fen).unwrap().key);
+        }
+        assert!(TuningPosition::parse(&format!("{} [2-0]", fen)).is_err());
+    }
+
+    #[test]
+    fn test_evals_use_full_evaluation() {
+        // Passed pawns and king safety aren't material or piece-square terms
+        let fens = [
+            "8/6k1/8/8/8/2P5/5K2/8 w - - 0 1",
+            "r2q1rk1/ppp2ppp/8/8/8/8/PPP2P1P/2KRQ2R b - - 0 1",
+        ];
+        let positions: Vec<TuningPosition> = fens
+            .iter()
+            .map(|fen| TuningPosition::parse(&format!("{} [0.5]", fen)).unwrap())
+            .collect();
+        let mut tuner = Tuner::new(&positions, EvalParams::default());
+        let evals = tuner.evals(&Weights::from_params(&EvalParams::default()));
+        for (fen, eval) in fens.iter().zip(&evals) {
+            let board = crate::Board::from_fen(fen).unwrap();
+            let expected = match board.active_color() {
+                Color::White => board.eval(),
+                Color::Black => -board.eval(),
+            };
+            assert_eq!(*eval, expected, "{}", fen);
+        }
+
+        let mut params = EvalParams::default();
+        params.passed_pawn[2] += 10;
+        params.king_open_file -= 10;
+        let tuned = tuner.evals(&Weights::from_params(&params));
+        assert_eq!(tuned[0], evals[0] + 10);
+        assert!(tuned[1] > evals[1]);
+    }
+
+    #[test]
+    fn test_weights_round_trip_params() {
+        let mut params = EvalParams::default();
+        params.tables[0][8] = 60; // a7 for white
+        params.king_endgame[63] = -60; // h1 for white
+        params.drawish_advantage += 1;
+        params.passed_pawn[7] += 2;
+        params.passed_pawn_king_distance += 3;
+        params.king_shelter[1] -= 4;
+        params.pawn_storm[6] -= 5;
+        let weights = Weights::from_params(&params);
+        assert_eq!(weights.values.len(), super::WEIGHT_COUNT);
+        assert_eq!(weights.values[super::PST_START + 48], 60);
+        assert_eq!(weights.to_params(&EvalParams::default()), params);
+    }
+
+    #[test]
+    fn test_local_search_reduces_error() {
+        // White wins every game where it is a knight up
+        let positions: Vec<TuningPosition> = [
+            "4k3/pppppppp/8/8/8/5N2/PPPPPPPP/4K3 w - - 0 1 [1-0]",
+            "4k3/pppppppp/8/8/8/2N5/PPPPPPPP/4K3 b - - 0 1 [1-0]",
+            "4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1 [1/2-1/2]",
+        ]
+        .iter()
+        .map(|l| TuningPosition::parse(l).unwrap())
+        .collect();
+        let mut tuner = Tuner::new(&positions, EvalParams::default());
+        let mut weights = Weights::from_params(&EvalParams::default());
+        tuner.fit_k(&weights);
+        let before = tuner.error(&weights);
+        let mut passes = 0;
+        let after = tuner.local_search(&mut weights, 3, |_, _, _| passes += 1);
+        assert!(after < before);
+        assert_eq!(after, tuner.error(&weights));
+        assert!(passes > 0 && passes <= 3);
+    }
+}
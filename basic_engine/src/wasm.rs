@@ -0,0 +1,135 @@
+//! JavaScript bindings for playing and analysing in a browser, built with wasm-pack
+//!
+//! ```js
+//! const engine = new Engine();
+//! engine.setPosition(null, "e2e4 e7e5");
+//! const best = engine.go(1000, (info) => console.log(info.depth, info.score, info.pv));
+//! ```
+//!
+//! A search runs on the calling thread, so it's best run in a Web Worker. Calling stop from the
+//! info callback is the only supported way to end a search early: nothing else on that thread
+//! runs until go returns, including a worker's message handlers. To stop from the page, have
+//! the callback check a flag the page sets, e.g. in a SharedArrayBuffer.
+
+use crate::board::Board;
+use crate::engine::{AlphaBeta, Engine as _, SearchInfo, SearchParameters, StopHandle};
+use crate::play::Play;
+use js_sys::{Array, Function, Object, Reflect};
+use std::cell::RefCell;
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+// Browsers limit a page's memory, so the table is much smaller than the native default
+const DEFAULT_HASH_SIZE_MB: usize = 16;
+
+#[wasm_bindgen]
+pub struct Engine {
+    // Methods only borrow the engine while they run so stop can be called during go
+    engine: RefCell<AlphaBeta<Board>>,
+    stop: StopHandle,
+}
+
+#[wasm_bindgen]
+impl Engine {
+    /// An engine at the starting position with a 16 MB transposition table
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Engine {
+        crate::init();
+        let engine = AlphaBeta::with_hash_size(Board::new(), DEFAULT_HASH_SIZE_MB);
+        let stop = engine.stop_handle();
+        Engine {
+            engine: RefCell::new(engine),
+            stop,
+        }
+    }
+
+    /// Replace the transposition table with an empty one of `megabytes`
+    #[wasm_bindgen(js_name = setHashSize)]
+    pub fn set_hash_size(&self, megabytes: u32) {
+        self.engine.borrow_mut().set_hash_size(megabytes as usize);
+    }
+
+    /// Set the position from a FEN, or the starting position if `fen` is null, followed by the
+    /// space separated moves in UCI notation
+    ///
+    /// Throws if the FEN or one of the moves isn't valid, the position is then left as it was
+    /// before the invalid move.
+    #[wasm_bindgen(js_name = setPosition)]
+    pub fn set_position(&self, fen: Option<String>, moves: Option<String>) -> Result<(), JsError> {
+        let mut engine = self.engine.borrow_mut();
+        engine
+            .parse_fen(fen.as_deref().unwrap_or(START_FEN))
+            .map_err(|e| JsError::new(&e))?;
+        for play in moves.as_deref().unwrap_or("").split_whitespace() {
+            engine.make_move_str(play).map_err(|e| JsError::new(&e))?;
+        }
+        Ok(())
+    }
+
+    /// Search for `move_time_ms` milliseconds and return the best move in UCI notation, or
+    /// undefined if there is no legal move
+    ///
+    /// `on_info` is called after each iteration with an object holding the depth, seldepth,
+    /// score (null for a mate), mate (the moves to mate, null if there isn't one), nodes, nps,
+    /// time and pv.
+    pub fn go(&self, move_time_ms: u32, on_info: Option<Function>) -> Option<String> {
+        self.stop.reset();
+        let mut params =
+            SearchParameters::builder().move_time(Duration::from_millis(move_time_ms.into()));
+        if let Some(on_info) = on_info {
+            params = params.on_iteration(move |info| {
+                // An exception from the callback doesn't abort the search
+                let _ = on_info.call1(&JsValue::NULL, &info_object(info));
+            });
+        }
+        self.engine
+            .borrow_mut()
+            .iterative_deepening_search(params.build())
+            .ok()
+            .map(|best_move| best_move.to_string())
+    }
+
+    /// Stop the running search, go returns the best move from the last completed iteration
+    ///
+    /// Only takes effect when called from go's info callback, see the module documentation.
+    pub fn stop(&self) {
+        self.stop.stop();
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn info_object(info: &SearchInfo<Play>) -> JsValue {
+    let millis = info.time.as_millis() as u64;
+    let nps = info.nodes * 1000 / millis.max(1);
+    let (score, mate) = match info.mate {
+        Some(mate_in) => (JsValue::NULL, JsValue::from(mate_in as f64)),
+        None => (JsValue::from(info.score as f64), JsValue::NULL),
+    };
+    let pv: Array = info
+        .pv
+        .iter()
+        .map(|play| JsValue::from(play.to_string()))
+        .collect();
+    let fields = [
+        ("depth", JsValue::from(info.depth)),
+        ("seldepth", JsValue::from(info.selective_depth)),
+        ("score", score),
+        ("mate", mate),
+        ("nodes", JsValue::from(info.nodes as f64)),
+        ("nps", JsValue::from(nps as f64)),
+        ("time", JsValue::from(millis as f64)),
+        ("pv", pv.into()),
+    ];
+    let object = Object::new();
+    for (key, value) in fields {
+        // Setting a property on a plain object can't fail
+        let _ = Reflect::set(&object, &JsValue::from(key), &value);
+    }
+    object.into()
+}
@@ -7,8 +7,8 @@ use rand::{Rng, SeedableRng};
 pub struct Zorbrist {
     pieces: [[u64; 64]; 12],
     pub side: u64,
-    //TODO castling:
     en_passant: [u64; 8],
+    castle: [u64; 4], // one for each CastlePermissions bit
 }
 
 impl Zorbrist {
@@ -25,6 +25,7 @@ impl Zorbrist {
             pieces,
             side: rng.gen(),
             en_passant: rng.gen(),
+            castle: rng.gen(),
         }
     }
 
@@ -39,6 +40,14 @@ impl Zorbrist {
     pub fn en_passant_key(&self, index: u8) -> u64 {
         self.en_passant[(index % 8) as usize]
     }
+
+    /// The keys of the castling permissions set in `bits` combined, so the key of a change in
+    /// permissions is that of the bits which changed
+    pub fn castle_key(&self, bits: u8) -> u64 {
+        (0..4)
+            .filter(|bit| bits & (1 << bit) != 0)
+            .fold(0, |key, bit| key ^ self.castle[bit])
+    }
 }
 
 #[cfg(test)]
@@ -49,10 +58,12 @@ mod test_zorbrist {
     #[test]
     fn test_all_random_numbers_unique() {
         let z = Zorbrist::new();
-        let mut all = z.pieces.iter().flatten().map(|&c| c).collect::<Vec<u64>>();
+        let mut all = z.pieces.iter().flatten().copied().collect::<Vec<u64>>();
         all.push(z.side);
         all.extend(z.en_passant);
+        all.extend(z.castle);
         let mut unique = all.clone();
+        unique.sort_unstable();
         unique.dedup();
         assert_eq!(all.len(), unique.len());
     }
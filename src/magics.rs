@@ -0,0 +1,22 @@
+use basic_engine::find_magics;
+
+/// Print the magic numbers as the constants embedded in basic_engine's magic.rs
+///
+/// Usage: `arche generate-magics`, only needed if the magic bitboard layout changes.
+pub fn run() {
+    let (straight, diagonal) = find_magics();
+    print_constant("STRAIGHT_MAGICS", "rooks and queens", &straight);
+    println!();
+    print_constant("DIAGONAL_MAGICS", "bishops and queens", &diagonal);
+}
+
+fn print_constant(name: &str, pieces: &str, magics: &[u64; 64]) {
+    println!("// Magic numbers for the {} on each square", pieces);
+    println!("#[rustfmt::skip]");
+    println!("const {}: [u64; 64] = [", name);
+    for row in magics.chunks(4) {
+        let row: Vec<String> = row.iter().map(|m| format!("0x{:016x},", m)).collect();
+        println!("    {}", row.join(" "));
+    }
+    println!("];");
+}
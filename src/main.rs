@@ -1,15 +1,36 @@
+mod magics;
+mod testsuite;
+mod tune;
 mod uci;
 
-pub use uci::UCI;
+pub use uci::{OutputFormat, UCI};
 
 #[macro_use]
 extern crate lazy_static;
 
 use basic_engine::Board;
 use basic_engine::{AlphaBeta, Engine};
+use std::env;
+use std::process;
 
 fn main() {
+    basic_engine::init();
+    let args: Vec<String> = env::args().collect();
+    let mut output = OutputFormat::Uci;
+    match args.get(1).map(String::as_str) {
+        Some("tune") => return tune::run(&args[2..]),
+        Some("generate-magics") => return magics::run(),
+        Some("testsuite") => return testsuite::run(&args[2..]),
+        Some("--output") => match args.get(2).map(|o| o.parse()) {
+            Some(Ok(o)) => output = o,
+            _ => {
+                eprintln!("usage: arche --output uci|json");
+                process::exit(1);
+            }
+        },
+        _ => (),
+    }
     let game = Board::new();
     let e = <AlphaBeta as Engine>::new(game);
-    UCI::new_with_engine(e).read_loop();
+    UCI::new_with_engine(e).with_output(output).read_loop();
 }
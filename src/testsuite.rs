@@ -0,0 +1,111 @@
+use basic_engine::{AlphaBeta, Board, Engine, Epd, SearchParameters};
+use std::cell::Cell;
+use std::fs;
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Search every position of an EPD test suite, such as WAC or STS, and count the solved ones
+///
+/// Usage: `arche testsuite <file> <movetime>`, with the time per position in milliseconds. A
+/// position is solved if the best move found is one of its bm moves and none of its am moves.
+pub fn run(args: &[String]) {
+    let (path, move_time) = match args {
+        [path, move_time] => match move_time.parse() {
+            Ok(ms) => (path, Duration::from_millis(ms)),
+            Err(_) => exit(&format!("invalid movetime: {}", move_time)),
+        },
+        _ => exit("usage: arche testsuite <file> <movetime>"),
+    };
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => exit(&format!("failed to read {}: {}", path, e)),
+    };
+
+    // One engine for the whole suite, allocating a hash table per position takes longer than
+    // some of the searches
+    let mut engine = <AlphaBeta as Engine>::new(Board::new());
+    let start = Instant::now();
+    let (mut solved, mut scored, mut errors, mut total_depth) = (0, 0, 0, 0u64);
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let epd = match Epd::parse(line) {
+            Ok(epd) => epd,
+            Err(e) => {
+                errors += 1;
+                println!("line {} error: {}", i + 1, e);
+                continue;
+            }
+        };
+        let id = epd.id.clone().unwrap_or_else(|| format!("line {}", i + 1));
+        if epd.best_moves.is_empty() && epd.avoid_moves.is_empty() {
+            println!("{} skipped, no bm or am", id);
+            continue;
+        }
+        // Each position is searched from scratch, as the suite's positions are unrelated
+        engine.clear_cache();
+        if let Err(e) = engine.parse_fen(&epd.fen) {
+            errors += 1;
+            println!("{} error: {}", id, e);
+            continue;
+        }
+        let depth = Rc::new(Cell::new(0));
+        let last_depth = Rc::clone(&depth);
+        let params = SearchParameters::builder()
+            .move_time(move_time)
+            .on_iteration(move |info| last_depth.set(info.depth))
+            .build();
+        let best_move = match engine.iterative_deepening_search(params) {
+            Ok(m) => m,
+            Err(e) => {
+                errors += 1;
+                println!("{} error: search failed: {:?}", id, e);
+                continue;
+            }
+        };
+
+        scored += 1;
+        total_depth += u64::from(depth.get());
+        let result = match epd.is_solved_by(&best_move) {
+            true => {
+                solved += 1;
+                "solved"
+            }
+            false => "failed",
+        };
+        let expected = match epd.operation("bm") {
+            Some(moves) => format!("bm {}", moves.join(" ")),
+            None => format!("am {}", epd.operation("am").unwrap_or_default().join(" ")),
+        };
+        println!(
+            "{} {} {} ({}) depth {}",
+            id,
+            result,
+            best_move.to_san(&epd.board),
+            expected,
+            depth.get()
+        );
+    }
+
+    let average_depth = match scored {
+        0 => 0.0,
+        _ => total_depth as f64 / scored as f64,
+    };
+    println!(
+        "solved {}/{} average depth {:.1} time {:.1}s",
+        solved,
+        scored,
+        average_depth,
+        start.elapsed().as_secs_f64()
+    );
+    if errors > 0 {
+        println!("{} positions couldn't be searched", errors);
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
@@ -0,0 +1,108 @@
+use basic_engine::{load_dataset, EvalParams, Tuner, Weights};
+use std::fs;
+use std::process;
+
+const DEFAULT_OUTPUT: &str = "weights.txt";
+const DEFAULT_PASSES: usize = 100;
+
+/// Tune the evaluation weights against a dataset of positions and game results
+///
+/// Usage: `arche tune <dataset> [output] [passes]`, the tuned weights are written to the output
+/// file after every pass so a long run can be stopped early.
+pub fn run(args: &[String]) {
+    let result = parse_args(args).and_then(|(dataset, output, passes)| {
+        tune(dataset, output, passes)?;
+        println!("weights written to {}", output);
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// The dataset, output file and number of passes
+fn parse_args(args: &[String]) -> Result<(&str, &str, usize), String> {
+    let dataset = match args.first() {
+        Some(d) => d,
+        None => return Err("usage: arche tune <dataset> [output] [passes]".to_string()),
+    };
+    let output = args.get(1).map_or(DEFAULT_OUTPUT, |o| o.as_str());
+    let passes = match args.get(2).map(|p| p.parse()) {
+        Some(Ok(p)) => p,
+        Some(Err(_)) => return Err(format!("invalid number of passes: {}", args[2])),
+        None => DEFAULT_PASSES,
+    };
+    Ok((dataset, output, passes))
+}
+
+fn tune(dataset: &str, output: &str, passes: usize) -> Result<(), String> {
+    let positions = load_dataset(dataset).map_err(|e| format!("failed to load dataset: {}", e))?;
+    println!("loaded {} positions", positions.len());
+
+    // Tuning starts from the built-in weights
+    let base = EvalParams::default();
+    let mut tuner = Tuner::new(&positions, base.clone());
+    let mut weights = Weights::from_params(&base);
+    let k = tuner.fit_k(&weights);
+    println!("k {:.4} error {:.6}", k, tuner.error(&weights));
+
+    let mut written = Ok(());
+    tuner.local_search(&mut weights, passes, |pass, error, weights| {
+        println!("pass {} error {:.6}", pass, error);
+        let params = weights.to_params(&base);
+        if written.is_ok() {
+            written = fs::write(output, params.to_string())
+                .map_err(|e| format!("failed to write {}: {}", output, e));
+        }
+    });
+    written
+}
+
+#[cfg(test)]
+mod test_tune {
+    use super::{parse_args, tune, DEFAULT_OUTPUT, DEFAULT_PASSES};
+    use basic_engine::EvalParams;
+    use pretty_assertions::assert_eq;
+    use std::env;
+    use std::fs;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(&args(&["data.epd"])),
+            Ok(("data.epd", DEFAULT_OUTPUT, DEFAULT_PASSES))
+        );
+        assert_eq!(
+            parse_args(&args(&["data.epd", "out.txt", "3"])),
+            Ok(("data.epd", "out.txt", 3))
+        );
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&args(&["data.epd", "out.txt", "many"])).is_err());
+    }
+
+    #[test]
+    fn test_tune_writes_weights() {
+        let dir = env::temp_dir();
+        let dataset = dir.join(format!("arche-tune-{}.epd", std::process::id()));
+        let output = dir.join(format!("arche-tune-{}.txt", std::process::id()));
+        fs::write(
+            &dataset,
+            "4k3/pppppppp/8/8/8/5N2/PPPPPPPP/4K3 w - - 0 1 [1-0]\n\
+             4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1 [1/2-1/2]\n",
+        )
+        .unwrap();
+        let result = tune(dataset.to_str().unwrap(), output.to_str().unwrap(), 1);
+        let written = EvalParams::load(output.to_str().unwrap());
+        let _ = fs::remove_file(&dataset);
+        let _ = fs::remove_file(&output);
+        assert_eq!(result, Ok(()));
+        assert_ne!(written.unwrap(), EvalParams::default());
+
+        assert!(tune("missing.epd", output.to_str().unwrap(), 1).is_err());
+    }
+}
@@ -1,10 +1,35 @@
-use basic_engine::Color;
+use basic_engine::Book;
+use basic_engine::Clock;
+use basic_engine::Complexity;
+use basic_engine::DisplayOptions;
 use basic_engine::Engine;
+use basic_engine::EvalParams;
+use basic_engine::MoveOf;
+use basic_engine::PonderHandle;
+use basic_engine::SearchError;
+use basic_engine::SearchInfo;
 use basic_engine::SearchParameters;
+use basic_engine::StopHandle;
+use basic_engine::DEFAULT_HASH_SIZE_MB;
+use basic_engine::MAX_CONTEMPT;
+use basic_engine::MAX_SKILL_LEVEL;
 use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
 use std::time::Duration;
 
 const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+const MAX_HASH_SIZE_MB: usize = 65536;
+// Upper bound on the hash size picked by "setoption name Hash value auto"
+const MAX_AUTO_HASH_SIZE_MB: usize = 2048;
+const MAX_THREADS: usize = 256;
+const MAX_NODE_LIMIT: u64 = 1_000_000_000;
+// File the transposition table is written to and read from by "Save Hash" and "Load Hash"
+const DEFAULT_HASH_FILE: &str = "arche.hash";
 
 lazy_static! {
     static ref WTIME_RE: Regex = Regex::new(r"wtime (\d+)").unwrap();
@@ -14,7 +39,32 @@ lazy_static! {
     static ref MOVES_TO_GO_RE: Regex = Regex::new(r"movestogo (\d+)").unwrap();
     static ref MOVE_TIME: Regex = Regex::new(r"movetime (\d+)").unwrap();
     static ref DEPTH_RE: Regex = Regex::new(r"depth (\d+)").unwrap();
+    static ref NODES_RE: Regex = Regex::new(r"nodes (\d+)").unwrap();
+    static ref MATE_RE: Regex = Regex::new(r"mate (\d+)").unwrap();
+    static ref SEARCH_MOVES_RE: Regex =
+        Regex::new(r"searchmoves ((?:[a-h][1-8][a-h][1-8][qrbn]? ?)+)").unwrap();
     static ref INFINITE_RE: Regex = Regex::new(r"infinite").unwrap();
+    static ref SET_OPTION_RE: Regex =
+        Regex::new(r"^setoption name (.+?)(?: value (.*))?$").unwrap();
+}
+
+/// How the progress and result of a search are printed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    Uci,
+    Json, // One JSON object per line, for scripts rather than GUIs
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "uci" => Ok(OutputFormat::Uci),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
 }
 
 pub struct UCI<T: Engine> {
@@ -23,6 +73,12 @@ pub struct UCI<T: Engine> {
     version: String,
 
     engine: T,
+    node_limit: Option<u64>, // Maximum nodes searched for every move, set with "Node Limit"
+    display_options: DisplayOptions, // How "display" draws the board
+    output: OutputFormat,
+    hash_file: String,  // Set with "Hash File"
+    book: Option<Book>, // Set with "Book File"
+    own_book: bool,     // Play from the book when it has a move, set with "OwnBook"
 }
 
 impl<T: Engine> UCI<T> {
@@ -32,36 +88,119 @@ impl<T: Engine> UCI<T> {
             name: env!("CARGO_PKG_NAME").to_string(), // TODO change based on engine?
             version: env!("CARGO_PKG_VERSION").to_string(),
             engine,
+            node_limit: None,
+            display_options: DisplayOptions::default(),
+            output: OutputFormat::Uci,
+            hash_file: DEFAULT_HASH_FILE.to_string(),
+            book: None,
+            own_book: false,
         }
     }
 
+    pub fn with_output(mut self, output: OutputFormat) -> Self {
+        self.output = output;
+        self
+    }
+
     pub fn read_loop(&mut self) {
-        loop {
-            if let Some(result) = std::io::stdin().lines().next() {
-                let line = result.unwrap();
-                if line.starts_with("quit") {
+        let lines = Self::spawn_reader(self.engine.stop_handle(), self.engine.ponder_handle());
+        for line in lines {
+            if line.starts_with("quit") {
+                break;
+            } else if line.starts_with("isready") {
+                println!("readyok");
+            } else if line.starts_with("ucinewgame") {
+                self.parse_position("position startpos");
+            } else if line.starts_with("uci") {
+                println!("id name {} {}", self.name, self.version);
+                println!("author {}", self.author);
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_HASH_SIZE_MB, MAX_HASH_SIZE_MB
+                );
+                println!(
+                    "option name Threads type spin default 1 min 1 max {}",
+                    MAX_THREADS
+                );
+                println!(
+                    "option name Skill Level type spin default {} min 0 max {}",
+                    MAX_SKILL_LEVEL, MAX_SKILL_LEVEL
+                );
+                println!(
+                    "option name Contempt type spin default 0 min {} max {}",
+                    -MAX_CONTEMPT, MAX_CONTEMPT
+                );
+                println!(
+                    "option name Node Limit type spin default 0 min 0 max {}",
+                    MAX_NODE_LIMIT
+                );
+                println!("option name Ponder type check default false");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name Unicode Board type check default false");
+                println!("option name Colored Board type check default false");
+                println!("option name Output type combo default uci var uci var json");
+                println!(
+                    "option name Hash File type string default {}",
+                    DEFAULT_HASH_FILE
+                );
+                println!("option name Save Hash type button");
+                println!("option name Load Hash type button");
+                println!("option name OwnBook type check default false");
+                println!("option name Book File type string default <empty>");
+                println!("uciok");
+            } else if line.starts_with("setoption") {
+                self.parse_set_option(&line);
+            } else if line.starts_with("position") {
+                self.parse_position(&line);
+            } else if line.starts_with("display") {
+                self.engine.display_board(self.display_options);
+            } else if line.starts_with("go") {
+                self.parse_go(&line);
+            } else if line.starts_with("stop") || line.starts_with("ponderhit") {
+                // The search has already been stopped or told to ponderhit by the reader thread
+            } else if line.starts_with("perft") {
+                self.parse_perft(&line);
+            } else {
+                println!("Failed to parse line: {}", line);
+            }
+        }
+        io::stdout().flush().unwrap();
+    }
+
+    /// Read stdin on a separate thread so commands can interrupt a running search
+    ///
+    /// Lines are forwarded over the returned channel, "stop" and "quit" also set the stop flag
+    /// immediately and "ponderhit" ends pondering immediately. If stdin is closed (e.g. the GUI
+    /// crashed) the search is stopped and the channel closes, ending the read loop.
+    fn spawn_reader(stop: StopHandle, ponder: PonderHandle) -> mpsc::Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                // The flag is cleared here rather than when the search starts so that a stop
+                // sent immediately after go is never lost
+                if line.starts_with("go") {
+                    stop.reset();
+                    if line.contains(" ponder") {
+                        ponder.start();
+                    } else {
+                        ponder.hit();
+                    }
+                } else if line.starts_with("ponderhit") {
+                    ponder.hit();
+                } else if line.starts_with("stop") || line.starts_with("quit") {
+                    stop.stop();
+                }
+                if sender.send(line).is_err() {
                     return;
-                } else if line.starts_with("isready") {
-                    println!("readyok");
-                } else if line.starts_with("ucinewgame") {
-                    self.parse_position("position startpos");
-                } else if line.starts_with("uci") {
-                    println!("id name {} {}", self.name, self.version);
-                    println!("author {}", self.author);
-                    println!("uciok");
-                } else if line.starts_with("position") {
-                    self.parse_position(&line);
-                } else if line.starts_with("display") {
-                    self.engine.display_board();
-                } else if line.starts_with("go") {
-                    self.parse_go(&line);
-                } else if line.starts_with("perft") {
-                    self.engine.perft();
-                } else {
-                    println!("Failed to parse line: {}", line);
                 }
-            };
-        }
+            }
+            stop.stop();
+        });
+        receiver
     }
 
     fn parse_position(&mut self, line: &str) {
@@ -82,76 +221,436 @@ impl<T: Engine> UCI<T> {
 
         if let Some(moves) = move_list {
             for m in moves.split_whitespace() {
-                assert!(
-                    self.engine.make_move_str(m.trim()),
-                    "Failed to parse/play {}",
-                    m
-                );
+                if let Err(e) = self.engine.make_move_str(m.trim()) {
+                    panic!("Failed to parse/play {}: {}", m, e);
+                }
             }
         }
     }
 
-    fn parse_go(&mut self, line: &str) {
-        let mut sp = SearchParameters::new();
-        sp.print_info = true;
-
-        let mut time = match self.engine.active_color() {
-            Color::White => {
-                if let Some(wtime) = WTIME_RE.captures(line) {
-                    Some(wtime.get(1).unwrap().as_str().parse::<u64>().unwrap())
-                } else {
-                    None
+    fn parse_set_option(&mut self, line: &str) {
+        let (name, value) = match SET_OPTION_RE.captures(line.trim()) {
+            Some(c) => (
+                c.get(1).unwrap().as_str(),
+                c.get(2).map(|v| v.as_str().trim()),
+            ),
+            None => {
+                println!("info string failed to parse option: {}", line);
+                return;
+            }
+        };
+        match (name.to_lowercase().as_str(), value) {
+            ("hash", Some("auto")) => {
+                let size = auto_hash_size();
+                self.engine.set_hash_size(size);
+                println!("info string hash size set to {} MB (auto)", size);
+            }
+            ("hash", Some(v)) => match v.parse::<usize>() {
+                Ok(size) if (1..=MAX_HASH_SIZE_MB).contains(&size) => {
+                    self.engine.set_hash_size(size);
+                    println!("info string hash size set to {} MB", size);
                 }
+                _ => println!("info string invalid hash size: {}", v),
+            },
+            ("threads", Some(v)) => match v.parse::<usize>() {
+                Ok(threads) if (1..=MAX_THREADS).contains(&threads) => {
+                    self.engine.set_threads(threads);
+                    println!("info string threads set to {}", threads);
+                }
+                _ => println!("info string invalid thread count: {}", v),
+            },
+            ("skill level", Some(v)) => match v.parse::<u8>() {
+                Ok(level) if level <= MAX_SKILL_LEVEL => {
+                    self.engine.set_skill_level(level);
+                    println!("info string skill level set to {}", level);
+                }
+                _ => println!("info string invalid skill level: {}", v),
+            },
+            ("contempt", Some(v)) => match v.parse::<i64>() {
+                Ok(contempt) if (-MAX_CONTEMPT..=MAX_CONTEMPT).contains(&contempt) => {
+                    self.engine.set_contempt(contempt);
+                    println!("info string contempt set to {}", contempt);
+                }
+                _ => println!("info string invalid contempt: {}", v),
+            },
+            // Pondering is controlled by "go ponder", the option only tells the GUI it's supported
+            ("ponder", Some(_)) => {}
+            ("evalfile", Some(path)) if path != "<empty>" => match EvalParams::load(path) {
+                Ok(params) => {
+                    self.engine.set_eval_params(params);
+                    println!("info string evaluation weights loaded from {}", path);
+                }
+                Err(e) => println!("info string failed to load evaluation weights: {}", e),
+            },
+            ("evalfile", _) => {
+                self.engine.set_eval_params(EvalParams::default());
+                println!("info string default evaluation weights restored");
             }
-            Color::Black => {
-                if let Some(btime) = BTIME_RE.captures(line) {
-                    Some(btime.get(1).unwrap().as_str().parse::<u64>().unwrap())
-                } else {
-                    None
+            // Zero removes the limit
+            ("node limit", Some(v)) => match v.parse::<u64>() {
+                Ok(0) => {
+                    self.node_limit = None;
+                    println!("info string node limit removed");
+                }
+                Ok(nodes) if nodes <= MAX_NODE_LIMIT => {
+                    self.node_limit = Some(nodes);
+                    println!("info string node limit set to {}", nodes);
+                }
+                _ => println!("info string invalid node limit: {}", v),
+            },
+            // Only change how "display" draws the board, e.g. when analysing from a terminal
+            ("unicode board", Some(v)) => match v.parse::<bool>() {
+                Ok(unicode) => self.display_options.unicode = unicode,
+                Err(_) => println!("info string invalid unicode board: {}", v),
+            },
+            ("colored board", Some(v)) => match v.parse::<bool>() {
+                Ok(colors) => self.display_options.colors = colors,
+                Err(_) => println!("info string invalid colored board: {}", v),
+            },
+            ("output", Some(v)) => match v.parse() {
+                Ok(output) => self.output = output,
+                Err(e) => println!("info string {}", e),
+            },
+            // Keep the search results between sessions, e.g. for a long analysis
+            ("hash file", Some(path)) => self.hash_file = path.to_string(),
+            ("save hash", _) => match self.engine.save_tt(&self.hash_file) {
+                Ok(()) => println!("info string hash saved to {}", self.hash_file),
+                Err(e) => println!("info string failed to save hash: {}", e),
+            },
+            ("load hash", _) => match self.engine.load_tt(&self.hash_file) {
+                Ok(()) => println!("info string hash loaded from {}", self.hash_file),
+                Err(e) => println!("info string failed to load hash: {}", e),
+            },
+            ("ownbook", Some(v)) => match v.parse::<bool>() {
+                Ok(own_book) => self.own_book = own_book,
+                Err(_) => println!("info string invalid ownbook: {}", v),
+            },
+            ("book file", Some(path)) if path != "<empty>" => match Book::open(path) {
+                Ok(book) => {
+                    println!(
+                        "info string book loaded from {}, {} entries",
+                        path,
+                        book.len()
+                    );
+                    self.book = Some(book);
                 }
+                Err(e) => println!("info string failed to load book: {}", e),
+            },
+            ("book file", _) => self.book = None,
+            _ => println!("info string unknown option: {}", name),
+        }
+    }
+
+    // A move from the book for "go", None if OwnBook is off or the position isn't in the book.
+    // Analysis and pondering always search, as does a go limited to some moves.
+    fn book_move(&self, line: &str) -> Option<MoveOf<T>> {
+        let book = self.book.as_ref().filter(|_| self.own_book)?;
+        if INFINITE_RE.is_match(line) || line.contains(" ponder") || line.contains("searchmoves") {
+            return None;
+        }
+        book.weighted_pick(self.engine.position())
+    }
+
+    // Print the perft count below each move and the total, e.g. for "perft 5", to depth 1 if no
+    // depth is given
+    fn parse_perft(&mut self, line: &str) {
+        let depth = match line.strip_prefix("perft").unwrap().trim() {
+            "" => 1,
+            d => match d.parse::<u8>() {
+                Ok(d) => d,
+                Err(_) => {
+                    println!("info string invalid perft depth: {}", d);
+                    return;
+                }
+            },
+        };
+        let divide = self.engine.perft(depth);
+        for (m, nodes) in &divide {
+            println!("{}: {}", m, nodes);
+        }
+        println!();
+        println!(
+            "Nodes searched: {}",
+            divide.iter().map(|(_, nodes)| nodes).sum::<u64>()
+        );
+    }
+
+    fn parse_go(&mut self, line: &str) {
+        if let Some(best_move) = self.book_move(line) {
+            match self.output {
+                OutputFormat::Uci => println!("bestmove {}", best_move),
+                OutputFormat::Json => println!(
+                    "{}",
+                    best_move_json(Some(best_move.to_string()), None, None, None)
+                ),
             }
+            return;
+        }
+        let output = self.output;
+        let mut sp = SearchParameters::builder().on_iteration(move |info| match output {
+            OutputFormat::Uci => print_info(info),
+            OutputFormat::Json => println!("{}", info_json(info)),
+        });
+
+        if let Some(depth) = capture(&DEPTH_RE, line) {
+            sp = sp.depth(depth);
+        }
+        if let Some(nodes) = capture(&NODES_RE, line).or(self.node_limit) {
+            sp = sp.nodes(nodes);
+        }
+        if let Some(move_time) = capture(&MOVE_TIME, line) {
+            sp = sp.move_time(Duration::from_millis(move_time));
+        }
+        let clock = Clock {
+            white_time: capture(&WTIME_RE, line).map(Duration::from_millis),
+            black_time: capture(&BTIME_RE, line).map(Duration::from_millis),
+            white_increment: Duration::from_millis(capture(&WINC_RE, line).unwrap_or(0)),
+            black_increment: Duration::from_millis(capture(&BINC_RE, line).unwrap_or(0)),
+            moves_to_go: capture(&MOVES_TO_GO_RE, line),
         };
-        let increment = match self.engine.active_color() {
-            Color::White => {
-                if let Some(winc) = WINC_RE.captures(line) {
-                    Some(winc.get(1).unwrap().as_str().parse::<u64>().unwrap())
-                } else {
-                    None
+        if clock.white_time.is_some() || clock.black_time.is_some() {
+            sp = sp.clock(clock);
+        }
+        if let Some(mate) = capture(&MATE_RE, line) {
+            sp = sp.mate(mate);
+        }
+        if INFINITE_RE.is_match(line) {
+            sp = sp.infinite();
+        }
+        if let Some(moves) = SEARCH_MOVES_RE.captures(line) {
+            sp = sp.search_moves(moves[1].split_whitespace().map(String::from).collect());
+        }
+        let sp = sp.build();
+
+        let result = self.engine.iterative_deepening_search(sp);
+        // The bestmove of a ponder search can't be sent until after ponderhit or stop
+        let ponder = self.engine.ponder_handle();
+        let stop = self.engine.stop_handle();
+        while ponder.is_pondering() && !stop.is_stopped() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        if self.output == OutputFormat::Json {
+            let (best_move, ponder, error) = match &result {
+                Ok(best_move) => {
+                    let pv = self.engine.pv_line();
+                    let ponder = match pv.moves() {
+                        [first, reply, ..] if first == best_move => Some(reply.to_string()),
+                        _ => None,
+                    };
+                    (Some(best_move.to_string()), ponder, None)
+                }
+                Err(e) => (None, None, Some(e.to_string())),
+            };
+            let complexity = self.engine.complexity();
+            println!("{}", best_move_json(best_move, ponder, error, complexity));
+            return;
+        }
+        match result {
+            Ok(best_move) => {
+                let pv = self.engine.pv_line();
+                match pv.moves() {
+                    [first, reply, ..] if *first == best_move => {
+                        println!("bestmove {} ponder {}", best_move, reply)
+                    }
+                    _ => println!("bestmove {}", best_move),
                 }
             }
-            Color::Black => {
-                if let Some(binc) = BINC_RE.captures(line) {
-                    Some(binc.get(1).unwrap().as_str().parse::<u64>().unwrap())
-                } else {
-                    None
+            Err(e) => {
+                // Report the final score of a finished game, then a null move tells the GUI there
+                // is no move to play
+                match e {
+                    SearchError::Checkmated => println!("info depth 0 score mate 0"),
+                    SearchError::Stalemate | SearchError::Drawn => {
+                        println!("info depth 0 score cp 0")
+                    }
+                    _ => {}
                 }
+                println!("info string {}", e);
+                println!("bestmove 0000");
             }
-        };
-        if let Some(move_time) = MOVE_TIME.captures(line) {
-            time = Some(move_time.get(1).unwrap().as_str().parse::<u64>().unwrap());
         }
+    }
+}
 
-        sp.depth = if let Some(depth_str) = DEPTH_RE.captures(line) {
-            Some(depth_str.get(1).unwrap().as_str().parse::<u8>().unwrap())
-        } else {
-            None
-        };
+/// Print a UCI info line with the progress of the search
+fn print_info<M: fmt::Display>(info: &SearchInfo<M>) {
+    let score = match info.mate {
+        Some(mate_in) => format!("mate {}", mate_in),
+        None => format!("cp {}", info.score),
+    };
+    let millis = info.time.as_millis() as u64;
+    let pv: Vec<String> = info.pv.iter().map(|p| p.to_string()).collect();
+    println!(
+        "info depth {} seldepth {} score {} nodes {} nps {} time {} pv {}",
+        info.depth,
+        info.selective_depth,
+        score,
+        info.nodes,
+        info.nodes * 1000 / millis.max(1),
+        millis,
+        pv.join(" "),
+    );
+}
 
-        // TODO what if inc is set but not time?
-        if let Some(time) = time {
-            let mut duration = if let Some(inc) = increment {
-                (time / 40) + inc
-            } else {
-                time / 40
-            };
-            duration -= (duration / 10).min(50); // Buffer to be sure we don't run out of time
-            sp.search_duration = Some(Duration::from_millis(duration));
+/// The progress of the search as a JSON object, the score is null when there is a mate score
+fn info_json<M: fmt::Display>(info: &SearchInfo<M>) -> String {
+    let millis = info.time.as_millis() as u64;
+    let pv: Vec<String> = info
+        .pv
+        .iter()
+        .map(|p| json_string(&p.to_string()))
+        .collect();
+    let (score, mate) = match info.mate {
+        Some(mate_in) => ("null".to_string(), mate_in.to_string()),
+        None => (info.score.to_string(), "null".to_string()),
+    };
+    format!(
+        concat!(
+            "{{\"type\":\"info\",\"depth\":{},\"seldepth\":{},\"score\":{},\"mate\":{},",
+            "\"nodes\":{},\"nps\":{},\"time\":{},\"pv\":[{}]}}"
+        ),
+        info.depth,
+        info.selective_depth,
+        score,
+        mate,
+        info.nodes,
+        info.nodes * 1000 / millis.max(1),
+        millis,
+        pv.join(","),
+    )
+}
+
+/// The result of the search as a JSON object, the best move is null if there is no move to play
+/// and the complexity is null if no iteration completed
+fn best_move_json(
+    best_move: Option<String>,
+    ponder: Option<String>,
+    error: Option<String>,
+    complexity: Option<Complexity>,
+) -> String {
+    let value = |s: Option<String>| s.map_or("null".to_string(), |s| json_string(&s));
+    let complexity = complexity.map_or("null".to_string(), |c| {
+        format!(
+            "{{\"stable_depth\":{},\"best_move_changes\":{},\"score_deviation\":{:.1}}}",
+            c.stable_depth, c.best_move_changes, c.score_deviation
+        )
+    });
+    format!(
+        concat!(
+            "{{\"type\":\"bestmove\",\"bestmove\":{},\"ponder\":{},\"error\":{},",
+            "\"complexity\":{}}}"
+        ),
+        value(best_move),
+        value(ponder),
+        value(error),
+        complexity,
+    )
+}
+
+// Quote and escape a string for JSON
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
         }
+    }
+    out.push('"');
+    out
+}
 
-        if INFINITE_RE.is_match(line) {
-            sp.search_duration = None;
+/// Parse the first capture group of a regex match on the line
+fn capture<T: FromStr>(re: &Regex, line: &str) -> Option<T> {
+    re.captures(line)?.get(1)?.as_str().parse().ok()
+}
+
+/// Pick a hash size from the memory available on this machine
+///
+/// Uses a quarter of the available memory (capped at MAX_AUTO_HASH_SIZE_MB), or the default size
+/// if the available memory can't be determined.
+fn auto_hash_size() -> usize {
+    match available_memory_mb() {
+        Some(available) => (available / 4).clamp(1, MAX_AUTO_HASH_SIZE_MB),
+        None => DEFAULT_HASH_SIZE_MB,
+    }
+}
+
+fn available_memory_mb() -> Option<usize> {
+    // TODO support platforms other than linux
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+    Some(kilobytes / 1024)
+}
+
+#[cfg(test)]
+mod test_json {
+    use super::{best_move_json, info_json, json_string};
+    use basic_engine::{Complexity, SearchInfo};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn info(mate: Option<i64>) -> SearchInfo<String> {
+        SearchInfo {
+            depth: 3,
+            selective_depth: 5,
+            score: -20,
+            mate,
+            nodes: 3000,
+            time: Duration::from_millis(1500),
+            pv: vec!["e2e4".to_string(), "e7e5".to_string()],
         }
+    }
+
+    #[test]
+    fn test_info_json() {
+        assert_eq!(
+            info_json(&info(None)),
+            concat!(
+                r#"{"type":"info","depth":3,"seldepth":5,"score":-20,"mate":null,"nodes":3000,"#,
+                r#""nps":2000,"time":1500,"pv":["e2e4","e7e5"]}"#
+            )
+        );
+        // The score is null when the mate distance is given
+        assert!(info_json(&info(Some(-2))).contains(r#""score":null,"mate":-2,"#));
+    }
+
+    #[test]
+    fn test_best_move_json() {
+        let complexity = Complexity {
+            stable_depth: 4,
+            best_move_changes: 2,
+            score_deviation: 12.345,
+        };
+        assert_eq!(
+            best_move_json(
+                Some("e2e4".into()),
+                Some("e7e5".into()),
+                None,
+                Some(complexity)
+            ),
+            concat!(
+                r#"{"type":"bestmove","bestmove":"e2e4","ponder":"e7e5","error":null,"#,
+                r#""complexity":{"stable_depth":4,"best_move_changes":2,"score_deviation":12.3}}"#
+            )
+        );
+        assert_eq!(
+            best_move_json(None, None, Some("checkmated".into()), None),
+            concat!(
+                r#"{"type":"bestmove","bestmove":null,"ponder":null,"error":"checkmated","#,
+                r#""complexity":null}"#
+            )
+        );
+    }
 
-        println!("bestmove {}", self.engine.iterative_deepening_search(sp));
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("e2e4"), r#""e2e4""#);
+        assert_eq!(json_string("a \"b\" \\c"), r#""a \"b\" \\c""#);
+        assert_eq!(json_string("tab\tnew\n"), r#""tab\u0009new\u000a""#);
     }
 }